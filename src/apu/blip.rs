@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::mem;
+
+// Width of the band-limited step in output samples, and how finely its
+// position between two samples is resolved.
+const TAPS: usize = 16;
+const PHASES: usize = 64;
+
+// Fraction of the output rate kept, a little under Nyquist so the
+// window has room to roll off.
+const CUTOFF: f64 = 0.45;
+
+// Interleaved samples kept when nobody drains the output, about a second
+// of stereo at 48 kHz.
+const MAX_BUFFERED: usize = 96_000;
+
+// Band-limited synthesis: the APU output only changes in steps, so each
+// change is added to the output as a windowed-sinc step at its exact
+// position instead of sampling the signal at the host rate. This is the
+// resampler from the native rate, nothing above the cutoff aliases back.
+struct Blip {
+    kernel: Vec<[f32; TAPS]>,
+    // Differences, integrated as samples are read out.
+    deltas: VecDeque<f32>,
+    level: f32,
+    sum: f32,
+}
+
+impl Blip {
+    fn new(kernel: Vec<[f32; TAPS]>) -> Blip {
+        Blip {
+            kernel,
+            deltas: VecDeque::from(vec![0.0; TAPS + 1]),
+            level: 0.0,
+            sum: 0.0,
+        }
+    }
+
+    // `time` is in output samples from the first unread one.
+    fn set_level(&mut self, time: f64, level: f32) {
+        let delta = level - self.level;
+        if delta == 0.0 {
+            return;
+        }
+        self.level = level;
+
+        let index = time as usize;
+        let phase = ((time - index as f64) * PHASES as f64) as usize;
+
+        while self.deltas.len() < index + TAPS + 1 {
+            self.deltas.push_back(0.0);
+        }
+        for (tap, &weight) in self.kernel[phase].iter().enumerate() {
+            self.deltas[index + tap] += delta * weight;
+        }
+    }
+
+    fn read(&mut self) -> f32 {
+        self.sum += self.deltas.pop_front().unwrap_or(0.0);
+        if self.deltas.len() < TAPS + 1 {
+            self.deltas.push_back(0.0);
+        }
+        self.sum
+    }
+}
+
+fn kernel() -> Vec<[f32; TAPS]> {
+    let center = (TAPS / 2) as f64;
+
+    (0..PHASES).map(|phase| {
+        let offset = phase as f64 / PHASES as f64;
+        let mut taps = [0.0; TAPS];
+
+        for (tap, weight) in taps.iter_mut().enumerate() {
+            let x = tap as f64 - center - offset;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (2.0 * PI * CUTOFF * x).sin() / (2.0 * PI * CUTOFF * x)
+            };
+            // Blackman window over the width of the kernel.
+            let w = (x + center) / TAPS as f64;
+            let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+            *weight = (sinc * window) as f32;
+        }
+
+        // Every phase must add up to exactly one step.
+        let total: f32 = taps.iter().sum();
+        for weight in taps.iter_mut() {
+            *weight /= total;
+        }
+        taps
+    }).collect()
+}
+
+// A stereo output at the host rate, fed with levels at T-cycle times.
+pub struct Stereo {
+    // Output samples per T-cycle.
+    ratio: f64,
+    time: f64,
+
+    left: Blip,
+    right: Blip,
+
+    // The output capacitors remove the DC offset of the DACs.
+    left_capacitor: f32,
+    right_capacitor: f32,
+    charge_factor: f32,
+
+    samples: Vec<f32>,
+}
+
+impl Stereo {
+    pub fn new(clock_rate: u64, sample_rate: u32) -> Stereo {
+        let kernel = kernel();
+        let ratio = sample_rate as f64 / clock_rate as f64;
+
+        Stereo {
+            ratio,
+            time: 0.0,
+
+            left: Blip::new(kernel.clone()),
+            right: Blip::new(kernel),
+
+            left_capacitor: 0.0,
+            right_capacitor: 0.0,
+            charge_factor: 0.999958f64.powf(1.0 / ratio) as f32,
+
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn set_level(&mut self, left: f32, right: f32) {
+        self.left.set_level(self.time, left);
+        self.right.set_level(self.time, right);
+    }
+
+    pub fn advance(&mut self, ticks: u32) {
+        self.time += ticks as f64 * self.ratio;
+
+        while self.time >= 1.0 {
+            self.time -= 1.0;
+
+            let left = self.left.read();
+            let right = self.right.read();
+            let left = high_pass(&mut self.left_capacitor, left, self.charge_factor);
+            let right = high_pass(&mut self.right_capacitor, right, self.charge_factor);
+
+            if self.samples.len() >= MAX_BUFFERED {
+                self.samples.drain(..MAX_BUFFERED / 2);
+            }
+            self.samples.push(left);
+            self.samples.push(right);
+        }
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        mem::take(&mut self.samples)
+    }
+}
+
+fn high_pass(capacitor: &mut f32, input: f32, charge_factor: f32) -> f32 {
+    let output = input - *capacitor;
+    *capacitor = input - output * charge_factor;
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(blip: &mut Blip, count: usize) -> Vec<f32> {
+        (0..count).map(|_| blip.read()).collect()
+    }
+
+    // A step settles on its new level within the width of the kernel,
+    // whatever its position between two samples, centered on it.
+    #[test]
+    fn step_settles() {
+        for &offset in &[0.0, 0.25, 0.5, 0.99] {
+            let mut blip = Blip::new(kernel());
+            blip.set_level(4.0 + offset, 1.0);
+            let output = read(&mut blip, 4 + 2 * TAPS);
+
+            assert!(output[..4].iter().all(|&sample| sample.abs() < 1e-6), "{}", offset);
+            assert!(output[4 + TAPS..].iter().all(|&sample| (sample - 1.0).abs() < 1e-5), "{}", offset);
+
+            let center = 4 + TAPS / 2;
+            assert!(output[center - 1] < 0.5 && output[center + 1] > 0.5, "{}: {:?}", offset, output);
+        }
+    }
+
+    // A pulse half a sample wide adds up to half a sample of signal, and
+    // nothing is left over after it.
+    #[test]
+    fn impulse_area() {
+        let mut blip = Blip::new(kernel());
+        blip.set_level(2.0, 1.0);
+        blip.set_level(2.5, 0.0);
+        let output = read(&mut blip, 4 * TAPS);
+
+        let area: f32 = output.iter().sum();
+        assert!((area - 0.5).abs() < 1e-3, "{}", area);
+        assert!(output[2 + TAPS..].iter().all(|&sample| sample.abs() < 1e-6));
+    }
+
+    // Half a second of T-cycles makes half a second of stereo samples. A
+    // held level is removed by the output capacitors.
+    #[test]
+    fn stereo_rate_and_dc() {
+        let mut stereo = Stereo::new(4_194_304, 48_000);
+        stereo.set_level(0.5, -0.5);
+        for _ in 0..2_097_152 / 4 {
+            stereo.advance(4);
+        }
+
+        let samples = stereo.take_samples();
+        assert!((samples.len() as i64 - 48_000).abs() <= 2, "{}", samples.len());
+        assert!(samples[TAPS * 2 + 1] < -0.25);
+        assert!(samples[samples.len() - 2].abs() < 1e-3);
+        assert!(samples[samples.len() - 1].abs() < 1e-3);
+    }
+}
@@ -0,0 +1,64 @@
+// Volume envelope of the square and noise channels, stepped at 64 Hz.
+pub struct Envelope {
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            register: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn register(&self) -> u8 {
+        self.register
+    }
+
+    pub fn set_register(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    // The upper five bits double as the DAC power switch.
+    pub fn dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+
+    fn period(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = if self.period() == 0 { 8 } else { self.period() };
+    }
+
+    pub fn clock(&mut self) {
+        if self.period() == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = self.period();
+
+        if self.register & 0x08 != 0 {
+            if self.volume < 15 {
+                self.volume += 1;
+            }
+        } else if self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
@@ -0,0 +1,113 @@
+// Length counter shared by all four channels. When enabled it counts down
+// at 256 Hz and switches the channel off on reaching zero.
+pub struct Length {
+    enabled: bool,
+    counter: u16,
+    max: u16,
+}
+
+impl Length {
+    pub fn new(max: u16) -> Length {
+        Length {
+            enabled: false,
+            counter: 0,
+            max,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn load(&mut self, value: u8) {
+        self.counter = self.max - value as u16;
+    }
+
+    // Returns true when the channel has to be switched off.
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+
+    // Handles the length enable and trigger bits of an NRx4 write.
+    // `extra_clock` is set when the next frame sequencer step does not
+    // clock lengths, in which case enabling the counter clocks it once
+    // right away. Returns true when the channel has to be switched off.
+    pub fn write_control(&mut self, value: u8, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        let trigger = value & 0x80 != 0;
+        self.enabled = value & 0x40 != 0;
+
+        let mut expired = false;
+        if !was_enabled && self.enabled && extra_clock && self.counter > 0 {
+            self.counter -= 1;
+            expired = self.counter == 0 && !trigger;
+        }
+
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if self.enabled && extra_clock {
+                self.counter -= 1;
+            }
+        }
+
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expires_only_when_enabled() {
+        let mut length = Length::new(64);
+        length.load(62);
+        assert!(!length.clock());
+        assert!(!length.clock());
+
+        length.write_control(0x40, false);
+        assert!(!length.clock());
+        assert!(length.clock());
+        assert!(!length.clock());
+    }
+
+    #[test]
+    fn trigger_reloads_expired_counter() {
+        let mut length = Length::new(256);
+        length.load(255);
+        length.write_control(0x40, false);
+        assert!(length.clock());
+
+        length.write_control(0xC0, false);
+        for _ in 0..255 {
+            assert!(!length.clock());
+        }
+        assert!(length.clock());
+    }
+
+    #[test]
+    fn enabling_in_odd_step_clocks_once() {
+        let mut length = Length::new(64);
+        length.load(62);
+        assert!(!length.write_control(0x40, true));
+        assert!(length.clock());
+
+        // Reaching zero that way switches the channel off, unless it is
+        // triggered at the same time, which reloads 63.
+        let mut length = Length::new(64);
+        length.load(63);
+        assert!(length.write_control(0x40, true));
+
+        let mut length = Length::new(64);
+        length.load(63);
+        assert!(!length.write_control(0xC0, true));
+        for _ in 0..62 {
+            assert!(!length.clock());
+        }
+        assert!(length.clock());
+    }
+}
@@ -0,0 +1,325 @@
+mod blip;
+mod envelope;
+mod length;
+mod noise;
+mod square;
+mod wave;
+
+use self::blip::Stereo;
+use self::noise::Noise;
+use self::square::Square;
+use self::wave::Wave;
+
+// T-cycles per second at normal speed.
+const CLOCK_RATE: u64 = 4_194_304;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+pub const CHANNELS: [Channel; 4] = [Channel::Square1, Channel::Square2, Channel::Wave, Channel::Noise];
+
+// Mixes the four channels into stereo samples at the host rate. Channel
+// timers run on T-cycles, lengths, envelopes and the sweep on the frame
+// sequencer, which the interconnect clocks from DIV.
+pub struct Apu {
+    enabled: bool,
+
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+
+    nr50: u8,
+    nr51: u8,
+
+    // The next of the eight frame sequencer steps.
+    frame_step: u8,
+
+    sample_rate: u32,
+    output: Stereo,
+    // Each channel on its own, panned and at master volume, when captured.
+    channel_outputs: Vec<Stereo>,
+}
+
+impl Default for Apu {
+    fn default() -> Apu {
+        Apu::new()
+    }
+}
+
+impl Apu {
+    // Starts powered on, as the boot ROM leaves it.
+    pub fn new() -> Apu {
+        Apu {
+            enabled: true,
+
+            square1: Square::new(true),
+            square2: Square::new(false),
+            wave: Wave::new(),
+            noise: Noise::new(),
+
+            nr50: 0,
+            nr51: 0,
+
+            frame_step: 0,
+
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            output: Stereo::new(CLOCK_RATE, DEFAULT_SAMPLE_RATE),
+            channel_outputs: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Drops any samples not taken yet.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        assert!(sample_rate > 0 && (sample_rate as u64) < CLOCK_RATE);
+
+        self.sample_rate = sample_rate;
+        self.output = Stereo::new(CLOCK_RATE, sample_rate);
+        if !self.channel_outputs.is_empty() {
+            self.set_channel_capture(true);
+        }
+    }
+
+    // Takes the samples produced so far, interleaved left and right in the
+    // range -1.0 to 1.0.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.output.take_samples()
+    }
+
+    // Also produces every channel separately, which costs about as much
+    // as the mix itself for each channel.
+    pub fn set_channel_capture(&mut self, enabled: bool) {
+        self.channel_outputs = if enabled {
+            CHANNELS.iter().map(|_| Stereo::new(CLOCK_RATE, self.sample_rate)).collect()
+        } else {
+            Vec::new()
+        };
+    }
+
+    // Samples of one channel, in the same format as `take_samples`. Empty
+    // unless channel capture is on.
+    pub fn take_channel_samples(&mut self, channel: Channel) -> Vec<f32> {
+        match self.channel_outputs.get_mut(channel as usize) {
+            Some(output) => output.take_samples(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn rb(&self, a: u16) -> u8 {
+        match a {
+            0xFF10..=0xFF14 => self.square1.rb(a - 0xFF10),
+            0xFF15..=0xFF19 => self.square2.rb(a - 0xFF15),
+            0xFF1A..=0xFF1E => self.wave.rb(a - 0xFF1A),
+            0xFF1F..=0xFF23 => self.noise.rb(a - 0xFF1F),
+            0xFF24 => self.nr50,
+            0xFF25 => self.nr51,
+            0xFF26 => {
+                let channels = [
+                    self.square1.enabled(),
+                    self.square2.enabled(),
+                    self.wave.enabled(),
+                    self.noise.enabled(),
+                ];
+                let status = channels.iter().enumerate()
+                    .fold(0, |status, (i, &on)| if on { status | 1 << i } else { status });
+
+                0x70 | if self.enabled { 0x80 } else { 0 } | status
+            }
+            0xFF27..=0xFF2F => 0xFF,
+            0xFF30..=0xFF3F => self.wave.load_ram(a - 0xFF30),
+            _ => panic!("APU does not handle read {:4X}", a),
+        }
+    }
+
+    pub fn wb(&mut self, a: u16, v: u8) {
+        // Wave RAM and NR52 stay writable while the APU is off.
+        match a {
+            0xFF26 => return self.set_power(v & 0x80 != 0),
+            0xFF30..=0xFF3F => return self.wave.store_ram(a - 0xFF30, v),
+            _ if !self.enabled => return,
+            _ => {}
+        }
+
+        // Enabling a length counter in the half of the sequencer period
+        // that does not clock lengths clocks it once.
+        let extra_length_clock = self.frame_step & 0x01 != 0;
+
+        match a {
+            0xFF10..=0xFF14 => self.square1.wb(a - 0xFF10, v, extra_length_clock),
+            0xFF15..=0xFF19 => self.square2.wb(a - 0xFF15, v, extra_length_clock),
+            0xFF1A..=0xFF1E => self.wave.wb(a - 0xFF1A, v, extra_length_clock),
+            0xFF1F..=0xFF23 => self.noise.wb(a - 0xFF1F, v, extra_length_clock),
+            0xFF24 => self.nr50 = v,
+            0xFF25 => self.nr51 = v,
+            0xFF27..=0xFF2F => {}
+            _ => panic!("APU does not handle write {:4X}", a),
+        }
+    }
+
+    // Powering off clears every register but wave RAM.
+    fn set_power(&mut self, on: bool) {
+        if self.enabled && !on {
+            self.square1 = Square::new(true);
+            self.square2 = Square::new(false);
+            self.wave.power_off();
+            self.noise = Noise::new();
+            self.nr50 = 0;
+            self.nr51 = 0;
+        } else if !self.enabled && on {
+            self.frame_step = 0;
+        }
+
+        self.enabled = on;
+    }
+
+    // Called on every falling edge of DIV bit 4 (bit 5 at double speed),
+    // 512 times a second.
+    pub fn step_frame_sequencer(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.frame_step & 0x01 == 0 {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.square1.clock_sweep();
+        }
+
+        if self.frame_step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+
+        self.frame_step = (self.frame_step + 1) & 0x07;
+    }
+
+    // Advances by T-cycles at normal speed.
+    pub fn cycle(&mut self, ticks: u32) {
+        if self.enabled {
+            self.square1.step(ticks);
+            self.square2.step(ticks);
+            self.wave.step(ticks);
+            self.noise.step(ticks);
+        }
+
+        let levels = self.levels();
+
+        let (left, right) = levels.iter()
+            .fold((0.0, 0.0), |(left, right), &(l, r)| (left + l, right + r));
+        self.output.set_level(left, right);
+        self.output.advance(ticks);
+
+        for (output, &(left, right)) in self.channel_outputs.iter_mut().zip(levels.iter()) {
+            output.set_level(left, right);
+            output.advance(ticks);
+        }
+    }
+
+    // The contribution of each channel to the left and right output.
+    fn levels(&self) -> [(f32, f32); 4] {
+        let mut levels = [(0.0, 0.0); 4];
+
+        if !self.enabled {
+            return levels;
+        }
+
+        let outputs = [
+            self.square1.output(),
+            self.square2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ];
+
+        let left_volume = ((self.nr50 >> 4) & 0x07) as f32 + 1.0;
+        let right_volume = (self.nr50 & 0x07) as f32 + 1.0;
+
+        for (i, (output, level)) in outputs.iter().zip(levels.iter_mut()).enumerate() {
+            // Each DAC maps 0-15 onto a voltage, a DAC that is off is silent.
+            let analog = match *output {
+                Some(digital) => digital as f32 / 7.5 - 1.0,
+                None => continue,
+            };
+
+            if self.nr51 & (0x10 << i) != 0 {
+                level.0 = analog / 4.0 * left_volume / 8.0;
+            }
+            if self.nr51 & (0x01 << i) != 0 {
+                level.1 = analog / 4.0 * right_volume / 8.0;
+            }
+        }
+
+        levels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Starts channel 2 with a length counter one clock from expiring.
+    fn expiring_square2(apu: &mut Apu) {
+        apu.wb(0xFF16, 0x3F);
+        apu.wb(0xFF17, 0xF0);
+        apu.wb(0xFF19, 0xC0);
+    }
+
+    #[test]
+    fn length_expiry_clears_status() {
+        let mut apu = Apu::new();
+        expiring_square2(&mut apu);
+        assert_eq!(apu.rb(0xFF26), 0xF2);
+
+        apu.step_frame_sequencer();
+        assert_eq!(apu.rb(0xFF26), 0xF0);
+    }
+
+    #[test]
+    fn lengths_clock_on_even_steps() {
+        let mut apu = Apu::new();
+        apu.step_frame_sequencer();
+        expiring_square2(&mut apu);
+
+        // Triggering in step 1, which does not clock lengths, reloads 64
+        // and takes one clock right away, so 63 even steps remain.
+        for _ in 0..62 {
+            apu.step_frame_sequencer();
+            apu.step_frame_sequencer();
+        }
+        apu.step_frame_sequencer();
+        assert_eq!(apu.rb(0xFF26) & 0x02, 0x02);
+        apu.step_frame_sequencer();
+        assert_eq!(apu.rb(0xFF26) & 0x02, 0x00);
+    }
+
+    #[test]
+    fn power_off_clears_registers() {
+        let mut apu = Apu::new();
+        expiring_square2(&mut apu);
+        apu.wb(0xFF25, 0xFF);
+        apu.wb(0xFF30, 0x12);
+
+        apu.wb(0xFF26, 0x00);
+        assert_eq!(apu.rb(0xFF26), 0x70);
+        assert_eq!(apu.rb(0xFF25), 0x00);
+        apu.wb(0xFF25, 0xFF);
+        assert_eq!(apu.rb(0xFF25), 0x00);
+        assert_eq!(apu.rb(0xFF30), 0x12);
+    }
+}
@@ -0,0 +1,173 @@
+use super::envelope::Envelope;
+use super::length::Length;
+
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// Channel 4 outputs the low bit of a linear feedback shift register,
+// 15 bits wide or 7 bits in the short mode.
+pub struct Noise {
+    enabled: bool,
+
+    polynomial: u8,
+    lfsr: u16,
+    timer: u32,
+
+    length: Length,
+    envelope: Envelope,
+}
+
+impl Noise {
+    pub fn new() -> Noise {
+        Noise {
+            enabled: false,
+
+            polynomial: 0,
+            lfsr: 0x7FFF,
+            timer: 8,
+
+            length: Length::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn output(&self) -> Option<u8> {
+        if !self.envelope.dac_enabled() {
+            return None;
+        }
+
+        if self.enabled && self.lfsr & 0x01 == 0 {
+            Some(self.envelope.volume())
+        } else {
+            Some(0)
+        }
+    }
+
+    fn period(&self) -> u32 {
+        DIVISORS[(self.polynomial & 0x07) as usize] << (self.polynomial >> 4)
+    }
+
+    pub fn step(&mut self, ticks: u32) {
+        let mut ticks = ticks;
+        while ticks >= self.timer {
+            ticks -= self.timer;
+            self.timer = self.period();
+
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+            if self.polynomial & 0x08 != 0 {
+                self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+            }
+        }
+        self.timer -= ticks;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = self.period();
+        self.lfsr = 0x7FFF;
+        self.envelope.trigger();
+    }
+
+    pub fn rb(&self, register: u16) -> u8 {
+        match register {
+            0 | 1 => 0xFF,
+            2 => self.envelope.register(),
+            3 => self.polynomial,
+            4 => 0xBF | if self.length.enabled() { 0x40 } else { 0 },
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn wb(&mut self, register: u16, value: u8, extra_length_clock: bool) {
+        match register {
+            0 => {}
+            1 => self.length.load(value & 0x3F),
+            2 => {
+                self.envelope.set_register(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.polynomial = value,
+            4 => {
+                if self.length.write_control(value, extra_length_clock) {
+                    self.enabled = false;
+                }
+                if value & 0x80 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Triggers with the fastest clock, which shifts every 8 T-cycles.
+    fn noise(polynomial: u8) -> Noise {
+        let mut noise = Noise::new();
+        noise.wb(2, 0xF0, false);
+        noise.wb(3, polynomial, false);
+        noise.wb(4, 0x80, false);
+        noise
+    }
+
+    fn period(noise: &mut Noise, mask: u16) -> usize {
+        let start = noise.lfsr & mask;
+        (1..=0x8000)
+            .find(|_| {
+                noise.step(8);
+                noise.lfsr & mask == start
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn lfsr_widths() {
+        assert_eq!(period(&mut noise(0x00), 0x7FFF), 32767);
+        assert_eq!(period(&mut noise(0x08), 0x007F), 127);
+    }
+
+    #[test]
+    fn lfsr_feedback() {
+        let mut noise = noise(0x00);
+        noise.step(7);
+        assert_eq!(noise.lfsr, 0x7FFF);
+        noise.step(1);
+        assert_eq!(noise.lfsr, 0x3FFF);
+        noise.step(8);
+        assert_eq!(noise.lfsr, 0x1FFF);
+
+        // The short mode also feeds the bit into bit 6.
+        let mut noise = self::noise(0x08);
+        noise.step(8);
+        assert_eq!(noise.lfsr, 0x3FBF);
+    }
+
+    #[test]
+    fn clock_shift_and_divisor() {
+        // Divisor code 0 is 8, code 1 is 16, then shifted by the clock.
+        let mut noise = noise(0x21);
+        noise.step(16 * 4 - 1);
+        assert_eq!(noise.lfsr, 0x7FFF);
+        noise.step(1);
+        assert_eq!(noise.lfsr, 0x3FFF);
+    }
+}
@@ -0,0 +1,268 @@
+use super::envelope::Envelope;
+use super::length::Length;
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+// Square channels 1 and 2. Only channel 1 has the frequency sweep, for
+// channel 2 the first register is unmapped.
+pub struct Square {
+    has_sweep: bool,
+    enabled: bool,
+
+    duty: u8,
+    duty_position: u8,
+    frequency: u16,
+    timer: u32,
+
+    length: Length,
+    envelope: Envelope,
+
+    sweep: u8,
+    sweep_enabled: bool,
+    sweep_shadow: u16,
+    sweep_timer: u8,
+    // Set once a subtraction has been calculated since the last trigger.
+    sweep_negated: bool,
+}
+
+impl Square {
+    pub fn new(has_sweep: bool) -> Square {
+        Square {
+            has_sweep,
+            enabled: false,
+
+            duty: 0,
+            duty_position: 0,
+            frequency: 0,
+            timer: 8192,
+
+            length: Length::new(64),
+            envelope: Envelope::new(),
+
+            sweep: 0,
+            sweep_enabled: false,
+            sweep_shadow: 0,
+            sweep_timer: 0,
+            sweep_negated: false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // The digital level 0-15, or None when the DAC is off.
+    pub fn output(&self) -> Option<u8> {
+        if !self.envelope.dac_enabled() {
+            return None;
+        }
+
+        if self.enabled && DUTY_PATTERNS[self.duty as usize][self.duty_position as usize] != 0 {
+            Some(self.envelope.volume())
+        } else {
+            Some(0)
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    pub fn step(&mut self, ticks: u32) {
+        let mut ticks = ticks;
+        while ticks >= self.timer {
+            ticks -= self.timer;
+            self.timer = self.period();
+            self.duty_position = (self.duty_position + 1) & 0x07;
+        }
+        self.timer -= ticks;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if self.sweep_timer > 0 {
+            self.sweep_timer -= 1;
+        }
+        if self.sweep_timer > 0 {
+            return;
+        }
+        self.sweep_timer = self.sweep_period();
+
+        if !self.sweep_enabled || self.sweep & 0x70 == 0 {
+            return;
+        }
+
+        let frequency = self.sweep_frequency();
+        if frequency <= 2047 && self.sweep & 0x07 != 0 {
+            self.sweep_shadow = frequency;
+            self.frequency = frequency;
+            // The result is checked for overflow once more, but not used.
+            self.sweep_frequency();
+        }
+    }
+
+    fn sweep_period(&self) -> u8 {
+        match (self.sweep >> 4) & 0x07 {
+            0 => 8,
+            period => period,
+        }
+    }
+
+    // Computes the next frequency and disables the channel on overflow.
+    fn sweep_frequency(&mut self) -> u16 {
+        let delta = self.sweep_shadow >> (self.sweep & 0x07);
+        let frequency = if self.sweep & 0x08 != 0 {
+            self.sweep_negated = true;
+            self.sweep_shadow - delta
+        } else {
+            self.sweep_shadow + delta
+        };
+
+        if frequency > 2047 {
+            self.enabled = false;
+        }
+        frequency
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        if self.has_sweep {
+            self.sweep_shadow = self.frequency;
+            self.sweep_timer = self.sweep_period();
+            self.sweep_enabled = self.sweep & 0x77 != 0;
+            self.sweep_negated = false;
+
+            if self.sweep & 0x07 != 0 {
+                self.sweep_frequency();
+            }
+        }
+    }
+
+    pub fn rb(&self, register: u16) -> u8 {
+        match register {
+            0 if self.has_sweep => 0x80 | self.sweep,
+            0 => 0xFF,
+            1 => 0x3F | self.duty << 6,
+            2 => self.envelope.register(),
+            3 => 0xFF,
+            4 => 0xBF | if self.length.enabled() { 0x40 } else { 0 },
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn wb(&mut self, register: u16, value: u8, extra_length_clock: bool) {
+        match register {
+            0 if self.has_sweep => {
+                // Leaving subtraction mode after it was used kills the channel.
+                if self.sweep_negated && self.sweep & 0x08 != 0 && value & 0x08 == 0 {
+                    self.enabled = false;
+                }
+                self.sweep = value & 0x7F;
+            }
+            0 => {}
+            1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0x3F);
+            }
+            2 => {
+                self.envelope.set_register(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x0700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | ((value as u16 & 0x07) << 8);
+
+                if self.length.write_control(value, extra_length_clock) {
+                    self.enabled = false;
+                }
+                if value & 0x80 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Channel 1 at full volume with the given sweep and frequency.
+    fn square(sweep: u8, frequency: u16) -> Square {
+        let mut square = Square::new(true);
+        square.wb(0, sweep, false);
+        square.wb(2, 0xF0, false);
+        square.wb(3, frequency as u8, false);
+        square.wb(4, 0x80 | (frequency >> 8) as u8, false);
+        square
+    }
+
+    #[test]
+    fn sweep_overflow_on_trigger() {
+        // 0x7FF + (0x7FF >> 1) overflows before the first sweep clock.
+        assert!(!square(0x11, 0x7FF).enabled());
+
+        // Without a shift nothing is calculated at trigger.
+        assert!(square(0x10, 0x7FF).enabled());
+        assert!(square(0x11, 0x554).enabled());
+    }
+
+    #[test]
+    fn sweep_overflow_after_update() {
+        // 0x400 becomes 0x600, and the second check sees 0x900.
+        let mut square = square(0x11, 0x400);
+        assert!(square.enabled());
+        square.clock_sweep();
+        assert_eq!(square.frequency, 0x600);
+        assert!(!square.enabled());
+    }
+
+    #[test]
+    fn sweep_period_and_shadow() {
+        let mut square = square(0x21, 0x100);
+        square.clock_sweep();
+        assert_eq!(square.frequency, 0x100);
+        square.clock_sweep();
+        assert_eq!(square.frequency, 0x180);
+
+        // NR13 writes do not reach the shadow register.
+        square.wb(3, 0x00, false);
+        square.clock_sweep();
+        square.clock_sweep();
+        assert_eq!(square.frequency, 0x240);
+        assert!(square.enabled());
+    }
+
+    #[test]
+    fn leaving_negate_after_use_disables() {
+        let mut square = square(0x19, 0x400);
+        assert!(square.enabled());
+        square.wb(0, 0x11, false);
+        assert!(!square.enabled());
+
+        // Switching before a subtraction was calculated is harmless.
+        let mut square = self::square(0x18, 0x400);
+        square.wb(0, 0x10, false);
+        assert!(square.enabled());
+    }
+}
@@ -0,0 +1,148 @@
+use super::length::Length;
+
+// Channel 3 plays back 32 four-bit samples from wave RAM.
+pub struct Wave {
+    enabled: bool,
+    dac_enabled: bool,
+
+    volume: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    // The sample last read from wave RAM, played until the next one.
+    sample: u8,
+
+    length: Length,
+
+    ram: [u8; 16],
+}
+
+impl Wave {
+    pub fn new() -> Wave {
+        Wave {
+            enabled: false,
+            dac_enabled: false,
+
+            volume: 0,
+            frequency: 0,
+            timer: 4096,
+            position: 0,
+            sample: 0,
+
+            length: Length::new(256),
+
+            ram: [0; 16],
+        }
+    }
+
+    // Everything but wave RAM is cleared when the APU is powered off.
+    pub fn power_off(&mut self) {
+        *self = Wave {
+            ram: self.ram,
+            ..Wave::new()
+        };
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn output(&self) -> Option<u8> {
+        if !self.dac_enabled {
+            return None;
+        }
+
+        if !self.enabled {
+            return Some(0);
+        }
+
+        // Volume codes 1-3 play at 100%, 50% and 25%, 0 mutes.
+        match self.volume {
+            0 => Some(0),
+            code => Some(self.sample >> (code - 1)),
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    pub fn step(&mut self, ticks: u32) {
+        let mut ticks = ticks;
+        while ticks >= self.timer {
+            ticks -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) & 0x1F;
+
+            let byte = self.ram[self.position as usize / 2];
+            self.sample = if self.position & 0x01 == 0 { byte >> 4 } else { byte & 0x0F };
+        }
+        self.timer -= ticks;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        // Playback starts a few dots late and at the second sample.
+        self.timer = self.period() + 6;
+        self.position = 0;
+    }
+
+    // While the channel plays, the CPU only reaches the byte being read.
+    pub fn load_ram(&self, offset: u16) -> u8 {
+        if self.enabled {
+            self.ram[self.position as usize / 2]
+        } else {
+            self.ram[offset as usize]
+        }
+    }
+
+    pub fn store_ram(&mut self, offset: u16, value: u8) {
+        if self.enabled {
+            self.ram[self.position as usize / 2] = value;
+        } else {
+            self.ram[offset as usize] = value;
+        }
+    }
+
+    pub fn rb(&self, register: u16) -> u8 {
+        match register {
+            0 => 0x7F | if self.dac_enabled { 0x80 } else { 0 },
+            1 => 0xFF,
+            2 => 0x9F | self.volume << 5,
+            3 => 0xFF,
+            4 => 0xBF | if self.length.enabled() { 0x40 } else { 0 },
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn wb(&mut self, register: u16, value: u8, extra_length_clock: bool) {
+        match register {
+            0 => {
+                self.dac_enabled = value & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(value),
+            2 => self.volume = (value >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x0700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | ((value as u16 & 0x07) << 8);
+
+                if self.length.write_control(value, extra_length_clock) {
+                    self.enabled = false;
+                }
+                if value & 0x80 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+const DMG_SIZE: usize = 0x100;
+const CGB_SIZE: usize = 0x900;
+
+// A boot ROM dump, mapped over the cartridge until 0xFF50 is written. The
+// DMG, MGB and SGB ones are 256 bytes at 0x0000. The CGB and AGB ones add
+// 0x0200-0x08FF, leaving the cartridge header visible in between.
+pub struct BootRom {
+    data: Vec<u8>,
+}
+
+impl BootRom {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<BootRom> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+
+        BootRom::from_bytes(data)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> io::Result<BootRom> {
+        match data.len() {
+            DMG_SIZE | CGB_SIZE => Ok(BootRom { data }),
+            len => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("boot ROM must be {} or {} bytes, not {}", DMG_SIZE, CGB_SIZE, len))),
+        }
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.data.len() == CGB_SIZE
+    }
+
+    pub fn load8(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x00FF => Some(self.data[addr as usize]),
+            0x0200..=0x08FF if self.is_cgb() => Some(self.data[addr as usize]),
+            _ => None,
+        }
+    }
+}
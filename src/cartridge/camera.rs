@@ -0,0 +1,314 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use rom::Rom;
+
+use super::{load_into, Mbc};
+
+pub const SENSOR_WIDTH: usize = 128;
+pub const SENSOR_HEIGHT: usize = 112;
+
+// Where the Pocket Camera gets its pictures from. A capture is
+// SENSOR_WIDTH * SENSOR_HEIGHT grey levels, row by row, 0 being black.
+pub trait ImageSensor {
+    fn capture(&mut self) -> Vec<u8>;
+}
+
+// Always sees the same picture, loaded from a binary or plain PGM/PPM
+// file and scaled to the sensor resolution.
+pub struct StaticImage {
+    pixels: Vec<u8>,
+}
+
+impl StaticImage {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<StaticImage> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+
+        StaticImage::from_netpbm(&data)
+    }
+
+    pub fn from_netpbm(data: &[u8]) -> io::Result<StaticImage> {
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+
+        let mut pos = 0;
+        let mut header = Vec::new();
+        while header.len() < 4 {
+            while pos < data.len() && data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < data.len() && data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+            let start = pos;
+            while pos < data.len() && !data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid("truncated image header"));
+            }
+            header.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+        }
+        // A single whitespace byte separates the header from binary data.
+        pos += 1;
+
+        let channels = match header[0].as_str() {
+            "P2" | "P5" => 1,
+            "P3" | "P6" => 3,
+            _ => return Err(invalid("not a PGM or PPM image")),
+        };
+        let parse = |s: &str| s.parse::<usize>().map_err(|_| invalid("bad image header"));
+        let width = parse(&header[1])?;
+        let height = parse(&header[2])?;
+        let max = parse(&header[3])?;
+        if width == 0 || height == 0 || max == 0 || max > 0xFFFF {
+            return Err(invalid("bad image header"));
+        }
+
+        let count = width * height * channels;
+        let samples: Vec<usize> = match header[0].as_str() {
+            "P2" | "P3" => String::from_utf8_lossy(&data[pos.min(data.len())..])
+                .split_whitespace()
+                .take(count)
+                .map(parse)
+                .collect::<io::Result<_>>()?,
+            _ if max < 0x100 => data.iter().skip(pos).take(count).map(|&b| b as usize).collect(),
+            _ => data[pos.min(data.len())..]
+                .chunks(2)
+                .take(count)
+                .map(|pair| (pair[0] as usize) << 8 | *pair.get(1).unwrap_or(&0) as usize)
+                .collect(),
+        };
+        if samples.len() < count {
+            return Err(invalid("truncated image data"));
+        }
+
+        let mut pixels = Vec::with_capacity(SENSOR_WIDTH * SENSOR_HEIGHT);
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let index = (y * height / SENSOR_HEIGHT * width + x * width / SENSOR_WIDTH) * channels;
+                let sum: usize = samples[index..index + channels].iter().sum();
+                pixels.push((sum * 0xFF / (max * channels)) as u8);
+            }
+        }
+
+        Ok(StaticImage { pixels })
+    }
+}
+
+impl ImageSensor for StaticImage {
+    fn capture(&mut self) -> Vec<u8> {
+        self.pixels.clone()
+    }
+}
+
+// Camera registers shadow RAM when bit 4 of the RAM bank is set. Only
+// the trigger register can be read back.
+const CAMERA_REGISTERS: usize = 0x36;
+const DITHER_MATRIX: usize = 0x06;
+// Captures land in RAM bank 0 as 16x14 tiles.
+const IMAGE_OFFSET: usize = 0x0100;
+
+// The Pocket Camera (MAC-GBD) banks 64 ROM banks and 16 RAM banks, plus
+// the M64282FP sensor registers. Writing 1 to bit 0 of register 0 takes
+// a picture and converts it to 2bpp tiles using the 4x4 threshold matrix
+// in registers 0x06-0x35. Gain, exposure and edge enhancement are not
+// modelled, the sensor picture is used as it comes.
+pub struct PocketCamera {
+    ram: Vec<u8>,
+    sensor: Option<Box<dyn ImageSensor>>,
+    ram_enabled: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    registers: [u8; CAMERA_REGISTERS],
+}
+
+impl PocketCamera {
+    pub fn new(rom: &Rom) -> PocketCamera {
+        PocketCamera {
+            ram: vec![0; rom.header.ram_size.bytes()],
+            sensor: None,
+            ram_enabled: false,
+            rom_bank: 0x01,
+            ram_bank: 0x00,
+            registers: [0; CAMERA_REGISTERS],
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        (self.ram_bank as usize * 0x2000 + addr as usize) % self.ram.len()
+    }
+
+    fn capture(&mut self) {
+        let pixels = match self.sensor {
+            Some(ref mut sensor) => sensor.capture(),
+            None => vec![0xFF; SENSOR_WIDTH * SENSOR_HEIGHT],
+        };
+
+        if self.ram.len() < IMAGE_OFFSET + SENSOR_WIDTH * SENSOR_HEIGHT / 4 {
+            return;
+        }
+
+        let matrix = &self.registers[DITHER_MATRIX..];
+
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let value = *pixels.get(y * SENSOR_WIDTH + x).unwrap_or(&0xFF);
+                let thresholds = &matrix[((y & 3) * 4 + (x & 3)) * 3..];
+
+                let color = if value < thresholds[0] {
+                    3
+                } else if value < thresholds[1] {
+                    2
+                } else if value < thresholds[2] {
+                    1
+                } else {
+                    0
+                };
+
+                let tile = (y / 8) * (SENSOR_WIDTH / 8) + x / 8;
+                let offset = IMAGE_OFFSET + tile * 16 + (y % 8) * 2;
+                let bit = 0x80 >> (x % 8);
+
+                if color & 0x01 != 0 {
+                    self.ram[offset] |= bit;
+                } else {
+                    self.ram[offset] &= !bit;
+                }
+                if color & 0x02 != 0 {
+                    self.ram[offset + 1] |= bit;
+                } else {
+                    self.ram[offset + 1] &= !bit;
+                }
+            }
+        }
+    }
+}
+
+impl Mbc for PocketCamera {
+    fn load_rom(&self, rom: &Rom, addr: u16) -> u8 {
+        if addr < 0x4000 {
+            rom.load8(addr as usize)
+        } else {
+            rom.load8(self.rom_bank as usize * 0x4000 + (addr & 0x3FFF) as usize)
+        }
+    }
+
+    fn store_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x1F,
+            _ => {}
+        }
+    }
+
+    fn load_ram(&self, addr: u16) -> u8 {
+        if self.ram_bank & 0x10 != 0 {
+            return if addr & 0x7F == 0 { self.registers[0] & 0x07 } else { 0x00 };
+        }
+
+        if self.ram.is_empty() {
+            return 0xFF;
+        }
+
+        self.ram[self.ram_offset(addr)]
+    }
+
+    fn store_ram(&mut self, addr: u16, value: u8) -> bool {
+        if self.ram_bank & 0x10 != 0 {
+            let register = (addr & 0x7F) as usize;
+            if register >= CAMERA_REGISTERS {
+                return false;
+            }
+
+            self.registers[register] = value;
+            if register == 0 && value & 0x01 != 0 {
+                // The capture finishes immediately, so the busy bit is
+                // never seen set.
+                self.capture();
+                self.registers[0] &= !0x01;
+                return true;
+            }
+            return false;
+        }
+
+        if !self.ram_enabled || self.ram.is_empty() {
+            return false;
+        }
+
+        let offset = self.ram_offset(addr);
+        self.ram[offset] = value;
+        true
+    }
+
+    fn set_image_sensor(&mut self, sensor: Box<dyn ImageSensor>) {
+        self.sensor = Some(sensor);
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_into(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(data: &[u8]) -> String {
+        match StaticImage::from_netpbm(data) {
+            Ok(_) => panic!("{:?} parsed", String::from_utf8_lossy(data)),
+            Err(err) => {
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+                err.to_string()
+            }
+        }
+    }
+
+    #[test]
+    fn scales_to_sensor() {
+        // Two columns, the top row white and the bottom one grey.
+        let image = StaticImage::from_netpbm(b"P5\n# comment\n2 2\n255\n\xFF\xFF\x80\x80").unwrap();
+        assert_eq!(image.pixels.len(), SENSOR_WIDTH * SENSOR_HEIGHT);
+        assert_eq!(image.pixels[0], 0xFF);
+        assert_eq!(image.pixels[SENSOR_WIDTH * SENSOR_HEIGHT - 1], 0x80);
+    }
+
+    #[test]
+    fn averages_colour_and_scales_max() {
+        let image = StaticImage::from_netpbm(b"P3 1 1 15 15 0 0").unwrap();
+        assert_eq!(image.pixels[0], 0x55);
+
+        let image = StaticImage::from_netpbm(b"P5 1 1 65535 \x80\x00").unwrap();
+        assert_eq!(image.pixels[0], 0x7F);
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        assert_eq!(error(b""), "truncated image header");
+        assert_eq!(error(b"P5 2 2"), "truncated image header");
+        assert_eq!(error(b"P5 2 2 # 255"), "truncated image header");
+        assert_eq!(error(b"P4 1 1 1 \x00"), "not a PGM or PPM image");
+        assert_eq!(error(b"P5 two 2 255 \x00"), "bad image header");
+        assert_eq!(error(b"P5 -1 1 255 \x00"), "bad image header");
+        assert_eq!(error(b"P5 0 1 255 "), "bad image header");
+        assert_eq!(error(b"P5 1 1 0 \x00"), "bad image header");
+        assert_eq!(error(b"P5 1 1 65536 \x00\x00"), "bad image header");
+    }
+
+    #[test]
+    fn rejects_short_data() {
+        assert_eq!(error(b"P5 2 2 255 \x00\x00\x00"), "truncated image data");
+        assert_eq!(error(b"P6 1 1 255 \x00\x00"), "truncated image data");
+        assert_eq!(error(b"P2 2 1 255 0"), "truncated image data");
+        assert_eq!(error(b"P2 2 1 255 0 x"), "bad image header");
+    }
+}
@@ -0,0 +1,90 @@
+use rom::Rom;
+
+use super::{load_into, Mbc};
+
+// HuC1 is close to MBC1 with a 6-bit ROM bank and up to four RAM banks,
+// but 0x0000-0x1FFF switches 0xA000-0xBFFF between RAM and the infrared
+// port instead of enabling RAM. Writing 0x0E selects the IR port, where
+// bit 0 drives the LED and reads report received light in bit 0. There
+// is no second Game Boy to talk to, so no light is ever seen.
+pub struct HuC1 {
+    ram: Vec<u8>,
+    ir_mode: bool,
+    ir_led: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+}
+
+impl HuC1 {
+    pub fn new(rom: &Rom) -> HuC1 {
+        HuC1 {
+            ram: vec![0; rom.header.ram_size.bytes()],
+            ir_mode: false,
+            ir_led: false,
+            rom_bank: 0x01,
+            ram_bank: 0x00,
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        (self.ram_bank as usize * 0x2000 + addr as usize) % self.ram.len()
+    }
+}
+
+impl Mbc for HuC1 {
+    fn load_rom(&self, rom: &Rom, addr: u16) -> u8 {
+        if addr < 0x4000 {
+            rom.load8(addr as usize)
+        } else {
+            rom.load8(self.rom_bank as usize * 0x4000 + (addr & 0x3FFF) as usize)
+        }
+    }
+
+    fn store_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ir_mode = value & 0x0F == 0x0E,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+
+    fn load_ram(&self, addr: u16) -> u8 {
+        if self.ir_mode {
+            return 0xC0;
+        }
+
+        if self.ram.is_empty() {
+            return 0xFF;
+        }
+
+        self.ram[self.ram_offset(addr)]
+    }
+
+    fn store_ram(&mut self, addr: u16, value: u8) -> bool {
+        if self.ir_mode {
+            self.ir_led = value & 0x01 != 0;
+            return false;
+        }
+
+        if self.ram.is_empty() {
+            return false;
+        }
+
+        let offset = self.ram_offset(addr);
+        self.ram[offset] = value;
+        true
+    }
+
+    fn ir_led(&self) -> bool {
+        self.ir_led
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_into(&mut self.ram, data);
+    }
+}
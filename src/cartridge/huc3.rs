@@ -0,0 +1,192 @@
+use rom::Rom;
+
+use super::{load_into, Mbc};
+use super::rtc::Clock;
+
+const MINUTES_PER_DAY: u64 = 24 * 60;
+
+// Clock chip memory and start time at the end of a save.
+const CLOCK_FOOTER: usize = 256 + 8;
+
+// HuC3 selects what 0xA000-0xBFFF talks to through 0x0000-0x1FFF:
+//
+//   0x00, 0x0A  RAM
+//   0x0B        command to the clock chip
+//   0x0C        result of the last command
+//   0x0D        semaphore, always ready
+//   0x0E        infrared port
+//
+// The clock chip keeps its state in 256 nibbles of memory. Commands carry
+// the opcode in bits 4-6 and an argument in the low nibble.
+pub struct HuC3 {
+    ram: Vec<u8>,
+    clock: Box<dyn Clock>,
+    mode: u8,
+    rom_bank: u8,
+    ram_bank: u8,
+    ir_led: bool,
+
+    // Seconds timestamp at which the chip's minute and day counters were
+    // zero.
+    base: u64,
+    memory: [u8; 256],
+    address: u8,
+    result: u8,
+    // Set until the clock is first saved and whenever the chip memory or
+    // the time is written.
+    clock_dirty: bool,
+}
+
+impl HuC3 {
+    pub fn new(rom: &Rom, clock: Box<dyn Clock>) -> HuC3 {
+        let base = clock.now();
+
+        HuC3 {
+            ram: vec![0; rom.header.ram_size.bytes()],
+            clock,
+            mode: 0x00,
+            rom_bank: 0x01,
+            ram_bank: 0x00,
+            ir_led: false,
+
+            base,
+            memory: [0; 256],
+            address: 0x00,
+            result: 0x00,
+            clock_dirty: true,
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        (self.ram_bank as usize * 0x2000 + addr as usize) % self.ram.len()
+    }
+
+    fn command(&mut self, value: u8) {
+        let argument = value & 0x0F;
+
+        match (value >> 4) & 0x07 {
+            0x1 => {
+                self.result = self.memory[self.address as usize];
+                self.address = self.address.wrapping_add(1);
+            }
+            0x3 => {
+                self.memory[self.address as usize] = argument;
+                self.address = self.address.wrapping_add(1);
+                self.clock_dirty = true;
+            }
+            0x4 => self.address = (self.address & 0xF0) | argument,
+            0x5 => self.address = (self.address & 0x0F) | argument << 4,
+            0x6 => match argument {
+                0x0 => self.latch_time(),
+                0x1 => self.set_time(),
+                // Status query, the chip is always idle.
+                0x2 => self.result = 0x01,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    // Minutes into the day in nibbles 0-2 and days in nibbles 3-5.
+    fn latch_time(&mut self) {
+        let elapsed = self.clock.now().saturating_sub(self.base) / 60;
+        let minutes = elapsed % MINUTES_PER_DAY;
+        let days = elapsed / MINUTES_PER_DAY;
+
+        for i in 0..3 {
+            self.memory[i] = (minutes >> (i * 4)) as u8 & 0x0F;
+            self.memory[i + 3] = (days >> (i * 4)) as u8 & 0x0F;
+        }
+    }
+
+    fn set_time(&mut self) {
+        let mut minutes = 0;
+        let mut days = 0;
+        for i in 0..3 {
+            minutes |= (self.memory[i] as u64) << (i * 4);
+            days |= (self.memory[i + 3] as u64) << (i * 4);
+        }
+
+        let elapsed = (days * MINUTES_PER_DAY + minutes) * 60;
+        self.base = self.clock.now().saturating_sub(elapsed);
+        self.clock_dirty = true;
+    }
+}
+
+impl Mbc for HuC3 {
+    fn load_rom(&self, rom: &Rom, addr: u16) -> u8 {
+        if addr < 0x4000 {
+            rom.load8(addr as usize)
+        } else {
+            rom.load8(self.rom_bank as usize * 0x4000 + (addr & 0x3FFF) as usize)
+        }
+    }
+
+    fn store_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.mode = value & 0x0F,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+
+    fn load_ram(&self, addr: u16) -> u8 {
+        match self.mode {
+            0x00 | 0x0A if !self.ram.is_empty() => self.ram[self.ram_offset(addr)],
+            0x0C => 0xE0 | self.result,
+            0x0D => 0x01,
+            0x0E => 0xC0,
+            _ => 0xFF,
+        }
+    }
+
+    fn store_ram(&mut self, addr: u16, value: u8) -> bool {
+        match self.mode {
+            0x0A if !self.ram.is_empty() => {
+                let offset = self.ram_offset(addr);
+                self.ram[offset] = value;
+                return true;
+            }
+            0x0B => self.command(value),
+            0x0E => self.ir_led = value & 0x01 != 0,
+            _ => {}
+        }
+        false
+    }
+
+    fn ir_led(&self) -> bool {
+        self.ir_led
+    }
+
+    // RAM, then the 256 nibbles of clock chip memory one per byte, then
+    // the 64-bit little endian time the counters started from.
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        data.extend_from_slice(&self.memory);
+        data.extend_from_slice(&self.base.to_le_bytes());
+        data
+    }
+
+    // Saves with only the RAM leave the clock where it is.
+    fn load_save_data(&mut self, data: &[u8]) {
+        let split = self.ram.len().min(data.len());
+        load_into(&mut self.ram, &data[..split]);
+
+        let footer = &data[split..];
+        if footer.len() >= CLOCK_FOOTER {
+            load_into(&mut self.memory, &footer[..256]);
+            let mut base = [0; 8];
+            base.copy_from_slice(&footer[256..264]);
+            self.base = u64::from_le_bytes(base);
+        }
+    }
+
+    fn dirty(&self) -> bool {
+        self.clock_dirty
+    }
+
+    fn clear_dirty(&mut self) {
+        self.clock_dirty = false;
+    }
+}
@@ -0,0 +1,106 @@
+use rom::{Rom, RomSize};
+
+use super::{load_into, Mbc};
+
+// MBC1 has a 5-bit BANK1 register for the switchable ROM bank and a 2-bit
+// BANK2 register that either extends the ROM bank number or selects the
+// RAM bank. In mode 0 BANK2 only affects 0x4000-0x7FFF, in mode 1 it also
+// applies to 0x0000-0x3FFF and to RAM.
+//
+// MBC1M multicarts wire BANK2 one bit lower, so only four bits of BANK1
+// reach the ROM and each game sees its own 256 KiB.
+pub struct Mbc1 {
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    bank1: u8,
+    bank2: u8,
+    mode: bool,
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: &Rom) -> Mbc1 {
+        let multicart = rom.header.rom_size == RomSize::Mib1 && rom.has_logo_at(0x10);
+
+        Mbc1 {
+            ram: vec![0; rom.header.ram_size.bytes()],
+            ram_enabled: false,
+            bank1: 0x01,
+            bank2: 0x00,
+            mode: false,
+            multicart,
+        }
+    }
+
+    fn bank2_shift(&self) -> usize {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn low_bank(&self) -> usize {
+        if self.mode {
+            (self.bank2 as usize) << self.bank2_shift()
+        } else {
+            0
+        }
+    }
+
+    fn high_bank(&self) -> usize {
+        let bank1 = if self.multicart { self.bank1 & 0x0F } else { self.bank1 };
+
+        (self.bank2 as usize) << self.bank2_shift() | bank1 as usize
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        let bank = if self.mode { self.bank2 as usize } else { 0 };
+
+        (bank * 0x2000 + addr as usize) % self.ram.len()
+    }
+}
+
+impl Mbc for Mbc1 {
+    fn load_rom(&self, rom: &Rom, addr: u16) -> u8 {
+        let offset = (addr & 0x3FFF) as usize;
+
+        if addr < 0x4000 {
+            rom.load8(self.low_bank() * 0x4000 + offset)
+        } else {
+            rom.load8(self.high_bank() * 0x4000 + offset)
+        }
+    }
+
+    fn store_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            // The zero check looks at all five bits, even on multicarts.
+            0x2000..=0x3FFF => self.bank1 = if value & 0x1F == 0 { 0x01 } else { value & 0x1F },
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+            _ => self.mode = value & 0x01 != 0,
+        }
+    }
+
+    fn load_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+
+        self.ram[self.ram_offset(addr)]
+    }
+
+    fn store_ram(&mut self, addr: u16, value: u8) -> bool {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return false;
+        }
+
+        let offset = self.ram_offset(addr);
+        self.ram[offset] = value;
+        true
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_into(&mut self.ram, data);
+    }
+}
@@ -0,0 +1,71 @@
+use rom::Rom;
+
+use super::{load_into, Mbc};
+
+// MBC2 has 512 half-bytes of RAM built into the controller and only one
+// register window. Address bit 8 picks between RAM enable (clear) and the
+// 4-bit ROM bank (set). The RAM repeats through all of 0xA000-0xBFFF and
+// the upper nibble of each byte is open bus.
+pub struct Mbc2 {
+    ram: [u8; 0x200],
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new() -> Mbc2 {
+        Mbc2 {
+            ram: [0; 0x200],
+            ram_enabled: false,
+            rom_bank: 0x01,
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn load_rom(&self, rom: &Rom, addr: u16) -> u8 {
+        if addr < 0x4000 {
+            rom.load8(addr as usize)
+        } else {
+            rom.load8(self.rom_bank as usize * 0x4000 + (addr & 0x3FFF) as usize)
+        }
+    }
+
+    fn store_rom(&mut self, addr: u16, value: u8) {
+        if addr >= 0x4000 {
+            return;
+        }
+
+        if addr & 0x0100 == 0 {
+            self.ram_enabled = value & 0x0F == 0x0A;
+        } else {
+            self.rom_bank = if value & 0x0F == 0 { 0x01 } else { value & 0x0F };
+        }
+    }
+
+    fn load_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        self.ram[(addr & 0x01FF) as usize] | 0xF0
+    }
+
+    fn store_ram(&mut self, addr: u16, value: u8) -> bool {
+        if self.ram_enabled {
+            self.ram[(addr & 0x01FF) as usize] = value & 0x0F;
+        }
+        self.ram_enabled
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_into(&mut self.ram, data);
+        for value in self.ram.iter_mut() {
+            *value &= 0x0F;
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use rom::Rom;
+
+use super::{load_into, Mbc};
+use super::rtc::{self, Clock, Rtc};
+
+// MBC3 switches 7 bits worth of ROM banks into 0x4000-0x7FFF and up to
+// four RAM banks into 0xA000-0xBFFF. Selecting 0x08-0x0C instead of a RAM
+// bank maps one of the clock registers there. Writing 0x00 then 0x01 to
+// 0x6000-0x7FFF latches the running clock into those registers.
+pub struct Mbc3 {
+    ram: Vec<u8>,
+    rtc: Option<Rtc>,
+    ram_enabled: bool,
+    rom_bank: u8,
+    ram_select: u8,
+    latch_armed: bool,
+}
+
+impl Mbc3 {
+    pub fn new(rom: &Rom, clock: Box<dyn Clock>) -> Mbc3 {
+        let rtc = if rom.header.cartridge_type.timer {
+            Some(Rtc::new(clock))
+        } else {
+            None
+        };
+
+        Mbc3 {
+            ram: vec![0; rom.header.ram_size.bytes()],
+            rtc,
+            ram_enabled: false,
+            rom_bank: 0x01,
+            ram_select: 0x00,
+            latch_armed: false,
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        (self.ram_select as usize * 0x2000 + addr as usize) % self.ram.len()
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn load_rom(&self, rom: &Rom, addr: u16) -> u8 {
+        if addr < 0x4000 {
+            rom.load8(addr as usize)
+        } else {
+            rom.load8(self.rom_bank as usize * 0x4000 + (addr & 0x3FFF) as usize)
+        }
+    }
+
+    fn store_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = if value & 0x7F == 0 { 0x01 } else { value & 0x7F },
+            0x4000..=0x5FFF => self.ram_select = value,
+            _ => {
+                if self.latch_armed && value == 0x01 {
+                    if let Some(ref mut rtc) = self.rtc {
+                        rtc.latch();
+                    }
+                }
+                self.latch_armed = value == 0x00;
+            }
+        }
+    }
+
+    fn load_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        match (self.ram_select, self.rtc.as_ref()) {
+            (0x00..=0x03, _) if !self.ram.is_empty() => self.ram[self.ram_offset(addr)],
+            (rtc::SECONDS..=rtc::DAYS_HIGH, Some(rtc)) => rtc.read(self.ram_select),
+            _ => 0xFF,
+        }
+    }
+
+    fn store_ram(&mut self, addr: u16, value: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+
+        match self.ram_select {
+            0x00..=0x03 if !self.ram.is_empty() => {
+                let offset = self.ram_offset(addr);
+                self.ram[offset] = value;
+                true
+            }
+            rtc::SECONDS..=rtc::DAYS_HIGH => {
+                if let Some(ref mut rtc) = self.rtc {
+                    rtc.write(self.ram_select, value);
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+
+    // RAM followed by the 48 byte clock footer BGB and VBA-M use.
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(ref rtc) = self.rtc {
+            data.extend_from_slice(&rtc.save());
+        }
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let split = self.ram.len().min(data.len());
+        load_into(&mut self.ram, &data[..split]);
+
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.load(&data[split..]);
+        }
+    }
+
+    fn dirty(&self) -> bool {
+        self.rtc.as_ref().is_some_and(|rtc| rtc.dirty())
+    }
+
+    fn clear_dirty(&mut self) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.clear_dirty();
+        }
+    }
+}
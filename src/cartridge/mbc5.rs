@@ -0,0 +1,88 @@
+use rom::Rom;
+
+use super::{load_into, Mbc};
+
+// MBC5 splits a 9-bit ROM bank over 0x2000-0x2FFF (low byte) and
+// 0x3000-0x3FFF (bit 8), and unlike the older controllers bank 0 can be
+// mapped into 0x4000-0x7FFF. Up to 16 RAM banks are selected through
+// 0x4000-0x5FFF. On rumble cartridges bit 3 of that register drives the
+// motor instead, leaving three bits for the RAM bank.
+pub struct Mbc5 {
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl Mbc5 {
+    pub fn new(rom: &Rom) -> Mbc5 {
+        Mbc5 {
+            ram: vec![0; rom.header.ram_size.bytes()],
+            ram_enabled: false,
+            rom_bank: 0x001,
+            ram_bank: 0x00,
+            has_rumble: rom.header.cartridge_type.rumble,
+            rumble: false,
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        (self.ram_bank as usize * 0x2000 + addr as usize) % self.ram.len()
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn load_rom(&self, rom: &Rom, addr: u16) -> u8 {
+        if addr < 0x4000 {
+            rom.load8(addr as usize)
+        } else {
+            rom.load8(self.rom_bank as usize * 0x4000 + (addr & 0x3FFF) as usize)
+        }
+    }
+
+    fn store_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0x0FF) | ((value & 0x01) as u16) << 8,
+            0x4000..=0x5FFF if self.has_rumble => {
+                self.rumble = value & 0x08 != 0;
+                self.ram_bank = value & 0x07;
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {}
+        }
+    }
+
+    fn load_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+
+        self.ram[self.ram_offset(addr)]
+    }
+
+    fn store_ram(&mut self, addr: u16, value: u8) -> bool {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return false;
+        }
+
+        let offset = self.ram_offset(addr);
+        self.ram[offset] = value;
+        true
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_into(&mut self.ram, data);
+    }
+}
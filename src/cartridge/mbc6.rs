@@ -0,0 +1,188 @@
+use rom::Rom;
+
+use super::{load_into, Mbc};
+
+const FLASH_SIZE: usize = 0x100000;
+const FLASH_SECTOR: usize = 0x20000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FlashState {
+    Idle,
+    Unlock1,
+    Unlock2,
+    Program,
+    EraseSetup,
+    EraseUnlock1,
+    EraseUnlock2,
+}
+
+// MBC6 cuts both the switchable ROM area and the RAM area in half and
+// banks each half on its own. 0x4000-0x5FFF and 0x6000-0x7FFF show 8 KiB
+// of either ROM or the 1 MiB flash chip, 0xA000-0xAFFF and 0xB000-0xBFFF
+// show 4 KiB RAM banks.
+//
+// The flash takes JEDEC style commands behind the 0x5555/0x2AAA unlock
+// sequence. Only byte program and sector erase are handled, which is
+// what Net de Get uses.
+pub struct Mbc6 {
+    ram: Vec<u8>,
+    flash: Vec<u8>,
+    ram_enabled: bool,
+    ram_bank: [u8; 2],
+    rom_bank: [u8; 2],
+    flash_selected: [bool; 2],
+    flash_enabled: bool,
+    flash_write_enabled: bool,
+    flash_state: FlashState,
+    flash_dirty: bool,
+}
+
+impl Mbc6 {
+    pub fn new(rom: &Rom) -> Mbc6 {
+        Mbc6 {
+            ram: vec![0; rom.header.ram_size.bytes()],
+            flash: vec![0xFF; FLASH_SIZE],
+            ram_enabled: false,
+            ram_bank: [0, 0],
+            rom_bank: [0, 0],
+            flash_selected: [false, false],
+            flash_enabled: false,
+            flash_write_enabled: false,
+            flash_state: FlashState::Idle,
+            flash_dirty: false,
+        }
+    }
+
+    // Window 0 is 0x4000-0x5FFF and window 1 is 0x6000-0x7FFF.
+    fn window(addr: u16) -> usize {
+        ((addr >> 13) & 0x01) as usize
+    }
+
+    fn flash_offset(&self, addr: u16) -> usize {
+        let window = Mbc6::window(addr);
+
+        (self.rom_bank[window] as usize * 0x2000 + (addr & 0x1FFF) as usize) % FLASH_SIZE
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        let bank = self.ram_bank[((addr >> 12) & 0x01) as usize] as usize;
+
+        (bank * 0x1000 + (addr & 0x0FFF) as usize) % self.ram.len()
+    }
+
+    fn flash_command(&mut self, offset: usize, value: u8) {
+        let command = offset & 0x7FFF;
+
+        self.flash_state = match (self.flash_state, command, value) {
+            (FlashState::Idle, 0x5555, 0xAA) => FlashState::Unlock1,
+            (FlashState::Unlock1, 0x2AAA, 0x55) => FlashState::Unlock2,
+            (FlashState::Unlock2, 0x5555, 0xA0) => FlashState::Program,
+            (FlashState::Unlock2, 0x5555, 0x80) => FlashState::EraseSetup,
+            (FlashState::Program, _, _) => {
+                // Programming can only clear bits, erasing sets them again.
+                self.flash[offset] &= value;
+                self.flash_dirty = true;
+                FlashState::Idle
+            }
+            // Reset, except as the data byte of a program command.
+            (_, _, 0xF0) => FlashState::Idle,
+            (FlashState::EraseSetup, 0x5555, 0xAA) => FlashState::EraseUnlock1,
+            (FlashState::EraseUnlock1, 0x2AAA, 0x55) => FlashState::EraseUnlock2,
+            (FlashState::EraseUnlock2, _, 0x30) => {
+                let start = offset / FLASH_SECTOR * FLASH_SECTOR;
+                for byte in &mut self.flash[start..start + FLASH_SECTOR] {
+                    *byte = 0xFF;
+                }
+                self.flash_dirty = true;
+                FlashState::Idle
+            }
+            (FlashState::EraseUnlock2, 0x5555, 0x10) => {
+                for byte in &mut self.flash {
+                    *byte = 0xFF;
+                }
+                self.flash_dirty = true;
+                FlashState::Idle
+            }
+            _ => FlashState::Idle,
+        };
+    }
+}
+
+impl Mbc for Mbc6 {
+    fn load_rom(&self, rom: &Rom, addr: u16) -> u8 {
+        if addr < 0x4000 {
+            return rom.load8(addr as usize);
+        }
+
+        let window = Mbc6::window(addr);
+        if self.flash_selected[window] {
+            if !self.flash_enabled {
+                return 0xFF;
+            }
+            return self.flash[self.flash_offset(addr)];
+        }
+
+        rom.load8(self.rom_bank[window] as usize * 0x2000 + (addr & 0x1FFF) as usize)
+    }
+
+    fn store_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x03FF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x0400..=0x07FF => self.ram_bank[0] = value,
+            0x0800..=0x0BFF => self.ram_bank[1] = value,
+            0x0C00..=0x0FFF => self.flash_enabled = value & 0x01 != 0,
+            0x1000 => self.flash_write_enabled = value & 0x01 != 0,
+            0x2000..=0x27FF => self.rom_bank[0] = value,
+            0x2800..=0x2FFF => self.flash_selected[0] = value == 0x08,
+            0x3000..=0x37FF => self.rom_bank[1] = value,
+            0x3800..=0x3FFF => self.flash_selected[1] = value == 0x08,
+            0x4000..=0x7FFF => {
+                let window = Mbc6::window(addr);
+                if self.flash_selected[window] && self.flash_enabled && self.flash_write_enabled {
+                    let offset = self.flash_offset(addr);
+                    self.flash_command(offset, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn load_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+
+        self.ram[self.ram_offset(addr)]
+    }
+
+    fn store_ram(&mut self, addr: u16, value: u8) -> bool {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return false;
+        }
+
+        let offset = self.ram_offset(addr);
+        self.ram[offset] = value;
+        true
+    }
+
+    // RAM followed by the whole flash chip.
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        data.extend_from_slice(&self.flash);
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let split = self.ram.len().min(data.len());
+        load_into(&mut self.ram, &data[..split]);
+        load_into(&mut self.flash, &data[split..]);
+    }
+
+    fn dirty(&self) -> bool {
+        self.flash_dirty
+    }
+
+    fn clear_dirty(&mut self) {
+        self.flash_dirty = false;
+    }
+}
@@ -0,0 +1,257 @@
+use rom::Rom;
+
+use super::Mbc;
+
+// Resting value of each accelerometer axis and the change for 1 g.
+const ACCEL_CENTER: f32 = 0x81D0 as f32;
+const ACCEL_GRAVITY: f32 = 0x70 as f32;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum EepromState {
+    Idle,
+    Command { bits: u16, count: u8 },
+    Read { word: u16, count: u8 },
+    Write { addr: Option<u8>, word: u16, count: u8 },
+}
+
+// 93LC56 serial EEPROM in 16-bit mode, 128 words. It is clocked by the
+// game through CS, CLK and DI and answers on DO. Commands are a start bit
+// followed by a 2-bit opcode and an 8-bit address field.
+struct Eeprom {
+    data: [u16; 128],
+    cs: bool,
+    clk: bool,
+    di: bool,
+    dout: bool,
+    write_enabled: bool,
+    state: EepromState,
+}
+
+impl Eeprom {
+    fn new() -> Eeprom {
+        Eeprom {
+            data: [0xFFFF; 128],
+            cs: false,
+            clk: false,
+            di: false,
+            dout: true,
+            write_enabled: false,
+            state: EepromState::Idle,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        let mut value = if self.cs { 0x80 } else { 0 };
+        if self.clk { value |= 0x40; }
+        if self.di { value |= 0x02; }
+        if self.dout { value |= 0x01; }
+        value
+    }
+
+    // Returns whether the stored words changed.
+    fn write(&mut self, value: u8) -> bool {
+        let data = self.data;
+        let cs = value & 0x80 != 0;
+        let clk = value & 0x40 != 0;
+        self.di = value & 0x02 != 0;
+
+        if !cs {
+            self.state = EepromState::Idle;
+        } else if clk && !self.clk {
+            self.clock(self.di);
+        }
+
+        self.cs = cs;
+        self.clk = clk;
+        self.data != data
+    }
+
+    fn clock(&mut self, di: bool) {
+        let bit = di as u16;
+
+        self.state = match self.state {
+            EepromState::Idle if di => EepromState::Command { bits: 0, count: 0 },
+            EepromState::Idle => EepromState::Idle,
+            EepromState::Command { bits, count } if count < 9 => {
+                EepromState::Command { bits: bits << 1 | bit, count: count + 1 }
+            }
+            EepromState::Command { bits, .. } => self.command(bits << 1 | bit),
+            EepromState::Read { word, count } => {
+                self.dout = word & 0x8000 != 0;
+                if count == 15 {
+                    EepromState::Idle
+                } else {
+                    EepromState::Read { word: word << 1, count: count + 1 }
+                }
+            }
+            EepromState::Write { addr, word, count } if count < 15 => {
+                EepromState::Write { addr, word: word << 1 | bit, count: count + 1 }
+            }
+            EepromState::Write { addr, word, .. } => {
+                let word = word << 1 | bit;
+                if self.write_enabled {
+                    match addr {
+                        Some(addr) => self.data[addr as usize] = word,
+                        None => self.data = [word; 128],
+                    }
+                }
+                self.dout = true;
+                EepromState::Idle
+            }
+        };
+    }
+
+    fn command(&mut self, bits: u16) -> EepromState {
+        let addr = (bits & 0x7F) as u8;
+
+        match (bits >> 8) & 0x03 {
+            0b10 => {
+                // A dummy zero comes out before the data.
+                self.dout = false;
+                EepromState::Read { word: self.data[addr as usize], count: 0 }
+            }
+            0b01 => EepromState::Write { addr: Some(addr), word: 0, count: 0 },
+            0b11 => {
+                if self.write_enabled {
+                    self.data[addr as usize] = 0xFFFF;
+                }
+                self.dout = true;
+                EepromState::Idle
+            }
+            _ => match (bits >> 6) & 0x03 {
+                0b11 => {
+                    self.write_enabled = true;
+                    EepromState::Idle
+                }
+                0b00 => {
+                    self.write_enabled = false;
+                    EepromState::Idle
+                }
+                0b10 => {
+                    if self.write_enabled {
+                        self.data = [0xFFFF; 128];
+                    }
+                    self.dout = true;
+                    EepromState::Idle
+                }
+                _ => EepromState::Write { addr: None, word: 0, count: 0 },
+            },
+        }
+    }
+}
+
+// MBC7 drops external RAM for a register block at 0xA000-0xAFFF holding a
+// two-axis accelerometer and the EEPROM pins. The block only responds
+// once both 0x0000-0x1FFF (0x0A) and 0x4000-0x5FFF (0x40) are written.
+//
+// Header type 0x22 lists rumble, but no MBC7 cartridge has a motor and the
+// controller has no register known to drive one, so `rumble` is left at
+// its default of false.
+pub struct Mbc7 {
+    eeprom: Eeprom,
+    ram_enabled: bool,
+    ram_enabled2: bool,
+    rom_bank: u8,
+
+    tilt_x: u16,
+    tilt_y: u16,
+    latched_x: u16,
+    latched_y: u16,
+    latch_erased: bool,
+}
+
+impl Mbc7 {
+    pub fn new() -> Mbc7 {
+        Mbc7 {
+            eeprom: Eeprom::new(),
+            ram_enabled: false,
+            ram_enabled2: false,
+            rom_bank: 0x01,
+
+            tilt_x: ACCEL_CENTER as u16,
+            tilt_y: ACCEL_CENTER as u16,
+            latched_x: 0x8000,
+            latched_y: 0x8000,
+            latch_erased: false,
+        }
+    }
+
+    fn registers_enabled(&self) -> bool {
+        self.ram_enabled && self.ram_enabled2
+    }
+}
+
+impl Mbc for Mbc7 {
+    fn load_rom(&self, rom: &Rom, addr: u16) -> u8 {
+        if addr < 0x4000 {
+            rom.load8(addr as usize)
+        } else {
+            rom.load8(self.rom_bank as usize * 0x4000 + (addr & 0x3FFF) as usize)
+        }
+    }
+
+    fn store_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.ram_enabled2 = value == 0x40,
+            _ => {}
+        }
+    }
+
+    fn load_ram(&self, addr: u16) -> u8 {
+        if !self.registers_enabled() || addr >= 0x1000 {
+            return 0xFF;
+        }
+
+        match (addr >> 4) & 0x0F {
+            0x2 => self.latched_x as u8,
+            0x3 => (self.latched_x >> 8) as u8,
+            0x4 => self.latched_y as u8,
+            0x5 => (self.latched_y >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    fn store_ram(&mut self, addr: u16, value: u8) -> bool {
+        if !self.registers_enabled() || addr >= 0x1000 {
+            return false;
+        }
+
+        match (addr >> 4) & 0x0F {
+            0x0 if value == 0x55 => {
+                self.latched_x = 0x8000;
+                self.latched_y = 0x8000;
+                self.latch_erased = true;
+            }
+            0x1 if value == 0xAA && self.latch_erased => {
+                self.latched_x = self.tilt_x;
+                self.latched_y = self.tilt_y;
+                self.latch_erased = false;
+            }
+            0x8 => return self.eeprom.write(value),
+            _ => {}
+        }
+        false
+    }
+
+    fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.tilt_x = (ACCEL_CENTER + x * ACCEL_GRAVITY) as u16;
+        self.tilt_y = (ACCEL_CENTER + y * ACCEL_GRAVITY) as u16;
+    }
+
+    // The EEPROM words, little endian.
+    fn save_data(&self) -> Vec<u8> {
+        self.eeprom.data.iter().flat_map(|&word| vec![word as u8, (word >> 8) as u8]).collect()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        for (word, bytes) in self.eeprom.data.iter_mut().zip(data.chunks(2)) {
+            if bytes.len() == 2 {
+                *word = bytes[0] as u16 | (bytes[1] as u16) << 8;
+            }
+        }
+    }
+}
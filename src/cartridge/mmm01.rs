@@ -0,0 +1,147 @@
+use rom::Rom;
+
+use super::{load_into, Mbc};
+
+// MMM01 is a multicart controller. It powers up unmapped, showing the
+// last 32 KiB of the ROM where the menu lives. The menu sets up which
+// slice of the ROM and RAM the chosen game may use, then sets bit 6 of
+// 0x0000-0x1FFF, after which the controller behaves like an MBC1 limited
+// to that slice. Until then the upper bank bits, the bank masks and the
+// mode lock can all be written:
+//
+//   0x0000-0x1FFF  RAM enable, RAM bank mask (bits 4-5), map (bit 6)
+//   0x2000-0x3FFF  ROM bank bits 0-4, bits 5-6 while unmapped
+//   0x4000-0x5FFF  RAM bank bits 0-1, RAM bank bits 2-3, ROM bank bits
+//                  7-8 (bits 4-5) and mode lock (bit 6) while unmapped
+//   0x6000-0x7FFF  mode, ROM bank mask for bits 1-4 (bits 2-5)
+//
+// Bits covered by a mask keep the value they had when mapping.
+pub struct Mmm01 {
+    ram: Vec<u8>,
+    mapped: bool,
+    ram_enabled: bool,
+    rom_bank: u16,
+    rom_mask: u8,
+    ram_bank: u8,
+    ram_mask: u8,
+    mode: bool,
+    mode_locked: bool,
+}
+
+impl Mmm01 {
+    pub fn new(rom: &Rom) -> Mmm01 {
+        Mmm01 {
+            ram: vec![0; rom.header.ram_size.bytes()],
+            mapped: false,
+            ram_enabled: false,
+            rom_bank: 0x000,
+            rom_mask: 0x00,
+            ram_bank: 0x00,
+            ram_mask: 0x00,
+            mode: false,
+            mode_locked: false,
+        }
+    }
+
+    // Bits of the ROM bank the game can still change.
+    fn rom_writable(&self) -> u16 {
+        if self.mapped {
+            0x1F & !((self.rom_mask as u16) << 1)
+        } else {
+            0x7F
+        }
+    }
+
+    fn ram_writable(&self) -> u8 {
+        if self.mapped {
+            0x03 & !self.ram_mask
+        } else {
+            0x0F
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        let writable = self.ram_writable() & 0x03;
+        let bank = if self.mode { self.ram_bank } else { self.ram_bank & !writable };
+
+        (bank as usize * 0x2000 + addr as usize) % self.ram.len()
+    }
+}
+
+impl Mbc for Mmm01 {
+    fn load_rom(&self, rom: &Rom, addr: u16) -> u8 {
+        let offset = (addr & 0x3FFF) as usize;
+
+        let bank = if !self.mapped {
+            // The upper bank bits are forced high, which wraps around to
+            // the end of the ROM.
+            if addr < 0x4000 { 0x1FE } else { 0x1FF }
+        } else if addr < 0x4000 {
+            self.rom_bank & !self.rom_writable()
+        } else if self.rom_bank & self.rom_writable() == 0 {
+            self.rom_bank | 0x01
+        } else {
+            self.rom_bank
+        };
+
+        rom.load8(bank as usize * 0x4000 + offset)
+    }
+
+    fn store_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+                if !self.mapped {
+                    self.ram_mask = (value >> 4) & 0x03;
+                    self.mapped = value & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3FFF => {
+                let writable = self.rom_writable();
+                self.rom_bank = (self.rom_bank & !writable) | (value as u16 & writable);
+            }
+            0x4000..=0x5FFF => {
+                let writable = self.ram_writable();
+                self.ram_bank = (self.ram_bank & !writable) | (value & writable);
+                if !self.mapped {
+                    self.rom_bank = (self.rom_bank & 0x7F) | ((value as u16 >> 4) & 0x03) << 7;
+                    self.mode_locked = value & 0x40 != 0;
+                }
+            }
+            _ => {
+                if !self.mode_locked {
+                    self.mode = value & 0x01 != 0;
+                }
+                if !self.mapped {
+                    self.rom_mask = (value >> 2) & 0x0F;
+                }
+            }
+        }
+    }
+
+    fn load_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+
+        self.ram[self.ram_offset(addr)]
+    }
+
+    fn store_ram(&mut self, addr: u16, value: u8) -> bool {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return false;
+        }
+
+        let offset = self.ram_offset(addr);
+        self.ram[offset] = value;
+        true
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_into(&mut self.ram, data);
+    }
+}
@@ -0,0 +1,781 @@
+use rom::{CartridgeHeader, Controller, Rom};
+
+mod rom_only;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod mbc6;
+mod mbc7;
+mod huc1;
+mod huc3;
+mod mmm01;
+mod camera;
+mod tama5;
+mod rtc;
+
+use self::rom_only::RomOnly;
+use self::mbc1::Mbc1;
+use self::mbc2::Mbc2;
+use self::mbc3::Mbc3;
+use self::mbc5::Mbc5;
+use self::mbc6::Mbc6;
+use self::mbc7::Mbc7;
+use self::huc1::HuC1;
+use self::huc3::HuC3;
+use self::mmm01::Mmm01;
+use self::camera::PocketCamera;
+use self::tama5::Tama5;
+
+pub use self::rtc::{Clock, FakeClock, SystemClock};
+pub use self::camera::{ImageSensor, StaticImage, SENSOR_HEIGHT, SENSOR_WIDTH};
+
+// The memory bank controller decides which part of the ROM and external
+// RAM is visible through the 0x0000-0x7FFF and 0xA000-0xBFFF windows.
+// Writes to the ROM window never reach the ROM, they program the
+// controller instead.
+pub trait Mbc {
+    fn load_rom(&self, rom: &Rom, addr: u16) -> u8;
+    fn store_rom(&mut self, addr: u16, value: u8);
+
+    // `addr` is relative to 0xA000. Writes return whether they changed
+    // the RAM or EEPROM that gets saved, the rest of the battery backed
+    // state is reported by `dirty`.
+    fn load_ram(&self, addr: u16) -> u8;
+    fn store_ram(&mut self, addr: u16, value: u8) -> bool;
+
+    // Whether the controller is currently driving a rumble motor.
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    // Tilt along each axis in g, for controllers with an accelerometer.
+    fn set_accelerometer(&mut self, _x: f32, _y: f32) {}
+
+    // Whether the infrared LED is lit, for controllers with an IR port.
+    fn ir_led(&self) -> bool {
+        false
+    }
+
+    fn set_image_sensor(&mut self, _sensor: Box<dyn ImageSensor>) {}
+
+    // Battery backed state, laid out the way other emulators store it in
+    // .sav files.
+    fn save_data(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_save_data(&mut self, _data: &[u8]) {}
+
+    // Whether battery backed state other than what `store_ram` reports
+    // changed since the last `clear_dirty`: flash, clock registers, or a
+    // clock that was never saved.
+    fn dirty(&self) -> bool {
+        false
+    }
+
+    fn clear_dirty(&mut self) {}
+}
+
+// Copies as much of a save as fits. Saves from other emulators may be
+// shorter or carry extra data at the end.
+fn load_into(dst: &mut [u8], src: &[u8]) {
+    let len = dst.len().min(src.len());
+    dst[..len].copy_from_slice(&src[..len]);
+}
+
+pub struct Cartridge {
+    rom: Rom,
+    mbc: Box<dyn Mbc>,
+    dirty: bool,
+}
+
+impl Cartridge {
+    pub fn new(rom: Rom) -> Cartridge {
+        Cartridge::with_clock(rom, Box::new(SystemClock))
+    }
+
+    // Cartridges with a real-time clock read the time from `clock`.
+    pub fn with_clock(rom: Rom, clock: Box<dyn Clock>) -> Cartridge {
+        let cartridge_type = rom.header.cartridge_type;
+        let ram_size = rom.header.ram_size.bytes();
+
+        let mbc: Box<dyn Mbc> = match cartridge_type.controller {
+            Controller::RomOnly => Box::new(RomOnly::new(ram_size)),
+            Controller::Mbc1 => Box::new(Mbc1::new(&rom)),
+            Controller::Mbc2 => Box::new(Mbc2::new()),
+            Controller::Mbc3 => Box::new(Mbc3::new(&rom, clock)),
+            Controller::Mbc5 => Box::new(Mbc5::new(&rom)),
+            Controller::Mbc6 => Box::new(Mbc6::new(&rom)),
+            Controller::Mbc7 => Box::new(Mbc7::new()),
+            Controller::HuC1 => Box::new(HuC1::new(&rom)),
+            Controller::HuC3 => Box::new(HuC3::new(&rom, clock)),
+            Controller::Mmm01 => Box::new(Mmm01::new(&rom)),
+            Controller::PocketCamera => Box::new(PocketCamera::new(&rom)),
+            Controller::Tama5 => Box::new(Tama5::new(clock)),
+        };
+
+        Cartridge { rom, mbc, dirty: false }
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.rom.header
+    }
+
+    pub fn load_rom(&self, addr: u16) -> u8 {
+        self.mbc.load_rom(&self.rom, addr)
+    }
+
+    pub fn store_rom(&mut self, addr: u16, value: u8) {
+        self.mbc.store_rom(addr, value)
+    }
+
+    pub fn load_ram(&self, addr: u16) -> u8 {
+        self.mbc.load_ram(addr)
+    }
+
+    pub fn store_ram(&mut self, addr: u16, value: u8) {
+        if self.mbc.store_ram(addr, value) {
+            self.dirty = true;
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.rom.header.cartridge_type.battery
+    }
+
+    pub fn save_data(&self) -> Vec<u8> {
+        self.mbc.save_data()
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        self.mbc.load_save_data(data)
+    }
+
+    // Set by any write that reached cartridge RAM since the last save, or
+    // by the controller for the rest of its battery backed state.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty || self.mbc.dirty()
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+        self.mbc.clear_dirty();
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.mbc.rumble()
+    }
+
+    // Feeds the accelerometer of MBC7 cartridges. `x` and `y` are in g,
+    // positive to the right and towards the player. Ignored by other
+    // controllers.
+    pub fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.mbc.set_accelerometer(x, y)
+    }
+
+    pub fn ir_led(&self) -> bool {
+        self.mbc.ir_led()
+    }
+
+    // Pictures taken by the Pocket Camera come from `sensor`. Ignored by
+    // other controllers.
+    pub fn set_image_sensor(&mut self, sensor: Box<dyn ImageSensor>) {
+        self.mbc.set_image_sensor(sensor)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rom::{RomSize, NINTENDO_LOGO};
+
+    // A blank 32 KiB image with just the cartridge type and RAM size set.
+    pub fn rom(cartridge_type: u8, ram_size: u8) -> Rom {
+        let mut data = vec![0; 0x8000];
+        data[0x0147] = cartridge_type;
+        data[0x0149] = ram_size;
+        Rom::from_bytes(data).unwrap()
+    }
+
+    // An image of the size given by the header code whose 16 KiB banks
+    // each start with their own index, low byte first.
+    pub fn tagged_rom(cartridge_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+        let banks = RomSize::from_code(rom_size).unwrap().banks();
+        let mut data = vec![0; banks * 0x4000];
+        for bank in 0..banks {
+            data[bank * 0x4000] = bank as u8;
+            data[bank * 0x4000 + 1] = (bank >> 8) as u8;
+        }
+        data[0x0147] = cartridge_type;
+        data[0x0148] = rom_size;
+        data[0x0149] = ram_size;
+        data
+    }
+
+    fn tagged_cartridge(cartridge_type: u8, rom_size: u8, ram_size: u8) -> Cartridge {
+        Cartridge::new(Rom::from_bytes(tagged_rom(cartridge_type, rom_size, ram_size)).unwrap())
+    }
+
+    fn cartridge(cartridge_type: u8, ram_size: u8, clock: &FakeClock) -> Cartridge {
+        Cartridge::with_clock(rom(cartridge_type, ram_size), Box::new(clock.clone()))
+    }
+
+    #[test]
+    fn ram_write_is_dirty() {
+        let mut cartridge = cartridge(0x03, 0x02, &FakeClock::new(0));
+        assert!(!cartridge.is_dirty());
+
+        cartridge.store_rom(0x0000, 0x0A);
+        cartridge.store_ram(0x0000, 0x12);
+        assert!(cartridge.is_dirty());
+
+        cartridge.clear_dirty();
+        assert!(!cartridge.is_dirty());
+    }
+
+    #[test]
+    fn dropped_ram_write_is_clean() {
+        let mut cartridge = cartridge(0x03, 0x02, &FakeClock::new(0));
+        cartridge.store_ram(0x0000, 0x12);
+        assert!(!cartridge.is_dirty());
+
+        // RTC registers are reported by the clock, not by the write.
+        let mut cartridge = self::cartridge(0x10, 0x02, &FakeClock::new(0));
+        cartridge.clear_dirty();
+        cartridge.store_rom(0x0000, 0x0A);
+        cartridge.store_rom(0x4000, 0x08);
+        cartridge.store_ram(0x0000, 0x12);
+        assert!(cartridge.is_dirty());
+    }
+
+    // Maps flash bank 2 at 0x4000-0x5FFF and sends the byte program
+    // command. 0x5555 and 0x2AAA in flash are reached through banks 2 and 1.
+    fn start_flash_program(cartridge: &mut Cartridge) {
+        cartridge.store_rom(0x0C00, 0x01);
+        cartridge.store_rom(0x1000, 0x01);
+        cartridge.store_rom(0x2800, 0x08);
+
+        cartridge.store_rom(0x2000, 0x02);
+        cartridge.store_rom(0x5555, 0xAA);
+        cartridge.store_rom(0x2000, 0x01);
+        cartridge.store_rom(0x4AAA, 0x55);
+        cartridge.store_rom(0x2000, 0x02);
+        cartridge.store_rom(0x5555, 0xA0);
+    }
+
+    #[test]
+    fn mbc6_flash_program_is_dirty() {
+        let mut cartridge = cartridge(0x20, 0x02, &FakeClock::new(0));
+        start_flash_program(&mut cartridge);
+        assert!(!cartridge.is_dirty());
+
+        cartridge.store_rom(0x4000, 0x12);
+        assert!(cartridge.is_dirty());
+        assert_eq!(cartridge.load_rom(0x4000), 0x12);
+
+        let data = cartridge.save_data();
+        assert_eq!(data[0x2000 + 0x4000], 0x12);
+    }
+
+    #[test]
+    fn mbc6_flash_programs_reset_value() {
+        let mut cartridge = cartridge(0x20, 0x02, &FakeClock::new(0));
+        start_flash_program(&mut cartridge);
+        cartridge.store_rom(0x4000, 0xF0);
+        assert_eq!(cartridge.load_rom(0x4000), 0xF0);
+    }
+
+    #[test]
+    fn clocks_are_dirty_until_saved() {
+        for &cartridge_type in &[0x0F, 0xFD, 0xFE] {
+            let mut cartridge = cartridge(cartridge_type, 0x00, &FakeClock::new(0));
+            assert!(cartridge.is_dirty(), "{:#04x}", cartridge_type);
+
+            cartridge.clear_dirty();
+            assert!(!cartridge.is_dirty(), "{:#04x}", cartridge_type);
+        }
+    }
+
+    #[test]
+    fn mbc3_rtc_write_is_dirty() {
+        let mut cartridge = cartridge(0x0F, 0x00, &FakeClock::new(0));
+        cartridge.clear_dirty();
+
+        cartridge.store_rom(0x0000, 0x0A);
+        cartridge.store_rom(0x4000, 0x08);
+        cartridge.store_ram(0x0000, 30);
+        assert!(cartridge.is_dirty());
+    }
+
+    // Sets the HuC3 clock to 1 day and 2 minutes and reads it back from a
+    // cartridge loaded from the save.
+    #[test]
+    fn huc3_clock_round_trip() {
+        let clock = FakeClock::new(1_000_000);
+        let mut cartridge = cartridge(0xFE, 0x02, &clock);
+        cartridge.clear_dirty();
+
+        cartridge.store_rom(0x0000, 0x0B);
+        cartridge.store_ram(0x0000, 0x40);
+        cartridge.store_ram(0x0000, 0x50);
+        for &nibble in &[0x2, 0x0, 0x0, 0x1, 0x0, 0x0] {
+            cartridge.store_ram(0x0000, 0x30 | nibble);
+        }
+        cartridge.store_ram(0x0000, 0x61);
+        assert!(cartridge.is_dirty());
+
+        let data = cartridge.save_data();
+        clock.advance(60);
+
+        let mut loaded = cartridge_with_save(0xFE, 0x02, &clock, &data);
+        loaded.store_rom(0x0000, 0x0B);
+        loaded.store_ram(0x0000, 0x60);
+        loaded.store_ram(0x0000, 0x40);
+        loaded.store_ram(0x0000, 0x50);
+
+        let mut nibbles = Vec::new();
+        for _ in 0..6 {
+            loaded.store_rom(0x0000, 0x0B);
+            loaded.store_ram(0x0000, 0x10);
+            loaded.store_rom(0x0000, 0x0C);
+            nibbles.push(loaded.load_ram(0x0000) & 0x0F);
+        }
+        assert_eq!(nibbles, [0x3, 0x0, 0x0, 0x1, 0x0, 0x0]);
+    }
+
+    #[test]
+    fn tama5_clock_round_trip() {
+        let clock = FakeClock::new(1_000_000);
+        let mut cartridge = cartridge(0xFD, 0x00, &clock);
+
+        // Minutes (address 1) set to 42.
+        tama5_command(&mut cartridge, 0x42, 0x04, 0x1);
+        let data = cartridge.save_data();
+
+        let mut loaded = cartridge_with_save(0xFD, 0x00, &clock, &data);
+        tama5_command(&mut loaded, 0x00, 0x06, 0x1);
+        loaded.store_ram(0x0001, 0x0C);
+        let low = loaded.load_ram(0x0000) & 0x0F;
+        loaded.store_ram(0x0001, 0x0D);
+        let high = loaded.load_ram(0x0000) & 0x0F;
+        assert_eq!(high << 4 | low, 0x42);
+    }
+
+    fn tama5_command(cartridge: &mut Cartridge, data: u8, command: u8, address: u8) {
+        for &(register, value) in &[(0x4, data & 0x0F), (0x5, data >> 4), (0x6, command), (0x7, address)] {
+            cartridge.store_ram(0x0001, register);
+            cartridge.store_ram(0x0000, value);
+        }
+    }
+
+    fn cartridge_with_save(cartridge_type: u8, ram_size: u8, clock: &FakeClock, data: &[u8]) -> Cartridge {
+        let mut cartridge = cartridge(cartridge_type, ram_size, clock);
+        cartridge.load_save_data(data);
+        cartridge
+    }
+
+    #[test]
+    fn mbc1_bank_zero_selects_one() {
+        let mut cartridge = tagged_cartridge(0x01, 0x04, 0x00);
+        assert_eq!(cartridge.load_rom(0x4000), 0x01);
+
+        cartridge.store_rom(0x2000, 0x00);
+        assert_eq!(cartridge.load_rom(0x4000), 0x01);
+        cartridge.store_rom(0x2000, 0x13);
+        assert_eq!(cartridge.load_rom(0x4000), 0x13);
+
+        // Only five bits are written, and checked for zero.
+        cartridge.store_rom(0x3FFF, 0x20);
+        assert_eq!(cartridge.load_rom(0x4000), 0x01);
+        cartridge.store_rom(0x3FFF, 0x3F);
+        assert_eq!(cartridge.load_rom(0x4000), 0x1F);
+    }
+
+    #[test]
+    fn mbc1_bank2_extends_rom_bank() {
+        let mut cartridge = tagged_cartridge(0x01, 0x06, 0x00);
+        cartridge.store_rom(0x2000, 0x05);
+        cartridge.store_rom(0x4000, 0x02);
+        assert_eq!(cartridge.load_rom(0x4000), 0x45);
+        assert_eq!(cartridge.load_rom(0x0000), 0x00);
+
+        // The zero check ignores BANK2: 0x40 reads as 0x41.
+        cartridge.store_rom(0x2000, 0x00);
+        assert_eq!(cartridge.load_rom(0x4000), 0x41);
+
+        // Mode 1 applies BANK2 to 0x0000-0x3FFF as well.
+        cartridge.store_rom(0x6000, 0x01);
+        assert_eq!(cartridge.load_rom(0x0000), 0x40);
+        assert_eq!(cartridge.load_rom(0x4000), 0x41);
+
+        cartridge.store_rom(0x6000, 0x00);
+        assert_eq!(cartridge.load_rom(0x0000), 0x00);
+    }
+
+    #[test]
+    fn mbc1_ram_enable_and_banking() {
+        let mut cartridge = tagged_cartridge(0x03, 0x00, 0x03);
+        cartridge.store_ram(0x0000, 0x12);
+        assert_eq!(cartridge.load_ram(0x0000), 0xFF);
+
+        // Any value with 0xA in the low nibble enables RAM.
+        cartridge.store_rom(0x0000, 0x3A);
+        assert_eq!(cartridge.load_ram(0x0000), 0x00);
+        cartridge.store_ram(0x0000, 0x12);
+
+        // In mode 0 BANK2 does not reach RAM.
+        cartridge.store_rom(0x4000, 0x02);
+        assert_eq!(cartridge.load_ram(0x0000), 0x12);
+
+        cartridge.store_rom(0x6000, 0x01);
+        assert_eq!(cartridge.load_ram(0x0000), 0x00);
+        cartridge.store_ram(0x0000, 0x34);
+
+        cartridge.store_rom(0x6000, 0x00);
+        assert_eq!(cartridge.load_ram(0x0000), 0x12);
+        assert_eq!(cartridge.save_data()[2 * 0x2000], 0x34);
+
+        cartridge.store_rom(0x0000, 0x00);
+        assert_eq!(cartridge.load_ram(0x0000), 0xFF);
+    }
+
+    // A 1 MiB MBC1 image with a second header at bank 0x10 is an MBC1M
+    // multicart, where BANK2 starts at bit 4.
+    #[test]
+    fn mbc1m_shifts_bank2_by_four() {
+        let mut data = tagged_rom(0x01, 0x05, 0x00);
+        let mut plain = Cartridge::new(Rom::from_bytes(data.clone()).unwrap());
+        data[0x10 * 0x4000 + 0x0104..0x10 * 0x4000 + 0x0134].copy_from_slice(&NINTENDO_LOGO);
+        let mut multicart = Cartridge::new(Rom::from_bytes(data).unwrap());
+
+        for cartridge in [&mut plain, &mut multicart].iter_mut() {
+            cartridge.store_rom(0x4000, 0x01);
+            cartridge.store_rom(0x2000, 0x13);
+        }
+        assert_eq!(plain.load_rom(0x4000), 0x33);
+        assert_eq!(multicart.load_rom(0x4000), 0x13);
+
+        multicart.store_rom(0x6000, 0x01);
+        assert_eq!(multicart.load_rom(0x0000), 0x10);
+
+        // 0x10 passes the five-bit zero check but only its low four bits
+        // reach the ROM.
+        multicart.store_rom(0x2000, 0x10);
+        assert_eq!(multicart.load_rom(0x4000), 0x10);
+    }
+
+    fn bank_at(cartridge: &Cartridge, addr: u16) -> u16 {
+        cartridge.load_rom(addr) as u16 | (cartridge.load_rom(addr + 1) as u16) << 8
+    }
+
+    // Address bit 8 picks the register, whatever the rest of the address.
+    #[test]
+    fn mbc2_register_select() {
+        let mut cartridge = tagged_cartridge(0x06, 0x03, 0x00);
+        cartridge.store_rom(0x0100, 0x0A);
+        assert_eq!(bank_at(&cartridge, 0x4000), 0x0A);
+        assert_eq!(cartridge.load_ram(0x0000), 0xFF);
+
+        cartridge.store_rom(0x3EFF, 0x0A);
+        assert_eq!(cartridge.load_ram(0x0000), 0xF0);
+
+        cartridge.store_rom(0x3F00, 0x00);
+        assert_eq!(bank_at(&cartridge, 0x4000), 0x01);
+        cartridge.store_rom(0x2100, 0x1F);
+        assert_eq!(bank_at(&cartridge, 0x4000), 0x0F);
+
+        cartridge.store_rom(0x0000, 0x00);
+        assert_eq!(cartridge.load_ram(0x0000), 0xFF);
+    }
+
+    // 512 half-bytes, repeated through the whole window, upper nibble set.
+    #[test]
+    fn mbc2_half_byte_ram() {
+        let mut cartridge = tagged_cartridge(0x06, 0x03, 0x00);
+        cartridge.store_rom(0x0000, 0x0A);
+        cartridge.store_ram(0x0001, 0xAB);
+
+        assert_eq!(cartridge.load_ram(0x0001), 0xFB);
+        assert_eq!(cartridge.load_ram(0x0201), 0xFB);
+        assert_eq!(cartridge.load_ram(0x1E01), 0xFB);
+        assert_eq!(cartridge.save_data()[1], 0x0B);
+        assert_eq!(cartridge.save_data().len(), 0x200);
+    }
+
+    #[test]
+    fn mbc5_nine_bit_bank() {
+        let mut cartridge = tagged_cartridge(0x19, 0x08, 0x00);
+        assert_eq!(bank_at(&cartridge, 0x4000), 0x001);
+
+        cartridge.store_rom(0x2000, 0x00);
+        assert_eq!(bank_at(&cartridge, 0x4000), 0x000);
+
+        cartridge.store_rom(0x2FFF, 0x05);
+        cartridge.store_rom(0x3000, 0x01);
+        assert_eq!(bank_at(&cartridge, 0x4000), 0x105);
+
+        // Only bit 0 of the high register is used.
+        cartridge.store_rom(0x3FFF, 0xFE);
+        assert_eq!(bank_at(&cartridge, 0x4000), 0x005);
+        assert_eq!(bank_at(&cartridge, 0x0000), 0x000);
+    }
+
+    #[test]
+    fn mbc5_sixteen_ram_banks() {
+        let mut cartridge = tagged_cartridge(0x1B, 0x00, 0x04);
+        cartridge.store_rom(0x0000, 0x0A);
+        for bank in 0..16 {
+            cartridge.store_rom(0x4000, bank);
+            cartridge.store_ram(0x0000, 0x10 + bank);
+        }
+
+        cartridge.store_rom(0x4000, 0x0F);
+        assert_eq!(cartridge.load_ram(0x0000), 0x1F);
+        cartridge.store_rom(0x4000, 0x03);
+        assert_eq!(cartridge.load_ram(0x0000), 0x13);
+        assert!(!cartridge.rumble());
+    }
+
+    // Bit 3 of the RAM bank drives the motor on rumble cartridges.
+    #[test]
+    fn mbc5_rumble_bit() {
+        let mut cartridge = tagged_cartridge(0x1E, 0x00, 0x04);
+        cartridge.store_rom(0x0000, 0x0A);
+        cartridge.store_rom(0x4000, 0x01);
+        cartridge.store_ram(0x0000, 0x11);
+
+        cartridge.store_rom(0x4000, 0x09);
+        assert!(cartridge.rumble());
+        assert_eq!(cartridge.load_ram(0x0000), 0x11);
+
+        cartridge.store_rom(0x4000, 0x01);
+        assert!(!cartridge.rumble());
+    }
+
+    // Raises CS and sends `count` bits of `value` on DI, most significant
+    // first, returning what DO reads after each rising clock edge.
+    fn eeprom_send(cartridge: &mut Cartridge, value: u32, count: u32) -> u32 {
+        let mut out = 0;
+        for bit in (0..count).rev() {
+            let di = if value >> bit & 1 != 0 { 0x02 } else { 0x00 };
+            cartridge.store_ram(0x0080, 0x80 | di);
+            cartridge.store_ram(0x0080, 0xC0 | di);
+            out = out << 1 | (cartridge.load_ram(0x0080) & 0x01) as u32;
+        }
+        out
+    }
+
+    fn eeprom_deselect(cartridge: &mut Cartridge) {
+        cartridge.store_ram(0x0080, 0x00);
+    }
+
+    // Start bit, opcode and address: EWEN is 1 00 11xxxxxx, WRITE 1 01 and
+    // READ 1 10 followed by the word address.
+    const EEPROM_EWEN: u32 = 0b100_1100_0000;
+    const EEPROM_WRITE: u32 = 0b101_0000_0000;
+    const EEPROM_READ: u32 = 0b110_0000_0000;
+
+    fn mbc7() -> Cartridge {
+        let mut cartridge = tagged_cartridge(0x22, 0x05, 0x00);
+        cartridge.store_rom(0x0000, 0x0A);
+        cartridge.store_rom(0x4000, 0x40);
+        cartridge
+    }
+
+    #[test]
+    fn mbc7_eeprom_write_and_read() {
+        let mut cartridge = mbc7();
+
+        // Writes are ignored until enabled.
+        eeprom_send(&mut cartridge, EEPROM_WRITE | 5, 11);
+        eeprom_send(&mut cartridge, 0x1234, 16);
+        eeprom_deselect(&mut cartridge);
+        assert_eq!(&cartridge.save_data()[10..12], [0xFF, 0xFF]);
+
+        eeprom_send(&mut cartridge, EEPROM_EWEN, 11);
+        eeprom_deselect(&mut cartridge);
+        eeprom_send(&mut cartridge, EEPROM_WRITE | 5, 11);
+        eeprom_send(&mut cartridge, 0x1234, 16);
+        eeprom_deselect(&mut cartridge);
+        assert_eq!(&cartridge.save_data()[10..12], [0x34, 0x12]);
+
+        // A dummy zero precedes the word.
+        eeprom_send(&mut cartridge, EEPROM_READ | 5, 11);
+        assert_eq!(cartridge.load_ram(0x0080) & 0x01, 0x00);
+        assert_eq!(eeprom_send(&mut cartridge, 0, 16), 0x1234);
+        eeprom_deselect(&mut cartridge);
+    }
+
+    #[test]
+    fn mbc7_accelerometer_latch() {
+        let mut cartridge = mbc7();
+        cartridge.set_accelerometer(1.0, -0.5);
+        assert_eq!(cartridge.load_ram(0x0030), 0x80);
+
+        // 0xAA only latches after 0x55 erased the last reading.
+        cartridge.store_ram(0x0010, 0xAA);
+        assert_eq!(cartridge.load_ram(0x0020), 0x00);
+
+        cartridge.store_ram(0x0000, 0x55);
+        cartridge.store_ram(0x0010, 0xAA);
+        let x = cartridge.load_ram(0x0020) as u16 | (cartridge.load_ram(0x0030) as u16) << 8;
+        let y = cartridge.load_ram(0x0040) as u16 | (cartridge.load_ram(0x0050) as u16) << 8;
+        assert_eq!(x, 0x81D0 + 0x70);
+        assert_eq!(y, 0x81D0 - 0x38);
+        assert!(!cartridge.rumble());
+    }
+
+    #[test]
+    fn mbc7_registers_need_both_enables() {
+        let mut cartridge = tagged_cartridge(0x22, 0x05, 0x00);
+        cartridge.store_rom(0x0000, 0x0A);
+        assert_eq!(cartridge.load_ram(0x0060), 0xFF);
+
+        cartridge.store_rom(0x4000, 0x40);
+        assert_eq!(cartridge.load_ram(0x0060), 0x00);
+        assert_eq!(cartridge.load_ram(0x1000), 0xFF);
+    }
+
+    // A 128 KiB MMM01 multicart whose header sits in the menu, in the last
+    // 32 KiB, as on the real carts.
+    fn mmm01() -> Cartridge {
+        let mut data = tagged_rom(0x00, 0x02, 0x00);
+        let menu = data.len() - 0x8000;
+        data[menu + 0x0147] = 0x0D;
+        data[menu + 0x0148] = 0x02;
+        data[menu + 0x0149] = 0x03;
+        Cartridge::new(Rom::from_bytes(data).unwrap())
+    }
+
+    #[test]
+    fn mmm01_boots_into_menu() {
+        let mut cartridge = mmm01();
+        assert_eq!(cartridge.header().cartridge_type.controller, Controller::Mmm01);
+        assert_eq!(cartridge.load_rom(0x0000), 0x06);
+        assert_eq!(cartridge.load_rom(0x4000), 0x07);
+
+        // Bank writes are latched but not applied until mapping.
+        cartridge.store_rom(0x2000, 0x02);
+        assert_eq!(cartridge.load_rom(0x4000), 0x07);
+    }
+
+    #[test]
+    fn mmm01_maps_game_slice() {
+        let mut cartridge = mmm01();
+
+        // A 64 KiB game at bank 4: the mask keeps ROM bank bits 2-4.
+        cartridge.store_rom(0x2000, 0x04);
+        cartridge.store_rom(0x6000, 0x0E << 2);
+        cartridge.store_rom(0x0000, 0x40);
+        assert_eq!(cartridge.load_rom(0x0000), 0x04);
+        assert_eq!(cartridge.load_rom(0x4000), 0x05);
+
+        cartridge.store_rom(0x2000, 0x06);
+        assert_eq!(cartridge.load_rom(0x4000), 0x06);
+        cartridge.store_rom(0x2000, 0x1F);
+        assert_eq!(cartridge.load_rom(0x4000), 0x07);
+        assert_eq!(cartridge.load_rom(0x0000), 0x04);
+
+        // Once mapped, the game cannot unmap or widen its slice.
+        cartridge.store_rom(0x0000, 0x00);
+        cartridge.store_rom(0x6000, 0x00);
+        cartridge.store_rom(0x2000, 0x00);
+        assert_eq!(cartridge.load_rom(0x0000), 0x04);
+        assert_eq!(cartridge.load_rom(0x4000), 0x05);
+    }
+
+    #[test]
+    fn mmm01_ram_after_mapping() {
+        let mut cartridge = mmm01();
+        cartridge.store_rom(0x0000, 0x40);
+        cartridge.store_ram(0x0000, 0x12);
+        assert_eq!(cartridge.load_ram(0x0000), 0xFF);
+
+        cartridge.store_rom(0x0000, 0x0A);
+        cartridge.store_ram(0x0000, 0x12);
+        assert_eq!(cartridge.load_ram(0x0000), 0x12);
+
+        // As on MBC1, RAM banking needs mode 1.
+        cartridge.store_rom(0x4000, 0x01);
+        assert_eq!(cartridge.load_ram(0x0000), 0x12);
+        cartridge.store_rom(0x6000, 0x01);
+        assert_eq!(cartridge.load_ram(0x0000), 0x00);
+    }
+
+    fn camera() -> Cartridge {
+        let mut cartridge = cartridge(0xFC, 0x04, &FakeClock::new(0));
+        cartridge.store_rom(0x0000, 0x0A);
+        cartridge
+    }
+
+    #[test]
+    fn camera_register_file() {
+        let mut cartridge = camera();
+        cartridge.store_ram(0x0000, 0x12);
+        cartridge.clear_dirty();
+
+        cartridge.store_rom(0x4000, 0x10);
+        assert_eq!(cartridge.load_ram(0x0000), 0x00);
+
+        // Only register 0 reads back, and only its low three bits.
+        cartridge.store_ram(0x0000, 0xF6);
+        cartridge.store_ram(0x0001, 0x55);
+        assert_eq!(cartridge.load_ram(0x0000), 0x06);
+        assert_eq!(cartridge.load_ram(0x0001), 0x00);
+        assert_eq!(cartridge.load_ram(0x0080), 0x06);
+
+        // Register writes do not touch the RAM below.
+        assert!(!cartridge.is_dirty());
+        cartridge.store_rom(0x4000, 0x00);
+        assert_eq!(cartridge.load_ram(0x0000), 0x12);
+    }
+
+    #[test]
+    fn camera_capture_fills_ram() {
+        let mut cartridge = camera();
+        // Left half black, right half white.
+        let image = StaticImage::from_netpbm(b"P2 2 1 255 0 255").unwrap();
+        cartridge.set_image_sensor(Box::new(image));
+
+        cartridge.store_rom(0x4000, 0x10);
+        for cell in 0..16 {
+            for (i, &threshold) in [0x40, 0x80, 0xC0].iter().enumerate() {
+                cartridge.store_ram(0x0006 + cell * 3 + i as u16, threshold);
+            }
+        }
+        cartridge.clear_dirty();
+        cartridge.store_ram(0x0000, 0x01);
+        assert_eq!(cartridge.load_ram(0x0000), 0x00);
+        assert!(cartridge.is_dirty());
+
+        let data = cartridge.save_data();
+        let tile = |x: usize, y: usize| 0x0100 + (y * 16 + x) * 16;
+        assert_eq!(&data[tile(0, 0)..tile(0, 0) + 16], &[0xFF; 16]);
+        assert_eq!(&data[tile(7, 13)..tile(7, 13) + 16], &[0xFF; 16]);
+        assert_eq!(&data[tile(8, 0)..tile(8, 0) + 16], &[0x00; 16]);
+        assert_eq!(&data[tile(15, 13)..tile(15, 13) + 16], &[0x00; 16]);
+        assert_eq!(data[tile(16, 13)], 0x00);
+    }
+
+    #[test]
+    fn huc1_ir_mode_replaces_ram() {
+        let mut cartridge = tagged_cartridge(0xFF, 0x02, 0x03);
+        cartridge.store_rom(0x2000, 0x05);
+        assert_eq!(cartridge.load_rom(0x4000), 0x05);
+
+        cartridge.store_ram(0x0000, 0x12);
+        cartridge.clear_dirty();
+
+        cartridge.store_rom(0x0000, 0x0E);
+        assert_eq!(cartridge.load_ram(0x0000), 0xC0);
+        cartridge.store_ram(0x0000, 0x01);
+        assert!(cartridge.ir_led());
+        assert!(!cartridge.is_dirty());
+
+        cartridge.store_rom(0x0000, 0x00);
+        assert_eq!(cartridge.load_ram(0x0000), 0x12);
+        cartridge.store_rom(0x4000, 0x01);
+        assert_eq!(cartridge.load_ram(0x0000), 0x00);
+    }
+}
@@ -0,0 +1,51 @@
+use rom::Rom;
+
+use super::{load_into, Mbc};
+
+// 32 KiB of ROM wired straight to the bus, optionally with up to 8 KiB of
+// RAM that is always enabled.
+pub struct RomOnly {
+    ram: Vec<u8>,
+}
+
+impl RomOnly {
+    pub fn new(ram_size: usize) -> RomOnly {
+        RomOnly {
+            ram: vec![0; ram_size],
+        }
+    }
+}
+
+impl Mbc for RomOnly {
+    fn load_rom(&self, rom: &Rom, addr: u16) -> u8 {
+        rom.load8(addr as usize)
+    }
+
+    fn store_rom(&mut self, _addr: u16, _value: u8) {}
+
+    fn load_ram(&self, addr: u16) -> u8 {
+        if self.ram.is_empty() {
+            return 0xFF;
+        }
+
+        self.ram[addr as usize % self.ram.len()]
+    }
+
+    fn store_ram(&mut self, addr: u16, value: u8) -> bool {
+        if self.ram.is_empty() {
+            return false;
+        }
+
+        let len = self.ram.len();
+        self.ram[addr as usize % len] = value;
+        true
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_into(&mut self.ram, data);
+    }
+}
@@ -0,0 +1,389 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Source of wall clock time for cartridge clocks, in seconds.
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+// A clock that only moves when told to. Clones share the same time, so one
+// copy can be handed to the cartridge while the other drives it.
+#[derive(Clone, Default)]
+pub struct FakeClock {
+    now: Rc<Cell<u64>>,
+}
+
+impl FakeClock {
+    pub fn new(now: u64) -> FakeClock {
+        FakeClock {
+            now: Rc::new(Cell::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: u64) {
+        self.now.set(now);
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.now.set(self.now.get() + seconds);
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> u64 {
+        self.now.get()
+    }
+}
+
+pub const SECONDS: u8 = 0x08;
+pub const MINUTES: u8 = 0x09;
+pub const HOURS: u8 = 0x0A;
+pub const DAYS_LOW: u8 = 0x0B;
+pub const DAYS_HIGH: u8 = 0x0C;
+
+const DH_DAY_BIT8: u8 = 0x01;
+const DH_HALT: u8 = 0x40;
+const DH_CARRY: u8 = 0x80;
+
+// The MBC3 clock. The running counters are brought up to date from the
+// clock source whenever they are looked at, and the game only ever reads
+// the copy taken by the last latch.
+pub struct Rtc {
+    clock: Box<dyn Clock>,
+    last_update: u64,
+
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halt: bool,
+    carry: bool,
+
+    latched: [u8; 5],
+
+    // Set until the clock is first saved and by every register write. The
+    // counters running on their own need no saving, the footer records
+    // when they were taken.
+    dirty: bool,
+}
+
+impl Rtc {
+    pub fn new(clock: Box<dyn Clock>) -> Rtc {
+        let last_update = clock.now();
+
+        Rtc {
+            clock,
+            last_update,
+
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halt: false,
+            carry: false,
+
+            latched: [0; 5],
+
+            dirty: true,
+        }
+    }
+
+    pub fn dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn update(&mut self) {
+        let now = self.clock.now();
+        let elapsed = now.saturating_sub(self.last_update);
+        self.last_update = now;
+
+        if self.halt || elapsed == 0 {
+            return;
+        }
+
+        // Counters written out of range keep counting up to their bit
+        // width before wrapping, so step those one unit at a time.
+        let mut elapsed = elapsed;
+        while elapsed > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.tick();
+            elapsed -= 1;
+        }
+
+        let total = self.seconds as u64 + elapsed;
+        self.seconds = (total % 60) as u8;
+        let total = self.minutes as u64 + total / 60;
+        self.minutes = (total % 60) as u8;
+        let total = self.hours as u64 + total / 60;
+        self.hours = (total % 24) as u8;
+        let total = self.days as u64 + total / 24;
+        if total > 0x1FF {
+            self.carry = true;
+        }
+        self.days = (total & 0x1FF) as u16;
+    }
+
+    fn tick(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+
+        self.days += 1;
+        if self.days > 0x1FF {
+            self.days = 0;
+            self.carry = true;
+        }
+    }
+
+    fn registers(&self) -> [u8; 5] {
+        let mut day_high = (self.days >> 8) as u8 & DH_DAY_BIT8;
+        if self.halt {
+            day_high |= DH_HALT;
+        }
+        if self.carry {
+            day_high |= DH_CARRY;
+        }
+
+        [self.seconds, self.minutes, self.hours, self.days as u8, day_high]
+    }
+
+    pub fn latch(&mut self) {
+        self.update();
+
+        self.latched = self.registers();
+    }
+
+    // BGB/VBA-M footer: the running then the latched registers as 32-bit
+    // little endian words, then the 64-bit time they were taken at. The
+    // running registers are stored as of the last update, along with the
+    // time of that update, so nothing has to be brought up to date first.
+    pub fn save(&self) -> [u8; 48] {
+        let mut footer = [0; 48];
+
+        let registers = self.registers();
+        for (i, &value) in registers.iter().chain(self.latched.iter()).enumerate() {
+            footer[i * 4] = value;
+        }
+        for i in 0..8 {
+            footer[40 + i] = (self.last_update >> (i * 8)) as u8;
+        }
+
+        footer
+    }
+
+    // Also accepts the older 44 byte footer with a 32-bit timestamp.
+    pub fn load(&mut self, footer: &[u8]) {
+        if footer.len() < 44 {
+            return;
+        }
+
+        let word = |i: usize| footer[i * 4] as u32 | (footer[i * 4 + 1] as u32) << 8 |
+            (footer[i * 4 + 2] as u32) << 16 | (footer[i * 4 + 3] as u32) << 24;
+
+        self.seconds = word(0) as u8 & 0x3F;
+        self.minutes = word(1) as u8 & 0x3F;
+        self.hours = word(2) as u8 & 0x1F;
+        self.days = (word(3) & 0xFF) as u16 | ((word(4) as u8 & DH_DAY_BIT8) as u16) << 8;
+        self.halt = word(4) as u8 & DH_HALT != 0;
+        self.carry = word(4) as u8 & DH_CARRY != 0;
+
+        for i in 0..5 {
+            self.latched[i] = word(5 + i) as u8;
+        }
+
+        self.last_update = if footer.len() >= 48 {
+            word(10) as u64 | (word(11) as u64) << 32
+        } else {
+            word(10) as u64
+        };
+    }
+
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched[(register - SECONDS) as usize]
+    }
+
+    pub fn write(&mut self, register: u8, value: u8) {
+        self.update();
+        self.dirty = true;
+
+        match register {
+            SECONDS => self.seconds = value & 0x3F,
+            MINUTES => self.minutes = value & 0x3F,
+            HOURS => self.hours = value & 0x1F,
+            DAYS_LOW => self.days = (self.days & 0x100) | value as u16,
+            _ => {
+                self.days = (self.days & 0xFF) | ((value & DH_DAY_BIT8) as u16) << 8;
+                self.halt = value & DH_HALT != 0;
+                self.carry = value & DH_CARRY != 0;
+            }
+        }
+
+        // Writes show up in the latched registers straight away.
+        let index = (register - SECONDS) as usize;
+        self.latched[index] = self.registers()[index];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::Cartridge;
+    use cartridge::tests::rom;
+
+    fn new_rtc(clock: &FakeClock) -> Rtc {
+        Rtc::new(Box::new(clock.clone()))
+    }
+
+    fn set(rtc: &mut Rtc, registers: [u8; 5]) {
+        for (register, &value) in (SECONDS..=DAYS_HIGH).zip(registers.iter()) {
+            rtc.write(register, value);
+        }
+    }
+
+    fn latched(rtc: &mut Rtc) -> [u8; 5] {
+        rtc.latch();
+        let mut registers = [0; 5];
+        for (register, value) in (SECONDS..=DAYS_HIGH).zip(registers.iter_mut()) {
+            *value = rtc.read(register);
+        }
+        registers
+    }
+
+    #[test]
+    fn latch_on_zero_then_one() {
+        let clock = FakeClock::new(1000);
+        let mut cartridge = Cartridge::with_clock(rom(0x0F, 0x00), Box::new(clock.clone()));
+        cartridge.store_rom(0x0000, 0x0A);
+        cartridge.store_rom(0x4000, SECONDS);
+
+        clock.advance(5);
+        assert_eq!(cartridge.load_ram(0x0000), 0);
+
+        cartridge.store_rom(0x6000, 0x00);
+        cartridge.store_rom(0x6000, 0x01);
+        assert_eq!(cartridge.load_ram(0x0000), 5);
+
+        // The latched copy holds until the next 0x00, 0x01 sequence.
+        clock.advance(3);
+        assert_eq!(cartridge.load_ram(0x0000), 5);
+        cartridge.store_rom(0x7FFF, 0x01);
+        assert_eq!(cartridge.load_ram(0x0000), 5);
+
+        cartridge.store_rom(0x7FFF, 0x00);
+        cartridge.store_rom(0x7FFF, 0x01);
+        assert_eq!(cartridge.load_ram(0x0000), 8);
+    }
+
+    #[test]
+    fn rollover() {
+        let clock = FakeClock::new(0);
+        let mut rtc = new_rtc(&clock);
+
+        set(&mut rtc, [59, 0, 0, 0, 0]);
+        clock.advance(1);
+        assert_eq!(latched(&mut rtc), [0, 1, 0, 0, 0]);
+
+        set(&mut rtc, [59, 59, 0, 0, 0]);
+        clock.advance(1);
+        assert_eq!(latched(&mut rtc), [0, 0, 1, 0, 0]);
+
+        set(&mut rtc, [59, 59, 23, 0, 0]);
+        clock.advance(1);
+        assert_eq!(latched(&mut rtc), [0, 0, 0, 1, 0]);
+
+        // Day 255 to 256 moves into bit 0 of the high register.
+        set(&mut rtc, [59, 59, 23, 0xFF, 0]);
+        clock.advance(1);
+        assert_eq!(latched(&mut rtc), [0, 0, 0, 0x00, DH_DAY_BIT8]);
+
+        set(&mut rtc, [0, 0, 0, 0, 0]);
+        clock.advance(2 * 86400 + 3 * 3600 + 4 * 60 + 5);
+        assert_eq!(latched(&mut rtc), [5, 4, 3, 2, 0]);
+    }
+
+    #[test]
+    fn day_carry() {
+        let clock = FakeClock::new(0);
+        let mut rtc = new_rtc(&clock);
+
+        set(&mut rtc, [59, 59, 23, 0xFF, DH_DAY_BIT8]);
+        clock.advance(1);
+        assert_eq!(latched(&mut rtc), [0, 0, 0, 0, DH_CARRY]);
+
+        // The carry stays set until it is written.
+        clock.advance(86400);
+        assert_eq!(latched(&mut rtc), [0, 0, 0, 1, DH_CARRY]);
+        rtc.write(DAYS_HIGH, 0);
+        assert_eq!(latched(&mut rtc), [0, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn halt() {
+        let clock = FakeClock::new(0);
+        let mut rtc = new_rtc(&clock);
+
+        set(&mut rtc, [10, 20, 5, 7, DH_HALT]);
+        clock.advance(3600);
+        assert_eq!(latched(&mut rtc), [10, 20, 5, 7, DH_HALT]);
+
+        // Time spent halted is not caught up on.
+        rtc.write(DAYS_HIGH, 0);
+        clock.advance(1);
+        assert_eq!(latched(&mut rtc), [11, 20, 5, 7, 0]);
+    }
+
+    #[test]
+    fn footer_round_trip() {
+        let clock = FakeClock::new(1_600_000_000);
+        let mut rtc = new_rtc(&clock);
+        set(&mut rtc, [30, 15, 12, 0x34, DH_DAY_BIT8]);
+        assert_eq!(latched(&mut rtc), [30, 15, 12, 0x34, DH_DAY_BIT8]);
+
+        let footer = rtc.save();
+        assert_eq!(footer[0], 30);
+        assert_eq!(footer[12], 0x34);
+        assert_eq!(footer[20], 30);
+        assert_eq!(&footer[40..48], &1_600_000_000u64.to_le_bytes());
+
+        // The time spent switched off is caught up on after loading.
+        clock.advance(90);
+        let mut loaded = new_rtc(&clock);
+        loaded.load(&footer);
+        assert_eq!(loaded.read(SECONDS), 30);
+        assert_eq!(latched(&mut loaded), [0, 17, 12, 0x34, DH_DAY_BIT8]);
+
+        // The older footer with a 32-bit timestamp.
+        let mut loaded = new_rtc(&clock);
+        loaded.load(&footer[..44]);
+        assert_eq!(latched(&mut loaded), [0, 17, 12, 0x34, DH_DAY_BIT8]);
+    }
+}
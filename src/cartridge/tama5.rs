@@ -0,0 +1,208 @@
+use rom::Rom;
+
+use super::{load_into, Mbc};
+use super::rtc::Clock;
+
+const BANK_LOW: u8 = 0x0;
+const BANK_HIGH: u8 = 0x1;
+const DATA_LOW: u8 = 0x4;
+const DATA_HIGH: u8 = 0x5;
+const COMMAND: u8 = 0x6;
+const ADDRESS: u8 = 0x7;
+const STATUS: u8 = 0xA;
+const RESULT_LOW: u8 = 0xC;
+const RESULT_HIGH: u8 = 0xD;
+
+const RAM_WRITE: u8 = 0x0;
+const RAM_READ: u8 = 0x1;
+const RTC_WRITE: u8 = 0x2;
+const RTC_READ: u8 = 0x3;
+
+// TAMA5 only exposes two bytes in 0xA000-0xBFFF. 0xA001 selects one of
+// sixteen 4-bit registers and 0xA000 reads or writes it. Besides the ROM
+// bank, the registers form a small command interface to 32 bytes of
+// EEPROM and the TAMA6 clock: the data goes in DATA_LOW/DATA_HIGH, the
+// command and address bit 4 in COMMAND, and writing address bits 0-3 to
+// ADDRESS runs it. Reads come back in RESULT_LOW/RESULT_HIGH.
+//
+// The clock registers hold BCD seconds, minutes, hours, day of the week,
+// day, month and year at addresses 0-6.
+pub struct Tama5 {
+    clock: Box<dyn Clock>,
+    offset: i64,
+    ram: [u8; 32],
+    rom_bank: u8,
+    register: u8,
+    registers: [u8; 16],
+    result: u8,
+    // Set until the clock is first saved and whenever the time is set.
+    clock_dirty: bool,
+}
+
+impl Tama5 {
+    pub fn new(clock: Box<dyn Clock>) -> Tama5 {
+        Tama5 {
+            clock,
+            offset: 0,
+            ram: [0; 32],
+            rom_bank: 0x00,
+            register: 0x0,
+            registers: [0; 16],
+            result: 0x00,
+            clock_dirty: true,
+        }
+    }
+
+    fn now(&self) -> i64 {
+        self.clock.now() as i64 + self.offset
+    }
+
+    // Returns whether the EEPROM was written.
+    fn run(&mut self) -> bool {
+        let data = self.registers[DATA_HIGH as usize] << 4 | self.registers[DATA_LOW as usize];
+        let command = self.registers[COMMAND as usize];
+        let address = ((command & 0x01) << 4 | self.registers[ADDRESS as usize]) as usize;
+
+        match command >> 1 {
+            RAM_WRITE => {
+                self.ram[address] = data;
+                return true;
+            }
+            RAM_READ => self.result = self.ram[address],
+            RTC_WRITE if address < 7 => {
+                let mut fields = time_fields(self.now());
+                fields[address] = from_bcd(data);
+                self.offset = fields_time(&fields) - self.clock.now() as i64;
+                self.clock_dirty = true;
+            }
+            RTC_READ if address < 7 => self.result = to_bcd(time_fields(self.now())[address]),
+            _ => {}
+        }
+        false
+    }
+}
+
+fn to_bcd(value: u32) -> u8 {
+    (((value / 10 % 10) << 4) | (value % 10)) as u8
+}
+
+fn from_bcd(value: u8) -> u32 {
+    ((value >> 4) * 10 + (value & 0x0F)) as u32
+}
+
+// Seconds, minutes, hours, weekday, day, month and year of the century.
+fn time_fields(time: i64) -> [u32; 7] {
+    let days = time.div_euclid(86400);
+    let seconds = time.rem_euclid(86400) as u32;
+    let (year, month, day) = civil_from_days(days);
+
+    [
+        seconds % 60,
+        seconds / 60 % 60,
+        seconds / 3600,
+        (days + 4).rem_euclid(7) as u32,
+        day,
+        month,
+        (year - 2000).rem_euclid(100) as u32,
+    ]
+}
+
+// The weekday is derived from the date and ignored here.
+fn fields_time(fields: &[u32; 7]) -> i64 {
+    let days = days_from_civil(2000 + fields[6] as i64, fields[5].clamp(1, 12), fields[4].clamp(1, 31));
+
+    days * 86400 + (fields[2] * 3600 + fields[1] * 60 + fields[0]) as i64
+}
+
+// Days since 1970-01-01 to a proleptic Gregorian date and back, see
+// http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+impl Mbc for Tama5 {
+    fn load_rom(&self, rom: &Rom, addr: u16) -> u8 {
+        if addr < 0x4000 {
+            rom.load8(addr as usize)
+        } else {
+            rom.load8(self.rom_bank as usize * 0x4000 + (addr & 0x3FFF) as usize)
+        }
+    }
+
+    fn store_rom(&mut self, _addr: u16, _value: u8) {}
+
+    fn load_ram(&self, addr: u16) -> u8 {
+        match (addr & 0x01, self.register) {
+            (0, RESULT_LOW) => 0xF0 | (self.result & 0x0F),
+            (0, RESULT_HIGH) => 0xF0 | (self.result >> 4),
+            // Commands complete immediately.
+            (0, STATUS) => 0xF1,
+            _ => 0xFF,
+        }
+    }
+
+    fn store_ram(&mut self, addr: u16, value: u8) -> bool {
+        if addr & 0x01 != 0 {
+            self.register = value & 0x0F;
+            return false;
+        }
+
+        let value = value & 0x0F;
+        self.registers[self.register as usize] = value;
+
+        match self.register {
+            BANK_LOW => self.rom_bank = (self.rom_bank & 0x10) | value,
+            BANK_HIGH => self.rom_bank = (self.rom_bank & 0x0F) | (value & 0x01) << 4,
+            ADDRESS => return self.run(),
+            _ => {}
+        }
+        false
+    }
+
+    // The EEPROM, then how far the clock is set from the host time in
+    // seconds, 64-bit little endian.
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.to_vec();
+        data.extend_from_slice(&self.offset.to_le_bytes());
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_into(&mut self.ram, data);
+
+        if data.len() >= self.ram.len() + 8 {
+            let mut offset = [0; 8];
+            offset.copy_from_slice(&data[self.ram.len()..self.ram.len() + 8]);
+            self.offset = i64::from_le_bytes(offset);
+        }
+    }
+
+    fn dirty(&self) -> bool {
+        self.clock_dirty
+    }
+
+    fn clear_dirty(&mut self) {
+        self.clock_dirty = false;
+    }
+}
@@ -1,5 +1,8 @@
 use interconnect::Interconnect;
-use opcode::Opcode;
+use model::EmulatedModel;
+use ppu::OamCorruption;
+use opcode::{self, Alu, Condition, Instruction, Operand8, Operation, Reg16, Reg16Stack, Reg8, Shift};
+use disasm;
 
 #[derive(Clone, Copy)]
 struct Flag {
@@ -10,14 +13,22 @@ struct Flag {
 }
 
 impl Flag {
-    pub fn new() -> Flag {
+    pub fn from_bits(value: u8) -> Flag {
         Flag {
-            z: false,
-            n: false,
-            h: false,
-            c: false,
+            z: value & 0x80 != 0,
+            n: value & 0x40 != 0,
+            h: value & 0x20 != 0,
+            c: value & 0x10 != 0,
         }
     }
+
+    // The low nibble of F is hard-wired to zero.
+    pub fn bits(&self) -> u8 {
+        (if self.z { 0x80 } else { 0 }) |
+        (if self.n { 0x40 } else { 0 }) |
+        (if self.h { 0x20 } else { 0 }) |
+        (if self.c { 0x10 } else { 0 })
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -27,7 +38,6 @@ struct Register {
     c: u8,
     d: u8,
     e: u8,
-    f: u8,
     h: u8,
     l: u8,
 
@@ -38,26 +48,58 @@ struct Register {
 }
 
 impl Register {
+    // Everything cleared, as the boot ROM finds it.
     pub fn new() -> Register {
         Register {
-            a: 0x01,
+            a: 0x00,
             b: 0x00,
-            c: 0x13,
+            c: 0x00,
             d: 0x00,
-            e: 0xd8,
-            f: 0xb0,
-            h: 0x01,
-            l: 0x4d,
+            e: 0x00,
+            h: 0x00,
+            l: 0x00,
 
-            pc: 0x0100,
-            sp: 0xfffe,
+            pc: 0x0000,
+            sp: 0x0000,
 
-            flag: Flag::new(),
+            flag: Flag::from_bits(0x00),
         }
     }
 
+    // What each boot ROM leaves behind when it hands over at 0x0100. Games
+    // tell the models apart by A, and B tells an AGB from a CGB. The DMG
+    // and MGB boot ROMs leave H and C set unless the header checksum is 0.
+    pub fn post_boot(model: EmulatedModel, cgb_mode: bool, header_checksum: u8) -> Register {
+        let checksum_flags = if header_checksum == 0 { 0x80 } else { 0xb0 };
+
+        let (af, bc, de, hl) = match model {
+            EmulatedModel::Dmg0 => (0x0100, 0xff13, 0x00c1, 0x8403),
+            EmulatedModel::Dmg => (0x0100 | checksum_flags, 0x0013, 0x00d8, 0x014d),
+            EmulatedModel::Mgb => (0xff00 | checksum_flags, 0x0013, 0x00d8, 0x014d),
+            EmulatedModel::Sgb => (0x0100, 0x0014, 0x0000, 0xc060),
+            EmulatedModel::Sgb2 => (0xff00, 0x0014, 0x0000, 0xc060),
+            EmulatedModel::Cgb if cgb_mode => (0x1180, 0x0000, 0xff56, 0x000d),
+            EmulatedModel::Cgb => (0x1180, 0x0000, 0x0008, 0x007c),
+            EmulatedModel::Agb if cgb_mode => (0x1100, 0x0100, 0xff56, 0x000d),
+            EmulatedModel::Agb => (0x1100, 0x0100, 0x0008, 0x007c),
+        };
+
+        let mut register = Register::new();
+        register.set_af(af);
+        register.set_bc(bc);
+        register.set_de(de);
+        register.set_hl(hl);
+        register.pc = 0x0100;
+        register.sp = 0xfffe;
+        register
+    }
+
+    pub fn f(&self) -> u8 {
+        self.flag.bits()
+    }
+
     pub fn af(&self) -> u16 {
-        (self.a as u16) << 8 | (self.f as u16)
+        (self.a as u16) << 8 | (self.f() as u16)
     }
 
     pub fn bc(&self) -> u16 {
@@ -74,7 +116,7 @@ impl Register {
 
     pub fn set_af(&mut self, value: u16) {
         self.a = (value >> 8) as u8;
-        self.f = (value & 0x00FF) as u8;
+        self.flag = Flag::from_bits(value as u8);
     }
 
     pub fn set_bc(&mut self, value: u16) {
@@ -94,1279 +136,1315 @@ impl Register {
 }
 
 pub struct Cpu {
-    regs: [u16; 16],
-
     current_pc: u16,
 
     pub interconnect: Interconnect,
 
     register: Register,
 
-    op: Opcode,
-
     pub halted: bool,
+    pub stopped: bool,
+    locked: bool,
+    halt_bug: bool,
 
     ime: bool,
 
-    set_di: u32,
-    set_ei: u32,
+    pub trace: bool,
+
+    // Total M-cycles elapsed, advanced by every bus access and delay.
+    ticks: u64,
+
+    // Set by EI, IME is raised once the following instruction completes.
+    ei_pending: bool,
 }
 
 impl Cpu {
     pub fn new(interconnect: Interconnect) -> Cpu {
-        let pc = 0x0100;
-        Cpu {
-            regs: [0; 16],
+        let register = if interconnect.boot_rom_mapped() {
+            Register::new()
+        } else {
+            let checksum = interconnect.cartridge().header().header_checksum;
+            Register::post_boot(interconnect.model(), interconnect.cgb(), checksum)
+        };
+        let pc = register.pc;
 
+        Cpu {
             current_pc: pc,
 
             interconnect,
 
-            register: Register::new(),
-
-            op: Opcode::jp_nn,
+            register,
 
             halted: false,
+            stopped: false,
+            locked: false,
+            halt_bug: false,
+
+            ime: false,
+
+            trace: false,
 
-            ime: true,
+            ticks: 0,
 
-            set_di: 0,
-			set_ei: 0,
+            ei_pending: false,
         }
     }
 
-    pub fn update_ime(&mut self) {
-		self.set_di = match self.set_di {
-			2 => 1,
-			1 => { self.ime = false; 0 },
-			_ => 0,
-		};
-		self.set_ei = match self.set_ei {
-			2 => 1,
-			1 => { self.ime = true; 0 },
-			_ => 0,
-		};
-	}
+    // Where the next instruction will be fetched from.
+    pub fn pc(&self) -> u16 {
+        self.register.pc
+    }
 
+    // Runs one instruction, interrupt dispatch or idle step and returns the
+    // number of M-cycles it took. The rest of the system has already been
+    // advanced by the time this returns.
     pub fn cycle(&mut self) -> u32 {
-        self.update_ime();
-
-        match self.handle_interrupt() {
-            0 => {},
-            n => return n,
-        };
-        
-        if !self.halted {
-            self.run_next_instruction()        
+        let start = self.ticks;
+
+        if self.locked || self.interconnect.hdma_copying() {
+            self.tick();
+        } else if self.stopped {
+            // The system clock is halted in STOP mode, so nothing is ticked
+            // until a joypad line goes low. The M-cycle is still counted, for
+            // callers keeping time by what this returns.
+            if self.interconnect.joypad().input_low() {
+                self.stopped = false;
+            }
+            self.ticks += 1;
         } else {
-            1
+            // HALT ends as soon as an enabled interrupt is requested, even
+            // with IME=0. In that case execution simply resumes.
+            if self.halted && self.interrupt_pending() {
+                self.halted = false;
+            }
+
+            if self.halted {
+                self.tick();
+            } else if !self.handle_interrupt() {
+                let enable_ime = self.ei_pending;
+
+                self.run_next_instruction();
+
+                if enable_ime && self.ei_pending {
+                    self.ei_pending = false;
+                    self.ime = true;
+                }
+            }
         }
-    }
 
-    fn store16(&mut self, addr: u16, value: u16) {
-        self.interconnect.store8(addr, (value & 0xff) as u8);
-        self.interconnect.store8(addr + 1, (value >> 8) as u8);
+        (self.ticks - start) as u32
     }
 
-    fn load16(&mut self, addr: u16) -> u16 {
-        let lhs = self.interconnect.load8(addr) as u16;
-        let rhs = (self.interconnect.load8(addr + 1) as u16) << 8;
+    fn interrupt_pending(&self) -> bool {
+        self.interconnect.interrupts.pending().is_some()
+    }
 
-        return lhs | rhs;
+    fn tick(&mut self) {
+        self.interconnect.cycle(4);
+        self.ticks += 1;
     }
 
-    pub fn handle_interrupt(&mut self) -> u32 {
-        if self.ime == false && self.halted == false { return 0 }
-		self.halted = false;
+    fn read8(&mut self, addr: u16) -> u8 {
+        self.tick();
+        self.interconnect.corrupt_oam(addr, OamCorruption::Read);
+        self.interconnect.load8(addr)
+    }
 
-        let device = self.interconnect.interrupt_enable & self.interconnect.interrupt_flag;
-        if device == 0 { return 0 }
+    // A read while the same address goes through the increment/decrement
+    // unit, which damages OAM differently.
+    fn read8_idu(&mut self, addr: u16) -> u8 {
+        self.tick();
+        self.interconnect.corrupt_oam(addr, OamCorruption::ReadIncDec);
+        self.interconnect.load8(addr)
+    }
 
-		if self.ime == false { return 0 }
-		self.ime = false;
+    fn write8(&mut self, addr: u16, value: u8) {
+        self.tick();
+        self.interconnect.corrupt_oam(addr, OamCorruption::Write);
+        self.interconnect.store8(addr, value);
+    }
 
-        let n = device.trailing_zeros();
-        if n >= 5 { panic!("Invalid device"); }
-        
-        // The Solution by Mathijs van de Nes.
-        //////////////////////////////////////
-        self.interconnect.interrupt_flag = self.interconnect.interrupt_flag & !(1 << n);
-        
-        let pc = self.register.pc;
-        self.register.sp = self.register.sp.wrapping_sub(2);
-        
-        let sp = self.register.sp;
-        self.store16(sp, pc);
-        
-        self.register.pc = 0x0040 | ((n as u16) << 3);
-        //////////////////////////////////////
-         
-        return 4
+    fn store16(&mut self, addr: u16, value: u16) {
+        self.write8(addr, (value & 0xff) as u8);
+        self.write8(addr.wrapping_add(1), (value >> 8) as u8);
     }
 
-    pub fn power_up(&mut self) {
-        self.store16(0xFF05, 0x00);
-        self.store16(0xFF06, 0x00);
-        self.store16(0xFF07, 0x00);
-        self.store16(0xFF10, 0x80);
-        self.store16(0xFF11, 0xBF);
-        self.store16(0xFF12, 0xF3);
-        self.store16(0xFF14, 0xBF);
-        self.store16(0xFF16, 0x3F);
-        self.store16(0xFF17, 0x00);
-        self.store16(0xFF19, 0xBF);
-        self.store16(0xFF1A, 0x7F);
-        self.store16(0xFF1B, 0xFF);
-        self.store16(0xFF1C, 0x9F);
-        self.store16(0xFF1E, 0xBF);
-        self.store16(0xFF20, 0xFF);
-        self.store16(0xFF21, 0x00);
-        self.store16(0xFF22, 0x00);
-        self.store16(0xFF23, 0xBF);
-        self.store16(0xFF24, 0x77);
-        self.store16(0xFF25, 0xF3);
-        self.store16(0xFF26, 0xF1);
-        self.store16(0xFF40, 0x91);
-        self.store16(0xFF42, 0x00);
-        self.store16(0xFF43, 0x00);
-        self.store16(0xFF45, 0x00);
-        self.store16(0xFF47, 0xFC);
-        self.store16(0xFF48, 0xFF);
-        self.store16(0xFF49, 0xFF);
-        self.store16(0xFF4A, 0x00);
-        self.store16(0xFF4B, 0x00);
-    }
-
-    fn get_regs(&self, index: u16) -> u16 {
-        self.regs[index as usize]
-    }
-
-    fn set_regs(&mut self, index: u16, value: u16) {
-        self.regs[index as usize] = value;
-    }
-
-    pub fn run_next_instruction(&mut self) -> u32 {
-        let instruction = self.interconnect.load8(self.register.pc);
+    fn load16(&mut self, addr: u16) -> u16 {
+        let lhs = self.read8(addr) as u16;
+        let rhs = (self.read8(addr.wrapping_add(1)) as u16) << 8;
 
-        self.current_pc = self.register.pc;
+        lhs | rhs
+    }
 
+    fn fetch8(&mut self) -> u8 {
         let pc = self.register.pc;
-
+        let value = self.read8(pc);
         self.register.pc = pc.wrapping_add(1);
-
-        self.decode(instruction)
+        value
     }
 
-    fn run_next_callback(&mut self) -> u32 {
-        panic!("CALL BACK");
+    fn fetch16(&mut self) -> u16 {
+        let addr = self.register.pc;
+        let value = self.load16(addr);
+        self.register.pc = self.register.pc.wrapping_add(2);
+        value
     }
 
-    fn decode(&mut self, instruction: u8) -> u32 {
-        let value = instruction & 0xff;
-        let opcode = self.op.find(value);
-
-        println!("Opcode: {:#x}", value);
-
-        match opcode {
-            Opcode::nop => 1,
+    // The high byte is pushed first. Callers account for the internal
+    // delay that precedes the writes, when SP is first decremented.
+    fn push16(&mut self, value: u16) {
+        let sp = self.register.sp;
+        self.interconnect.corrupt_oam(sp, OamCorruption::Write);
 
-            Opcode::callback => self.run_next_callback(),
+        self.register.sp = self.register.sp.wrapping_sub(1);
+        let addr = self.register.sp;
+        self.write8(addr, (value >> 8) as u8);
 
-            Opcode::ld_bc_nn => {
-                let addr = self.register.pc;
-                let nn = self.load16(addr);
+        self.register.sp = self.register.sp.wrapping_sub(1);
+        let addr = self.register.sp;
+        self.write8(addr, (value & 0xff) as u8);
+    }
 
-                self.register.pc = self.register.pc.wrapping_add(2);
+    fn pop16(&mut self) -> u16 {
+        let addr = self.register.sp;
+        let lhs = self.read8_idu(addr) as u16;
+        let rhs = (self.read8_idu(addr.wrapping_add(1)) as u16) << 8;
 
-                self.register.set_bc(nn);
-                3
-            }
+        self.register.sp = self.register.sp.wrapping_add(2);
+        lhs | rhs
+    }
 
-            Opcode::ld_de_nn => {
-                let addr = self.register.pc;
-                let nn = self.load16(addr);
+    // Interrupt dispatch takes 5 M-cycles: two wait states, the two pushes
+    // of PC and the jump. The source is chosen between the pushes, so a push
+    // that overwrites IE can redirect or cancel the dispatch, in which case
+    // execution continues at 0x0000.
+    pub fn handle_interrupt(&mut self) -> bool {
+        if !self.ime || !self.interrupt_pending() { return false }
+        self.ime = false;
 
-                self.register.pc = self.register.pc.wrapping_add(2);
+        self.tick();
+        self.tick();
+        let sp = self.register.sp;
+        self.interconnect.corrupt_oam(sp, OamCorruption::Write);
+
+        // EI followed by HALT with a request already pending: the handler
+        // returns to the HALT, which is then executed again.
+        let mut pc = self.register.pc;
+        if self.halt_bug {
+            self.halt_bug = false;
+            pc = pc.wrapping_sub(1);
+        }
 
-                self.register.set_de(nn);
-                3
-            }
+        self.register.sp = self.register.sp.wrapping_sub(1);
+        let addr = self.register.sp;
+        self.write8(addr, (pc >> 8) as u8);
 
-            Opcode::ld_hl_nn => {
-                let addr = self.register.pc;
-                let nn = self.load16(addr);
+        let interrupt = self.interconnect.interrupts.pending();
 
-                self.register.pc = self.register.pc.wrapping_add(2);
+        self.register.sp = self.register.sp.wrapping_sub(1);
+        let addr = self.register.sp;
+        self.write8(addr, (pc & 0xff) as u8);
 
-                self.register.set_hl(nn);
-                3
+        self.tick();
+        self.register.pc = match interrupt {
+            Some(interrupt) => {
+                self.interconnect.interrupts.acknowledge(interrupt);
+                interrupt.vector()
             }
+            None => 0x0000,
+        };
 
-            Opcode::ld_sp_nn => {
-                let addr = self.register.pc;
-                let nn = self.load16(addr);
+        true
+    }
 
-                self.register.pc = self.register.pc.wrapping_add(2);
+    // Puts the IO registers in their post-boot state. Does nothing with a
+    // boot ROM mapped, it sets them up itself.
+    pub fn power_up(&mut self) {
+        if self.interconnect.boot_rom_mapped() {
+            return;
+        }
 
-                self.register.sp = nn;
-                3
-            }
+        self.interconnect.store8(0xFF05, 0x00);
+        self.interconnect.store8(0xFF06, 0x00);
+        self.interconnect.store8(0xFF07, 0x00);
+        self.interconnect.store8(0xFF10, 0x80);
+        self.interconnect.store8(0xFF11, 0xBF);
+        self.interconnect.store8(0xFF12, 0xF3);
+        self.interconnect.store8(0xFF14, 0xBF);
+        self.interconnect.store8(0xFF16, 0x3F);
+        self.interconnect.store8(0xFF17, 0x00);
+        self.interconnect.store8(0xFF19, 0xBF);
+        self.interconnect.store8(0xFF1A, 0x7F);
+        self.interconnect.store8(0xFF1B, 0xFF);
+        self.interconnect.store8(0xFF1C, 0x9F);
+        self.interconnect.store8(0xFF1E, 0xBF);
+        self.interconnect.store8(0xFF20, 0xFF);
+        self.interconnect.store8(0xFF21, 0x00);
+        self.interconnect.store8(0xFF22, 0x00);
+        self.interconnect.store8(0xFF23, 0xBF);
+        self.interconnect.store8(0xFF24, 0x77);
+        self.interconnect.store8(0xFF25, 0xF3);
+        self.interconnect.store8(0xFF26, 0xF1);
+        self.interconnect.store8(0xFF40, 0x91);
+        self.interconnect.store8(0xFF42, 0x00);
+        self.interconnect.store8(0xFF43, 0x00);
+        self.interconnect.store8(0xFF45, 0x00);
+        self.interconnect.store8(0xFF47, 0xFC);
+        self.interconnect.store8(0xFF48, 0xFF);
+        self.interconnect.store8(0xFF49, 0xFF);
+        self.interconnect.store8(0xFF4A, 0x00);
+        self.interconnect.store8(0xFF4B, 0x00);
+    }
 
-            Opcode::ld_a_a => 1,
-            Opcode::ld_a_b => {
-                self.register.a = self.register.b;
-                1
-            }
-            Opcode::ld_a_c => {
-                self.register.a = self.register.c;
-                1
-            }
-            Opcode::ld_a_d => {
-                self.register.a = self.register.d;
-                1
-            }
-            Opcode::ld_a_e => {
-                self.register.a = self.register.e;
-                1
-            }
-            Opcode::ld_a_h => {
-                self.register.a = self.register.h;
-                1
-            }
-            Opcode::ld_a_l => {
-                self.register.a = self.register.l;
-                1
-            }
-            Opcode::ld_a_bc => {
-                let value = self.interconnect.load8(self.register.bc());
-                self.register.a = value;
-                2
-            }
-            Opcode::ld_a_de => {
-                let value = self.interconnect.load8(self.register.de());
-                self.register.a = value;
-                2
-            }
-            Opcode::ld_a_nn => {
-                let value = self.interconnect.load16(self.register.pc);
-                self.register.a = self.interconnect.load8(value);
-                self.register.pc = self.register.pc.wrapping_add(2);
-                4
-            }
-            Opcode::ld_a_sharp => {
-                let value = self.interconnect.load8(self.register.pc);
-                self.register.a = value;
-                self.register.pc = self.register.pc.wrapping_add(1);
-                2
-            }
-            Opcode::ld_a_hl => {
-                let addr = self.register.hl();
-                self.register.a = self.interconnect.load8(addr);
-                2
-            }
-            Opcode::ld_b_b => {
-                self.register.b = self.register.b;
-                1
-            }
-            Opcode::ld_b_c => {
-                self.register.b = self.register.c;
-                1
-            }
-            Opcode::ld_b_d => {
-                self.register.b = self.register.d;
-                1
-            }
-            Opcode::ld_b_e => {
-                self.register.b = self.register.e;
-                1
-            }
-            Opcode::ld_b_h => {
-                self.register.b = self.register.h;
-                1
-            }
-            Opcode::ld_b_l => {
-                self.register.b = self.register.l;
-                1
-            }
-            Opcode::ld_b_hl => {
-                let addr = self.register.hl();
-                self.register.b = self.interconnect.load8(addr);
-                2
-            }
-            Opcode::ld_c_b => {
-                self.register.c = self.register.b;
-                1
-            }
-            Opcode::ld_c_c => {
-                self.register.c = self.register.c;
-                1
-            }
-            Opcode::ld_c_d => {
-                self.register.c = self.register.d;
-                1
-            }
-            Opcode::ld_c_e => {
-                self.register.c = self.register.e;
-                1
-            }
-            Opcode::ld_c_h => {
-                self.register.c = self.register.h;
-                1
-            }
-            Opcode::ld_c_l => {
-                self.register.c = self.register.l;
-                1
-            }
-            Opcode::ld_c_hl => {
-                let addr = self.register.hl();
-                self.register.c = self.interconnect.load8(addr);
-                2
-            }
-            Opcode::ld_d_b => {
-                self.register.d = self.register.b;
-                1
-            }
-            Opcode::ld_d_c => {
-                self.register.d = self.register.c;
-                1
-            }
-            Opcode::ld_d_d => {
-                self.register.d = self.register.d;
-                1
-            }
-            Opcode::ld_d_e => {
-                self.register.d = self.register.e;
-                1
-            }
-            Opcode::ld_d_h => {
-                self.register.d = self.register.h;
-                1
-            }
-            Opcode::ld_d_l => {
-                self.register.d = self.register.l;
-                1
-            }
-            Opcode::ld_d_hl => {
-                let addr = self.register.hl();
-                self.register.d = self.interconnect.load8(addr);
-                2
-            }
-            Opcode::ld_e_b => {
-                self.register.e = self.register.b;
-                1
-            }
-            Opcode::ld_e_c => {
-                self.register.e = self.register.c;
-                1
-            }
-            Opcode::ld_e_d => {
-                self.register.e = self.register.d;
-                1
-            }
-            Opcode::ld_e_e => {
-                self.register.e = self.register.e;
-                1
-            }
-            Opcode::ld_e_h => {
-                self.register.e = self.register.h;
-                1
-            }
-            Opcode::ld_e_l => {
-                self.register.e = self.register.l;
-                1
-            }
-            Opcode::ld_e_hl => {
-                let addr = self.register.hl();
-                self.register.e = self.interconnect.load8(addr);
-                2
-            }
-            Opcode::ld_h_b => {
-                self.register.h = self.register.b;
-                1
-            }
-            Opcode::ld_h_c => {
-                self.register.h = self.register.c;
-                1
-            }
-            Opcode::ld_h_d => {
-                self.register.h = self.register.d;
-                1
-            }
-            Opcode::ld_h_e => {
-                self.register.h = self.register.e;
-                1
-            }
-            Opcode::ld_h_h => {
-                self.register.h = self.register.h;
-                1
-            }
-            Opcode::ld_h_l => {
-                self.register.h = self.register.l;
-                1
-            }
-            Opcode::ld_h_hl => {
-                let addr = self.register.hl();
-                self.register.h = self.interconnect.load8(addr);
-                2
-            }
-            Opcode::ld_l_b => {
-                self.register.l = self.register.b;
-                1
-            }
-            Opcode::ld_l_c => {
-                self.register.l = self.register.c;
-                1
-            }
-            Opcode::ld_l_d => {
-                self.register.l = self.register.d;
-                1
-            }
-            Opcode::ld_l_e => {
-                self.register.l = self.register.e;
-                1
-            }
-            Opcode::ld_l_h => {
-                self.register.l = self.register.h;
-                1
-            }
-            Opcode::ld_l_l => {
-                self.register.l = self.register.l;
-                1
-            }
-            Opcode::ld_l_hl => {
-                let addr = self.register.hl();
-                self.register.l = self.interconnect.load8(addr);
-                2
-            }
-            Opcode::ld_hl_b => {
-                let value = self.register.b;
-                let addr = self.register.hl();
-                self.interconnect.store8(addr, value);
-                2
-            }
-            Opcode::ld_hl_c => {
-                let value = self.register.c;
-                let addr = self.register.hl();
-                self.interconnect.store8(addr, value);
-                2
-            }
-            Opcode::ld_hl_d => {
-                let value = self.register.d;
-                let addr = self.register.hl();
-                self.interconnect.store8(addr, value);
-                2
-            }
-            Opcode::ld_hl_e => {
-                let value = self.register.e;
-                let addr = self.register.hl();
-                self.interconnect.store8(addr, value);
-                2
-            }
-            Opcode::ld_hl_h => {
-                let value = self.register.h;
-                let addr = self.register.hl();
-                self.interconnect.store8(addr, value);
-                2
-            }
-            Opcode::ld_hl_l => {
-                let value = self.register.l;
-                let addr = self.register.hl();
-                self.interconnect.store8(addr, value);
-                2
-            }
-            Opcode::ld_hl_n => {
-                let value = self.interconnect.load8(self.current_pc);
-                let addr = self.register.hl();
-                self.interconnect.store8(addr, value);
-                4
-            }
+    pub fn run_next_instruction(&mut self) {
+        let start = self.ticks;
 
-            Opcode::ld_b_a => {
-                self.register.b = self.register.a;
-                2
-            }
+        self.current_pc = self.register.pc;
 
-            Opcode::ld_c_a => {
-                self.register.c = self.register.a;
-                2
-            }
+        if self.trace {
+            self.trace_instruction();
+        }
 
-            Opcode::ld_d_a => {
-                self.register.d = self.register.a;
-                2
-            }
+        // After the HALT bug the byte following HALT is fetched without
+        // incrementing PC, so it is executed twice.
+        let opcode = if self.halt_bug {
+            self.halt_bug = false;
+            let pc = self.register.pc;
+            self.read8(pc)
+        } else {
+            self.fetch8()
+        };
 
-            Opcode::ld_e_a => {
-                self.register.e = self.register.a;
-                2
-            }
+        let instruction = opcode::decode(opcode);
+        let cycles = self.execute(instruction);
 
-            Opcode::ld_h_a => {
-                self.register.h = self.register.a;
-                2
-            }
+        debug_assert_eq!(self.ticks - start, cycles as u64, "{:?}", instruction);
+    }
 
-            Opcode::ld_l_a => {
-                self.register.l = self.register.a;
-                2
-            }
+    fn run_next_callback(&mut self) -> u32 {
+        let instruction = opcode::decode_callback(self.fetch8());
+        self.execute(instruction)
+    }
 
-            Opcode::ld_bc_a => {
-                let value = self.register.a;
-                self.register.set_bc(value as u16);
-                2
-            }
+    fn trace_instruction(&self) {
+        let pc = self.current_pc;
+        let (text, _) = disasm::disassemble(pc, |addr| self.interconnect.load8(addr));
 
-            Opcode::ld_de_a => {
-                let value = self.register.a;
-                self.register.set_de(value as u16);
-                2
-            }
+        println!(
+            "{:04X}  {:<18} A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X}",
+            pc, text, self.register.a, self.register.f(), self.register.b, self.register.c,
+            self.register.d, self.register.e, self.register.h, self.register.l, self.register.sp
+        );
+    }
 
-            Opcode::ld_hl_a => {
-                let value = self.register.a;
+    fn read_reg8(&mut self, reg: Reg8) -> u8 {
+        match reg {
+            Reg8::B => self.register.b,
+            Reg8::C => self.register.c,
+            Reg8::D => self.register.d,
+            Reg8::E => self.register.e,
+            Reg8::H => self.register.h,
+            Reg8::L => self.register.l,
+            Reg8::HlInd => {
                 let addr = self.register.hl();
-                self.interconnect.store8(addr, value);
-                2
+                self.read8(addr)
             }
+            Reg8::A => self.register.a,
+        }
+    }
 
-            Opcode::ld_nn_a => {
-                let addr = self.interconnect.load16(self.current_pc);
-                let value = self.register.a;
-                self.interconnect.store8(addr, value);
-                4
-            }
-
-            Opcode::add_a_a => {
-                let res = self.register.a.wrapping_add(self.register.a);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = (self.register.a & 0xF) + (self.register.a & 0xF) > 0xF;
-                self.register.flag.c = (self.register.a as u16) + (self.register.a as u16) > 0xF;
-
-                self.register.a = res;
-                2
-            }
-
-            Opcode::add_a_b => {
-                let res = self.register.a.wrapping_add(self.register.b);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = (self.register.a & 0xF) + (self.register.b & 0xF) > 0xF;
-                self.register.flag.c = (self.register.a as u16) + (self.register.b as u16) > 0xF;
-
-                self.register.a = res;
-                2
+    fn write_reg8(&mut self, reg: Reg8, value: u8) {
+        match reg {
+            Reg8::B => self.register.b = value,
+            Reg8::C => self.register.c = value,
+            Reg8::D => self.register.d = value,
+            Reg8::E => self.register.e = value,
+            Reg8::H => self.register.h = value,
+            Reg8::L => self.register.l = value,
+            Reg8::HlInd => {
+                let addr = self.register.hl();
+                self.write8(addr, value)
             }
+            Reg8::A => self.register.a = value,
+        }
+    }
 
-            Opcode::add_a_c => {
-                let res = self.register.a.wrapping_add(self.register.c);
+    fn read_reg16(&self, reg: Reg16) -> u16 {
+        match reg {
+            Reg16::BC => self.register.bc(),
+            Reg16::DE => self.register.de(),
+            Reg16::HL => self.register.hl(),
+            Reg16::SP => self.register.sp,
+        }
+    }
 
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = (self.register.a & 0xF) + (self.register.c & 0xF) > 0xF;
-                self.register.flag.c = (self.register.a as u16) + (self.register.c as u16) > 0xF;
+    fn write_reg16(&mut self, reg: Reg16, value: u16) {
+        match reg {
+            Reg16::BC => self.register.set_bc(value),
+            Reg16::DE => self.register.set_de(value),
+            Reg16::HL => self.register.set_hl(value),
+            Reg16::SP => self.register.sp = value,
+        }
+    }
 
-                self.register.a = res;
-                2
+    // Resolves the address of a memory operand, consuming any immediate
+    // bytes and applying the HL post-increment/decrement.
+    fn operand_addr(&mut self, operand: Operand8) -> u16 {
+        match operand {
+            Operand8::Ind(reg) => self.read_reg16(reg),
+            Operand8::HlInc => {
+                let addr = self.register.hl();
+                self.register.set_hl(addr.wrapping_add(1));
+                addr
             }
-
-            Opcode::add_a_d => {
-                let res = self.register.a.wrapping_add(self.register.d);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = (self.register.a & 0xF) + (self.register.d & 0xF) > 0xF;
-                self.register.flag.c = (self.register.a as u16) + (self.register.d as u16) > 0xF;
-
-                self.register.a = res;
-                2
+            Operand8::HlDec => {
+                let addr = self.register.hl();
+                self.register.set_hl(addr.wrapping_sub(1));
+                addr
             }
+            Operand8::Abs => self.fetch16(),
+            Operand8::HighImm => 0xFF00 | self.fetch8() as u16,
+            Operand8::HighC => 0xFF00 | self.register.c as u16,
+            Operand8::Reg(_) | Operand8::Imm8 => unreachable!(),
+        }
+    }
 
-            Opcode::add_a_e => {
-                let res = self.register.a.wrapping_add(self.register.e);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = (self.register.a & 0xF) + (self.register.e & 0xF) > 0xF;
-                self.register.flag.c = (self.register.a as u16) + (self.register.e as u16) > 0xF;
-
-                self.register.a = res;
-                2
+    fn read_operand8(&mut self, operand: Operand8) -> u8 {
+        match operand {
+            Operand8::Reg(reg) => self.read_reg8(reg),
+            Operand8::Imm8 => self.fetch8(),
+            Operand8::HlInc | Operand8::HlDec => {
+                let addr = self.operand_addr(operand);
+                self.read8_idu(addr)
             }
-
-            Opcode::add_a_h => {
-                let res = self.register.a.wrapping_add(self.register.h);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = (self.register.a & 0xF) + (self.register.h & 0xF) > 0xF;
-                self.register.flag.c = (self.register.a as u16) + (self.register.h as u16) > 0xF;
-
-                self.register.a = res;
-                2
+            _ => {
+                let addr = self.operand_addr(operand);
+                self.read8(addr)
             }
+        }
+    }
 
-            Opcode::add_a_l => {
-                let res = self.register.a.wrapping_add(self.register.l);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = (self.register.a & 0xF) + (self.register.l & 0xF) > 0xF;
-                self.register.flag.c = (self.register.a as u16) + (self.register.l as u16) > 0xF;
-
-                self.register.a = res;
-                2
+    fn write_operand8(&mut self, operand: Operand8, value: u8) {
+        match operand {
+            Operand8::Reg(reg) => self.write_reg8(reg, value),
+            _ => {
+                let addr = self.operand_addr(operand);
+                self.write8(addr, value)
             }
+        }
+    }
 
-            Opcode::add_a_hl => {
-                let value = self.interconnect.load8(self.register.hl());
-                let res = self.register.a.wrapping_add(value as u8);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = (self.register.a & 0xF) + (value as u8 & 0xF) > 0xF;
-                self.register.flag.c = (self.register.a as u16) + (value as u16) > 0xF;
+    fn condition(&self, condition: Option<Condition>) -> bool {
+        match condition {
+            None => true,
+            Some(Condition::NZ) => !self.register.flag.z,
+            Some(Condition::Z) => self.register.flag.z,
+            Some(Condition::NC) => !self.register.flag.c,
+            Some(Condition::C) => self.register.flag.c,
+        }
+    }
 
-                self.register.a = res;
-                4
-            }
+    fn alu_add(&mut self, value: u8, use_carry: bool) {
+        let carry = if use_carry && self.register.flag.c { 1 } else { 0 };
+        let a = self.register.a;
+        let res = a.wrapping_add(value).wrapping_add(carry);
 
-            Opcode::push_af => {
-                self.register.sp -= 2;
+        self.register.flag.z = res == 0;
+        self.register.flag.n = false;
+        self.register.flag.h = (a & 0xF) + (value & 0xF) + carry > 0xF;
+        self.register.flag.c = (a as u16) + (value as u16) + (carry as u16) > 0xFF;
 
-                let value = self.register.af();
+        self.register.a = res;
+    }
 
-                let addr = self.register.sp;
-                self.store16(addr, value);
-                4
-            }
+    fn alu_sub(&mut self, value: u8, use_carry: bool) {
+        let carry = if use_carry && self.register.flag.c { 1 } else { 0 };
+        let a = self.register.a;
+        let res = a.wrapping_sub(value).wrapping_sub(carry);
 
-            Opcode::push_bc => {
-                self.register.sp -= 2;
+        self.register.flag.z = res == 0;
+        self.register.flag.n = true;
+        self.register.flag.h = (a & 0xF) < (value & 0xF) + carry;
+        self.register.flag.c = (a as u16) < (value as u16) + (carry as u16);
 
-                let value = self.register.bc();
+        self.register.a = res;
+    }
 
-                let addr = self.register.sp;
-                self.store16(addr, value);
-                4
-            }
+    fn alu_and(&mut self, value: u8) {
+        let res = self.register.a & value;
 
-            Opcode::push_de => {
-                self.register.sp -= 2;
+        self.register.flag.z = res == 0;
+        self.register.flag.n = false;
+        self.register.flag.h = true;
+        self.register.flag.c = false;
 
-                let value = self.register.de();
+        self.register.a = res;
+    }
 
-                let addr = self.register.sp;
-                self.store16(addr, value);
-                4
-            }
+    fn alu_xor(&mut self, value: u8) {
+        let res = self.register.a ^ value;
 
-            Opcode::push_hl => {
-                self.register.sp -= 2;
+        self.register.flag.z = res == 0;
+        self.register.flag.n = false;
+        self.register.flag.h = false;
+        self.register.flag.c = false;
 
-                let value = self.register.hl();
+        self.register.a = res;
+    }
 
-                let addr = self.register.sp;
-                self.store16(addr, value);
-                4
-            }
+    fn alu_or(&mut self, value: u8) {
+        let res = self.register.a | value;
 
-            Opcode::call_nn => {
-                self.register.sp -= 2;
+        self.register.flag.z = res == 0;
+        self.register.flag.n = false;
+        self.register.flag.h = false;
+        self.register.flag.c = false;
 
-                let value = self.register.pc;
+        self.register.a = res;
+    }
 
-                let mut addr = self.register.sp;
-                self.store16(addr, value);
+    fn alu_cp(&mut self, value: u8) {
+        let a = self.register.a;
+        self.alu_sub(value, false);
+        self.register.a = a;
+    }
 
-                addr = self.register.pc;
-                let nn = self.load16(addr);
+    fn inc8(&mut self, value: u8) -> u8 {
+        let res = value.wrapping_add(1);
 
-                self.register.pc = nn;
+        self.register.flag.z = res == 0;
+        self.register.flag.n = false;
+        self.register.flag.h = (value & 0xF) == 0xF;
 
-                2
-            }
+        res
+    }
 
-            Opcode::di => {
-                self.set_di = 2;
-                2
-            }
+    fn dec8(&mut self, value: u8) -> u8 {
+        let res = value.wrapping_sub(1);
 
-            Opcode::ei => {
-                self.set_ei = 2;
-                2
-            }
+        self.register.flag.z = res == 0;
+        self.register.flag.n = true;
+        self.register.flag.h = (value & 0xF) == 0;
 
-            Opcode::xor_a_a => {
-                let res = self.register.a ^ self.register.a;
+        res
+    }
 
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = false;
-                self.register.flag.c = false;
+    fn add_hl(&mut self, value: u16) {
+        let hl = self.register.hl();
+        let res = hl.wrapping_add(value);
 
-                self.register.a = res;
-                1
-            }
+        self.register.flag.n = false;
+        self.register.flag.h = (hl & 0x0FFF) + (value & 0x0FFF) > 0x0FFF;
+        self.register.flag.c = (hl as u32) + (value as u32) > 0xFFFF;
 
-            Opcode::xor_a_b => {
-                let res = self.register.a ^ self.register.b;
+        self.register.set_hl(res);
+    }
 
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = false;
-                self.register.flag.c = false;
+    // SP + signed immediate, shared by ADD SP,e and LD HL,SP+e.
+    // H and C come from the unsigned addition of the low byte.
+    fn add_sp_signed(&mut self) -> u16 {
+        let value = self.fetch8();
+        let sp = self.register.sp;
 
-                self.register.a = res;
-                1
-            }
+        self.register.flag.z = false;
+        self.register.flag.n = false;
+        self.register.flag.h = (sp & 0x000F) + (value as u16 & 0x000F) > 0x000F;
+        self.register.flag.c = (sp & 0x00FF) + (value as u16) > 0x00FF;
 
-            Opcode::xor_a_c => {
-                let res = self.register.a ^ self.register.c;
+        sp.wrapping_add(value as i8 as u16)
+    }
 
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = false;
-                self.register.flag.c = false;
+    fn daa(&mut self) {
+        let mut a = self.register.a;
+        let mut carry = self.register.flag.c;
 
-                self.register.a = res;
-                1
+        if !self.register.flag.n {
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
             }
-
-            Opcode::xor_a_d => {
-                let res = self.register.a ^ self.register.d;
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = false;
-                self.register.flag.c = false;
-
-                self.register.a = res;
-                1
+            if self.register.flag.h || (a & 0x0F) > 0x09 {
+                a = a.wrapping_add(0x06);
             }
-
-            Opcode::xor_a_e => {
-                let res = self.register.a ^ self.register.e;
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = false;
-                self.register.flag.c = false;
-
-                self.register.a = res;
-                1
+        } else {
+            if carry {
+                a = a.wrapping_sub(0x60);
             }
-
-            Opcode::xor_a_h => {
-                let res = self.register.a ^ self.register.h;
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = false;
-                self.register.flag.c = false;
-
-                self.register.a = res;
-                1
+            if self.register.flag.h {
+                a = a.wrapping_sub(0x06);
             }
+        }
 
-            Opcode::xor_a_l => {
-                let res = self.register.a ^ self.register.l;
+        self.register.flag.z = a == 0;
+        self.register.flag.h = false;
+        self.register.flag.c = carry;
 
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = false;
-                self.register.flag.c = false;
+        self.register.a = a;
+    }
 
-                self.register.a = res;
-                1
-            }
+    fn alu(&mut self, op: Alu, value: u8) {
+        match op {
+            Alu::Add => self.alu_add(value, false),
+            Alu::Adc => self.alu_add(value, true),
+            Alu::Sub => self.alu_sub(value, false),
+            Alu::Sbc => self.alu_sub(value, true),
+            Alu::And => self.alu_and(value),
+            Alu::Xor => self.alu_xor(value),
+            Alu::Or => self.alu_or(value),
+            Alu::Cp => self.alu_cp(value),
+        }
+    }
 
-            Opcode::xor_a_hl => {
-                let value = self.interconnect.load8(self.register.hl());
-                let res = self.register.a ^ value;
+    fn shift(&mut self, op: Shift, value: u8) -> u8 {
+        let (res, carry) = match op {
+            Shift::Rlc => (value.rotate_left(1), value & 0x80 != 0),
+            Shift::Rrc => (value.rotate_right(1), value & 0x01 != 0),
+            Shift::Rl => {
+                let carry = if self.register.flag.c { 0x01 } else { 0 };
+                ((value << 1) | carry, value & 0x80 != 0)
+            }
+            Shift::Rr => {
+                let carry = if self.register.flag.c { 0x80 } else { 0 };
+                ((value >> 1) | carry, value & 0x01 != 0)
+            }
+            Shift::Sla => (value << 1, value & 0x80 != 0),
+            Shift::Sra => ((value >> 1) | (value & 0x80), value & 0x01 != 0),
+            Shift::Swap => (value.rotate_left(4), false),
+            Shift::Srl => (value >> 1, value & 0x01 != 0),
+        };
 
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = false;
-                self.register.flag.c = false;
+        self.register.flag.z = res == 0;
+        self.register.flag.n = false;
+        self.register.flag.h = false;
+        self.register.flag.c = carry;
 
-                self.register.a = res;
-                4
-            }
+        res
+    }
 
-            Opcode::xor_a_asterisk => {
-                let value = self.interconnect.load8(self.register.pc);
-                let res = self.register.a ^ value;
+    // The accumulator rotates never set Z, unlike their CB counterparts.
+    fn shift_a(&mut self, op: Shift) {
+        let a = self.register.a;
+        self.register.a = self.shift(op, a);
+        self.register.flag.z = false;
+    }
 
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = false;
-                self.register.flag.c = false;
+    fn execute(&mut self, instruction: Instruction) -> u32 {
+        match instruction.operation {
+            Operation::Nop => {}
 
-                self.register.a = res;
+            Operation::Prefix => return self.run_next_callback(),
 
+            Operation::Stop => {
+                // STOP is followed by a padding byte that is skipped.
                 self.register.pc = self.register.pc.wrapping_add(1);
 
-                4
-            }
-
-            Opcode::ret_nz => {
-                if !self.register.flag.z {
-                    self.register.pc = self.interconnect.load16(self.register.sp);
-                    self.register.sp = self.register.sp.wrapping_add(2);
-
-                    return 5
+                // On CGB an armed KEY1 turns STOP into a speed switch
+                // instead of entering low-power mode.
+                if !self.interconnect.switch_speed() {
+                    self.stopped = true;
                 }
 
-                2
+                self.interconnect.store8(0xFF04, 0x00);
             }
 
-            Opcode::ret_z => {
-                if self.register.flag.z {
-                    self.register.pc = self.interconnect.load16(self.register.sp);
-                    self.register.sp = self.register.sp.wrapping_add(2);
-                
-                    return 5
+            Operation::Halt => {
+                if !self.ime && self.interrupt_pending() {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
                 }
-
-                2
             }
 
-            Opcode::ret_nc => {
-                if !self.register.flag.c {
-                    self.register.pc = self.interconnect.load16(self.register.sp);
-                    self.register.sp = self.register.sp.wrapping_add(2);
-                
-                    return 5
-                }
-
-                2
+            Operation::Di => {
+                self.ime = false;
+                self.ei_pending = false;
             }
 
-            Opcode::ret_c => {
-                if self.register.flag.c {
-                    self.register.pc = self.interconnect.load16(self.register.sp);
-                    self.register.sp = self.register.sp.wrapping_add(2);
-                
-                    return 5
-                }
+            Operation::Ei => self.ei_pending = !self.ime,
 
-                2
-            }
+            Operation::Illegal => self.locked = true,
 
-            Opcode::ldh_n_a => {
-                let addr = 0xFF00 | self.interconnect.load8(self.register.pc) as u16;
-                self.interconnect.store8(addr, self.register.a);
-            
-                3
+            Operation::Ld8(dst, src) => {
+                let value = self.read_operand8(src);
+                self.write_operand8(dst, value);
             }
 
-            Opcode::ldh_a_n => {
-                let addr = 0xFF00 | self.interconnect.load8(self.register.pc) as u16;
-                self.register.a = self.interconnect.load8(addr);
-
-                self.register.pc = self.register.pc.wrapping_add(1);
-            
-                3
+            Operation::Ld16(reg) => {
+                let nn = self.fetch16();
+                self.write_reg16(reg, nn);
             }
 
-            Opcode::add_a_sharp => {
-                let value = self.interconnect.load8(self.current_pc);
-                let res = self.register.a.wrapping_add(value);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = false;
-                self.register.flag.h = (self.register.a & 0xF) + (value & 0xF) > 0xF;
-                self.register.flag.c = (self.register.a as u16) + (value as u16) > 0xF;
-
-                self.register.a = res;
-                4
+            Operation::LdAbsSp => {
+                let addr = self.fetch16();
+                let value = self.register.sp;
+                self.store16(addr, value);
             }
 
-            Opcode::cp_a_a => {
-                let res = self.register.a.wrapping_sub(self.register.a);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (self.register.a & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (self.register.a as u16);
-
-                self.register.a = self.register.a;
-                1
+            Operation::LdSpHl => {
+                self.tick();
+                self.register.sp = self.register.hl();
             }
 
-            Opcode::cp_a_b => {
-                let res = self.register.a.wrapping_sub(self.register.b);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (self.register.b & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (self.register.b as u16);
-
-                self.register.a = self.register.a;
-                1
+            Operation::LdHlSpImm => {
+                let value = self.add_sp_signed();
+                self.tick();
+                self.register.set_hl(value);
             }
 
-            Opcode::cp_a_c => {
-                let res = self.register.a.wrapping_sub(self.register.c);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (self.register.c & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (self.register.c as u16);
-
-                self.register.a = self.register.a;
-                1
+            Operation::Inc8(reg) => {
+                let value = self.read_reg8(reg);
+                let res = self.inc8(value);
+                self.write_reg8(reg, res);
             }
 
-            Opcode::cp_a_d => {
-                let res = self.register.a.wrapping_sub(self.register.d);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (self.register.d & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (self.register.d as u16);
-
-                self.register.a = self.register.a;
-                1
+            Operation::Dec8(reg) => {
+                let value = self.read_reg8(reg);
+                let res = self.dec8(value);
+                self.write_reg8(reg, res);
             }
 
-            Opcode::cp_a_e => {
-                let res = self.register.a.wrapping_sub(self.register.e);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (self.register.e & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (self.register.e as u16);
-
-                self.register.a = self.register.a;
-                1
+            Operation::Inc16(reg) => {
+                self.tick();
+                let value = self.read_reg16(reg);
+                self.interconnect.corrupt_oam(value, OamCorruption::Write);
+                self.write_reg16(reg, value.wrapping_add(1));
             }
 
-            Opcode::cp_a_h => {
-                let res = self.register.a.wrapping_sub(self.register.h);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (self.register.h & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (self.register.h as u16);
-
-                self.register.a = self.register.a;
-                1
+            Operation::Dec16(reg) => {
+                self.tick();
+                let value = self.read_reg16(reg);
+                self.interconnect.corrupt_oam(value, OamCorruption::Write);
+                self.write_reg16(reg, value.wrapping_sub(1));
             }
 
-            Opcode::cp_a_l => {
-                let res = self.register.a.wrapping_sub(self.register.l);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (self.register.l & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (self.register.l as u16);
+            Operation::AddHl(reg) => {
+                self.tick();
+                let value = self.read_reg16(reg);
+                self.add_hl(value);
+            }
 
-                self.register.a = self.register.a;
-                1
+            Operation::AddSpImm => {
+                let value = self.add_sp_signed();
+                self.tick();
+                self.tick();
+                self.register.sp = value;
             }
 
-            Opcode::cp_a_hl => {
-                let value = self.interconnect.load8(self.register.hl());
-                let res = self.register.a.wrapping_sub(value as u8);
+            Operation::Alu(op, src) => {
+                let value = self.read_operand8(src);
+                self.alu(op, value);
+            }
 
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (value as u8 & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (value as u16);
+            Operation::Rlca => self.shift_a(Shift::Rlc),
+            Operation::Rrca => self.shift_a(Shift::Rrc),
+            Operation::Rla => self.shift_a(Shift::Rl),
+            Operation::Rra => self.shift_a(Shift::Rr),
 
-                self.register.a = self.register.a;
-                4
-            }
+            Operation::Daa => self.daa(),
 
-            Opcode::cp_a_sharp => {
-                let value = self.interconnect.load8(self.current_pc);
-                let res = self.register.a.wrapping_sub(value);
+            Operation::Cpl => {
+                self.register.a = !self.register.a;
 
-                self.register.flag.z = res == 0;
                 self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (value & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (value as u16);
-
-                self.register.a = self.register.a;
-                4
+                self.register.flag.h = true;
             }
 
-            Opcode::jr_nz_n => {
-                if !self.register.flag.z {
-                    let n = self.interconnect.load8(self.register.pc);
-                    
-                    self.register.pc = self.register.pc.wrapping_add(1);
-                    self.register.pc = ((self.register.pc as i32).wrapping_add(n as i32)) as u16;
-                
-                    3
-                } else {
-                    self.register.pc = self.register.pc.wrapping_add(1);
-                    2
-                }
+            Operation::Scf => {
+                self.register.flag.n = false;
+                self.register.flag.h = false;
+                self.register.flag.c = true;
             }
 
-            Opcode::jr_z_n => {
-                if self.register.flag.z {
-                    let n = self.interconnect.load8(self.register.pc);
-                    
-                    self.register.pc = self.register.pc.wrapping_add(1);
-                    self.register.pc = ((self.register.pc as i32).wrapping_add(n as i32)) as u16;
-                
-                    3
-                } else {
-                    self.register.pc = self.register.pc.wrapping_add(1);
-                
-                    2
-                }
+            Operation::Ccf => {
+                self.register.flag.n = false;
+                self.register.flag.h = false;
+                self.register.flag.c = !self.register.flag.c;
             }
 
-            Opcode::jr_nc_n => {
-                if !self.register.flag.c {
-                    let n = self.interconnect.load8(self.register.pc);
-                    
-                    self.register.pc = self.register.pc.wrapping_add(1);
-                    self.register.pc = ((self.register.pc as i32).wrapping_add(n as i32)) as u16;
-                
-                    3
-                } else {
-                    self.register.pc = self.register.pc.wrapping_add(1);
-                    2
-                }
-            }
+            Operation::Jr(condition) => {
+                let n = self.fetch8() as i8;
 
-            Opcode::jr_c_n => {
-                if self.register.flag.c {
-                    let n = self.interconnect.load8(self.register.pc);
-                    
-                    self.register.pc = self.register.pc.wrapping_add(1);
-                    self.register.pc = ((self.register.pc as i32).wrapping_add(n as i32)) as u16;
-                    3
-                } else {
-                    self.register.pc = self.register.pc.wrapping_add(1);
-                    2
+                if self.condition(condition) {
+                    self.tick();
+                    self.register.pc = self.register.pc.wrapping_add(n as u16);
+                    return instruction.branch_cycles as u32;
                 }
             }
 
-            Opcode::ret => {
-                let res = self.interconnect.load16(self.register.sp);
-                self.register.sp = self.register.sp.wrapping_add(2);
+            Operation::Jp(condition) => {
+                let nn = self.fetch16();
 
-                self.register.pc = res;
-                4
+                if self.condition(condition) {
+                    self.tick();
+                    self.register.pc = nn;
+                    return instruction.branch_cycles as u32;
+                }
             }
 
-            Opcode::sub_a_a => {
-                let res = self.register.a.wrapping_sub(self.register.a);
+            Operation::JpHl => self.register.pc = self.register.hl(),
 
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (self.register.a & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (self.register.a as u16);
+            Operation::Call(condition) => {
+                let nn = self.fetch16();
 
-                self.register.a = res;
-                2
+                if self.condition(condition) {
+                    self.tick();
+                    let pc = self.register.pc;
+                    self.push16(pc);
+                    self.register.pc = nn;
+                    return instruction.branch_cycles as u32;
+                }
             }
 
-            Opcode::sub_a_b => {
-                let res = self.register.a.wrapping_sub(self.register.b);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (self.register.b & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (self.register.b as u16);
-
-                self.register.a = res;
-                2
+            Operation::Ret(None) => {
+                self.register.pc = self.pop16();
+                self.tick();
             }
 
-            Opcode::sub_a_c => {
-                let res = self.register.a.wrapping_sub(self.register.c);
+            Operation::Ret(condition) => {
+                self.tick();
 
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (self.register.c & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (self.register.c as u16);
-
-                self.register.a = res;
-                2
+                if self.condition(condition) {
+                    self.register.pc = self.pop16();
+                    self.tick();
+                    return instruction.branch_cycles as u32;
+                }
             }
 
-            Opcode::sub_a_d => {
-                let res = self.register.a.wrapping_sub(self.register.d);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (self.register.d & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (self.register.d as u16);
-
-                self.register.a = res;
-                2
+            Operation::Reti => {
+                self.register.pc = self.pop16();
+                self.tick();
+                self.ime = true;
             }
 
-            Opcode::sub_a_e => {
-                let res = self.register.a.wrapping_sub(self.register.e);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (self.register.e & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (self.register.e as u16);
-
-                self.register.a = res;
-                2
+            Operation::Rst(addr) => {
+                self.tick();
+                let pc = self.register.pc;
+                self.push16(pc);
+                self.register.pc = addr as u16;
             }
 
-            Opcode::sub_a_h => {
-                let res = self.register.a.wrapping_sub(self.register.h);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (self.register.h & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (self.register.h as u16);
-
-                self.register.a = res;
-                2
+            Operation::Push(reg) => {
+                let value = match reg {
+                    Reg16Stack::BC => self.register.bc(),
+                    Reg16Stack::DE => self.register.de(),
+                    Reg16Stack::HL => self.register.hl(),
+                    Reg16Stack::AF => self.register.af(),
+                };
+                self.tick();
+                self.push16(value);
             }
 
-            Opcode::sub_a_l => {
-                let res = self.register.a.wrapping_sub(self.register.l);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (self.register.l & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (self.register.l as u16);
-
-                self.register.a = res;
-                2
+            Operation::Pop(reg) => {
+                let value = self.pop16();
+                match reg {
+                    Reg16Stack::BC => self.register.set_bc(value),
+                    Reg16Stack::DE => self.register.set_de(value),
+                    Reg16Stack::HL => self.register.set_hl(value),
+                    Reg16Stack::AF => self.register.set_af(value),
+                }
             }
 
-            Opcode::sub_a_hl => {
-                let value = self.interconnect.load8(self.register.hl());
-                let res = self.register.a.wrapping_sub(value as u8);
+            Operation::Shift(op, reg) => {
+                let value = self.read_reg8(reg);
+                let res = self.shift(op, value);
+                self.write_reg8(reg, res);
+            }
 
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (value as u8 & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (value as u16);
+            Operation::Bit(bit, reg) => {
+                let value = self.read_reg8(reg);
 
-                self.register.a = res;
-                2
+                self.register.flag.z = value & (1 << bit) == 0;
+                self.register.flag.n = false;
+                self.register.flag.h = true;
             }
 
-            Opcode::sub_a_sharp => {
-                let value = self.interconnect.load8(self.current_pc);
-                let res = self.register.a.wrapping_sub(value);
-
-                self.register.flag.z = res == 0;
-                self.register.flag.n = true;
-                self.register.flag.h = (self.register.a & 0x0F) < (value & 0x0F);
-                self.register.flag.c = (self.register.a as u16) < (value as u16);
+            Operation::Res(bit, reg) => {
+                let value = self.read_reg8(reg);
+                self.write_reg8(reg, value & !(1 << bit));
+            }
 
-                self.register.a = res;
-                2
+            Operation::Set(bit, reg) => {
+                let value = self.read_reg8(reg);
+                self.write_reg8(reg, value | (1 << bit));
             }
+        }
 
-            Opcode::jp_nn => {
-                let addr = self.register.pc;
-                let nn = self.load16(addr);
+        instruction.cycles as u32
+    }
+}
 
-                self.register.pc = nn;
-                2
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::Cartridge;
+    use interrupt::Interrupt;
+    use joypad::Button;
+    use rom::Rom;
 
-            Opcode::rst_00 => {
-                let value = self.current_pc;
+    fn cpu(program: &[u8]) -> Cpu {
+        cpu_with_cgb(program, false)
+    }
 
-                let addr = self.register.sp;
-                self.store16(addr, value);
+    fn cpu_with_cgb(program: &[u8], cgb: bool) -> Cpu {
+        cpu_from(image(program), cgb)
+    }
 
-                self.register.pc = 0x00;
-                4
-            }
+    // A 32 KiB ROM with `program` at the entry point.
+    fn image(program: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; 0x8000];
+        data[0x0100..0x0100 + program.len()].copy_from_slice(program);
+        data
+    }
 
-            Opcode::rst_08 => {
-                let value = self.current_pc;
+    fn cpu_from(data: Vec<u8>, cgb: bool) -> Cpu {
+        let cartridge = Cartridge::new(Rom::from_bytes(data).unwrap());
+        Cpu::new(Interconnect::with_cgb(cartridge, cgb))
+    }
 
-                let addr = self.register.sp;
-                self.store16(addr, value);
+    fn run(cpu: &mut Cpu, instructions: usize) -> Vec<u32> {
+        (0..instructions).map(|_| cpu.cycle()).collect()
+    }
 
-                self.register.pc = 0x08;
-                4
-            }
+    // Stops the timer, clears TIMA and DIV, then starts TIMA counting
+    // every 4 M-cycles.
+    const TIMER_SETUP: [u8; 14] = [
+        0x21, 0x05, 0xFF, // LD HL,FF05
+        0xAF,             // XOR A
+        0xE0, 0x07,       // LDH (07),A
+        0xE0, 0x05,       // LDH (05),A
+        0xE0, 0x04,       // LDH (04),A
+        0x3E, 0x05,       // LD A,05
+        0xE0, 0x07,       // LDH (07),A
+    ];
+
+    // The counter is at 5 M-cycles past the DIV write when the timer is
+    // enabled, so TIMA first increments 3 M-cycles later. Each read sees
+    // the timer as of the M-cycle it is made in.
+    #[test]
+    fn reads_see_their_own_m_cycle() {
+        for &(read, tima) in &[
+            (&[0x7E][..], 0x00),             // LD A,(HL), read in M-cycle 2
+            (&[0xF0, 0x05][..], 0x01),       // LDH A,(05), read in M-cycle 3
+            (&[0xFA, 0x05, 0xFF][..], 0x01), // LD A,(FF05), read in M-cycle 4
+        ] {
+            let mut program = TIMER_SETUP.to_vec();
+            program.extend_from_slice(read);
+            let mut cpu = cpu(&program);
+
+            run(&mut cpu, 8);
+            assert_eq!(cpu.register.a, tima, "{:02X?}", read);
+        }
+    }
 
-            Opcode::rst_10 => {
-                let value = self.current_pc;
+    // TIMA increments in the last M-cycle of the PUSH, just before the
+    // write to it. Were the timer advanced after the writes instead, the
+    // increment would land on the written value.
+    #[test]
+    fn writes_see_their_own_m_cycle() {
+        let mut program = TIMER_SETUP.to_vec();
+        program.extend_from_slice(&[
+            0x31, 0x07, 0xFF, // LD SP,FF07
+            0xC5,             // PUSH BC, writes FF06 then FF05
+            0x00, 0x00, 0x00, 0x00,
+        ]);
+        let mut cpu = cpu(&program);
+        cpu.register.set_bc(0x1020);
+
+        run(&mut cpu, 9);
+        assert_eq!(cpu.interconnect.load8(0xFF06), 0x10);
+        assert_eq!(cpu.interconnect.load8(0xFF05), 0x20);
+
+        run(&mut cpu, 3);
+        assert_eq!(cpu.interconnect.load8(0xFF05), 0x20);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.interconnect.load8(0xFF05), 0x21);
+    }
 
-                let addr = self.register.sp;
-                self.store16(addr, value);
+    #[test]
+    fn cycle_returns_m_cycles() {
+        let mut cpu = cpu(&[
+            0x00,             // NOP
+            0x21, 0x00, 0xC0, // LD HL,C000
+            0x36, 0x13,       // LD (HL),13
+            0xCD, 0x0C, 0x01, // CALL 010C
+            0x20, 0x00,       // JR NZ,+0, taken as bit 0 was set
+            0x76,             // HALT
+            0xCB, 0x46,       // BIT 0,(HL)
+            0xC9,             // RET
+        ]);
+
+        assert_eq!(run(&mut cpu, 5), [1, 3, 3, 6, 3]);
+        assert_eq!(run(&mut cpu, 2), [4, 3]);
+        assert_eq!(cpu.register.pc, 0x010B);
+    }
 
-                self.register.pc = 0x10;
-                4
-            }
+    // Enables the timer interrupt alone.
+    const ENABLE_TIMER: [u8; 4] = [
+        0x3E, 0x04, // LD A,04
+        0xE0, 0xFF, // LDH (FF),A
+    ];
+
+    #[test]
+    fn halt_resumes_without_ime() {
+        let mut program = ENABLE_TIMER.to_vec();
+        program.extend_from_slice(&[
+            0x76, // HALT
+            0x04, // INC B
+        ]);
+        let mut cpu = cpu(&program);
+
+        run(&mut cpu, 3);
+        assert!(cpu.halted);
+        assert_eq!(run(&mut cpu, 3), [1, 1, 1]);
+        assert!(cpu.halted);
+
+        // With IME=0 the request is left pending and execution carries on
+        // after the HALT.
+        cpu.interconnect.interrupts.request(Interrupt::Timer);
+        run(&mut cpu, 1);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.register.b, 0x01);
+        assert_eq!(cpu.register.pc, 0x0106);
+        assert!(cpu.interconnect.interrupts.is_requested(Interrupt::Timer));
+    }
 
-            Opcode::rst_18 => {
-                let value = self.current_pc;
+    #[test]
+    fn halt_bug_repeats_next_byte() {
+        let mut program = ENABLE_TIMER.to_vec();
+        program.extend_from_slice(&[
+            0xE0, 0x0F, // LDH (0F),A, requests the timer interrupt
+            0x76,       // HALT, with IME=0 and an interrupt pending
+            0x3C,       // INC A, run twice
+            0x00,       // NOP
+        ]);
+        let mut cpu = cpu(&program);
+
+        run(&mut cpu, 4);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.register.pc, 0x0107);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.register.a, 0x05);
+        assert_eq!(cpu.register.pc, 0x0107);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.register.a, 0x06);
+        assert_eq!(cpu.register.pc, 0x0108);
+    }
 
-                let addr = self.register.sp;
-                self.store16(addr, value);
+    // Stopped, the timer and the PPU are frozen but every call still
+    // reports an idle M-cycle. Pressing a key in a selected row wakes it.
+    #[test]
+    fn stop_idles_until_input() {
+        let mut cpu = cpu(&[
+            0x10, 0x00, // STOP
+            0x3C,       // INC A
+        ]);
+
+        run(&mut cpu, 1);
+        assert!(cpu.stopped);
+        let ly = cpu.interconnect.load8(0xFF44);
+
+        assert!(run(&mut cpu, 100).iter().all(|&cycles| cycles == 1));
+        assert!(cpu.stopped);
+        assert_eq!(cpu.interconnect.load8(0xFF04), 0x00);
+        assert_eq!(cpu.interconnect.load8(0xFF44), ly);
+
+        cpu.interconnect.press(Button::A);
+        assert_eq!(run(&mut cpu, 1), [1]);
+        assert!(!cpu.stopped);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.register.a, 0x02);
+    }
 
-                self.register.pc = 0x18;
-                4
-            }
+    #[test]
+    fn stop_switches_speed_when_armed() {
+        let mut cpu = cpu_with_cgb(&[
+            0x3E, 0x01, // LD A,01
+            0xE0, 0x4D, // LDH (4D),A, arms the switch
+            0x10, 0x00, // STOP
+            0x00,       // NOP
+        ], true);
+
+        run(&mut cpu, 2);
+        assert_eq!(cpu.interconnect.load8(0xFF4D), 0x7F);
+
+        run(&mut cpu, 1);
+        assert!(!cpu.stopped);
+        assert!(cpu.interconnect.double_speed);
+        assert_eq!(cpu.interconnect.load8(0xFF4D), 0xFE);
+        assert_eq!(cpu.interconnect.load8(0xFF04), 0x00);
+        assert_eq!(cpu.register.pc, 0x0106);
+    }
 
-            Opcode::rst_20 => {
-                let value = self.current_pc;
+    // Enables the VBlank and timer interrupts, then IME.
+    const ENABLE_INTERRUPTS: [u8; 5] = [
+        0x3E, 0x05, // LD A,05
+        0xE0, 0xFF, // LDH (FF),A
+        0xFB,       // EI
+    ];
+
+    #[test]
+    fn dispatch_takes_five_m_cycles() {
+        let mut program = ENABLE_INTERRUPTS.to_vec();
+        program.extend_from_slice(&[0x00, 0x00]);
+        let mut cpu = cpu(&program);
+        run(&mut cpu, 4);
+
+        cpu.interconnect.interrupts.request(Interrupt::Timer);
+        assert_eq!(run(&mut cpu, 1), [5]);
+        assert_eq!(cpu.register.pc, 0x0050);
+        assert_eq!(cpu.register.sp, 0xFFFC);
+        assert_eq!(cpu.interconnect.load8(0xFFFC), 0x06);
+        assert_eq!(cpu.interconnect.load8(0xFFFD), 0x01);
+        assert!(!cpu.ime);
+        assert!(!cpu.interconnect.interrupts.is_requested(Interrupt::Timer));
+    }
 
-                let addr = self.register.sp;
-                self.store16(addr, value);
+    // The lowest bit wins, the other request stays pending. Disabled
+    // requests are not dispatched at all.
+    #[test]
+    fn dispatch_priority() {
+        let mut program = ENABLE_INTERRUPTS.to_vec();
+        program.push(0x00);
+        let mut cpu = cpu(&program);
+        run(&mut cpu, 4);
+
+        cpu.interconnect.interrupts.request(Interrupt::Joypad);
+        cpu.interconnect.interrupts.request(Interrupt::Timer);
+        cpu.interconnect.interrupts.request(Interrupt::VBlank);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.register.pc, 0x0040);
+        assert!(cpu.interconnect.interrupts.is_requested(Interrupt::Timer));
+        assert!(cpu.interconnect.interrupts.is_requested(Interrupt::Joypad));
+    }
 
-                self.register.pc = 0x20;
-                4
-            }
+    // With SP at 0x0000 the high byte of PC lands in IE. It no longer
+    // enables the timer by the time the source is picked, so the dispatch
+    // goes to 0x0000 and the request stays.
+    #[test]
+    fn ie_push_cancels_dispatch() {
+        let mut program = ENABLE_INTERRUPTS.to_vec();
+        program.extend_from_slice(&[
+            0x31, 0x00, 0x00, // LD SP,0000
+            0x00,
+        ]);
+        let mut cpu = cpu(&program);
+        run(&mut cpu, 4);
+
+        cpu.interconnect.interrupts.request(Interrupt::Timer);
+        assert_eq!(run(&mut cpu, 1), [5]);
+        assert_eq!(cpu.register.pc, 0x0000);
+        assert_eq!(cpu.interconnect.load8(0xFFFF), 0x01);
+        assert!(cpu.interconnect.interrupts.is_requested(Interrupt::Timer));
+    }
 
-            Opcode::rst_28 => {
-                let value = self.current_pc;
+    // IME is only raised after the instruction following EI.
+    #[test]
+    fn ei_is_delayed() {
+        let mut program = ENABLE_INTERRUPTS.to_vec();
+        program.extend_from_slice(&[0x04, 0x04]);
+        let mut cpu = cpu(&program);
+        run(&mut cpu, 2);
+        cpu.interconnect.interrupts.request(Interrupt::Timer);
+
+        run(&mut cpu, 1);
+        assert!(!cpu.ime);
+        run(&mut cpu, 1);
+        assert!(cpu.ime);
+        assert_eq!(cpu.register.b, 0x01);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.register.pc, 0x0050);
+        assert_eq!(cpu.register.b, 0x01);
+    }
 
-                let addr = self.register.sp;
-                self.store16(addr, value);
+    #[test]
+    fn ei_then_di_never_enables() {
+        let mut program = ENABLE_INTERRUPTS.to_vec();
+        program.extend_from_slice(&[0xF3, 0x00, 0x00]);
+        let mut cpu = cpu(&program);
+        run(&mut cpu, 2);
+        cpu.interconnect.interrupts.request(Interrupt::Timer);
+
+        run(&mut cpu, 4);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.register.pc, 0x0108);
+    }
 
-                self.register.pc = 0x28;
-                4
-            }
+    // RETI raises IME at once, another pending request is taken right
+    // after it returns.
+    #[test]
+    fn reti_enables_at_once() {
+        let mut program = ENABLE_INTERRUPTS.to_vec();
+        program.extend_from_slice(&[0x00, 0x00]);
+        let mut data = image(&program);
+        data[0x0040] = 0xD9; // RETI
+        data[0x0050] = 0xD9;
+        let mut cpu = cpu_from(data, false);
+        run(&mut cpu, 4);
+
+        cpu.interconnect.interrupts.request(Interrupt::VBlank);
+        cpu.interconnect.interrupts.request(Interrupt::Timer);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.register.pc, 0x0040);
+
+        assert_eq!(run(&mut cpu, 1), [4]);
+        assert!(cpu.ime);
+        assert_eq!(cpu.register.pc, 0x0106);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.register.pc, 0x0050);
+    }
 
-            Opcode::rst_30 => {
-                let value = self.current_pc;
+    // A after LD A,x; ADD/SUB y; DAA, with the resulting flags.
+    #[test]
+    fn daa_adjusts_to_bcd() {
+        for &(x, op, y, a, f) in &[
+            (0x15, 0xC6, 0x27, 0x42, 0x00), // 15 + 27
+            (0x09, 0xC6, 0x08, 0x17, 0x00), // half carry out of the low digit
+            (0x99, 0xC6, 0x01, 0x00, 0x90), // 99 + 1 carries out
+            (0x90, 0xC6, 0x90, 0x80, 0x10), // binary carry
+            (0x42, 0xD6, 0x15, 0x27, 0x40), // 42 - 15 borrows a digit
+            (0x10, 0xD6, 0x20, 0x90, 0x50), // 10 - 20 borrows out
+            (0x00, 0xD6, 0x00, 0x00, 0xC0), // N is kept
+        ] {
+            let mut cpu = cpu(&[0x3E, x, op, y, 0x27]);
+            run(&mut cpu, 3);
+            assert_eq!((cpu.register.a, cpu.register.f()), (a, f), "{:02X} {:02X} {:02X}", x, op, y);
+        }
+    }
 
-                let addr = self.register.sp;
-                self.store16(addr, value);
+    // H and C come from the low byte as an unsigned addition, whatever the
+    // sign of the offset, and Z is always cleared.
+    #[test]
+    fn sp_offset_flags() {
+        for &(sp, offset, result, f) in &[
+            (0x00FF, 0x01, 0x0100, 0x30),
+            (0x000F, 0x01, 0x0010, 0x20),
+            (0x00F0, 0x10, 0x0100, 0x10),
+            (0x0100, 0xFF, 0x00FF, 0x00),
+            (0x0001, 0xFF, 0x0000, 0x30),
+            (0xFFF8, 0x08, 0x0000, 0x30),
+        ] {
+            let load_sp = [0x31, sp as u8, (sp >> 8) as u8];
+
+            // ADD SP,e
+            let mut program = load_sp.to_vec();
+            program.extend_from_slice(&[0xE8, offset]);
+            let mut cpu = cpu(&program);
+            assert_eq!(run(&mut cpu, 2), [3, 4]);
+            assert_eq!((cpu.register.sp, cpu.register.f()), (result, f), "{:04X}{:+}", sp, offset as i8);
+
+            // LD HL,SP+e
+            let mut program = load_sp.to_vec();
+            program.extend_from_slice(&[0xF8, offset]);
+            let mut cpu = self::cpu(&program);
+            assert_eq!(run(&mut cpu, 2), [3, 3]);
+            assert_eq!((cpu.register.hl(), cpu.register.f()), (result, f), "{:04X}{:+}", sp, offset as i8);
+            assert_eq!(cpu.register.sp, sp);
+        }
+    }
 
-                self.register.pc = 0x30;
-                4
-            }
+    // The illegal opcodes hang the CPU for good, interrupts included.
+    #[test]
+    fn illegal_opcode_locks_up() {
+        let mut program = ENABLE_INTERRUPTS.to_vec();
+        program.extend_from_slice(&[0x00, 0xD3, 0x3C]);
+        let mut cpu = cpu(&program);
+        run(&mut cpu, 5);
+        let a = cpu.register.a;
+
+        cpu.interconnect.interrupts.request(Interrupt::VBlank);
+        assert_eq!(run(&mut cpu, 3), [1, 1, 1]);
+        assert_eq!(cpu.register.pc, 0x0100 + program.len() as u16 - 1);
+        assert_eq!(cpu.register.a, a);
+    }
 
-            Opcode::rst_38 => {
-                let value = self.current_pc;
+    // A and F after the CB rotates, shifts and BIT on A. Unlike RLCA and
+    // friends these set Z from the result, and BIT keeps C.
+    #[test]
+    fn cb_register_flags() {
+        for &(program, a, f) in &[
+            (&[0x3E, 0x80, 0xCB, 0x07][..], 0x01, 0x10),             // RLC A
+            (&[0x3E, 0x00, 0xCB, 0x07][..], 0x00, 0x80),             // RLC A of zero
+            (&[0x3E, 0x00, 0x07][..], 0x00, 0x00),                   // RLCA of zero
+            (&[0x3E, 0x01, 0xCB, 0x0F][..], 0x80, 0x10),             // RRC A
+            (&[0x37, 0x3E, 0x80, 0xCB, 0x17][..], 0x01, 0x10),       // RL A, carry in
+            (&[0x37, 0x3F, 0x3E, 0x01, 0xCB, 0x1F][..], 0x00, 0x90), // RR A
+            (&[0x3E, 0x80, 0xCB, 0x27][..], 0x00, 0x90),             // SLA A
+            (&[0x3E, 0x81, 0xCB, 0x2F][..], 0xC0, 0x10),             // SRA A keeps bit 7
+            (&[0x3E, 0x81, 0xCB, 0x3F][..], 0x40, 0x10),             // SRL A
+            (&[0x37, 0x3E, 0xF0, 0xCB, 0x37][..], 0x0F, 0x00),       // SWAP A clears C
+            (&[0x37, 0x3E, 0x7F, 0xCB, 0x7F][..], 0x7F, 0xB0),       // BIT 7,A
+            (&[0x37, 0x3F, 0x3E, 0x01, 0xCB, 0x47][..], 0x01, 0x20), // BIT 0,A
+        ] {
+            let mut cpu = cpu(program);
+            while cpu.register.pc < 0x0100 + program.len() as u16 {
+                cpu.cycle();
+            }
+            assert_eq!((cpu.register.a, cpu.register.f()), (a, f), "{:02X?}", program);
+        }
+    }
 
-                let addr = self.register.sp;
-                self.store16(addr, value);
+    // (HL) operands take a read M-cycle, and a write one unless for BIT.
+    #[test]
+    fn cb_hl_operands() {
+        let mut cpu = cpu(&[
+            0x21, 0x00, 0xC0, // LD HL,C000
+            0x36, 0x01,       // LD (HL),01
+            0xCB, 0xFE,       // SET 7,(HL)
+            0xCB, 0x86,       // RES 0,(HL)
+            0xCB, 0x7E,       // BIT 7,(HL)
+            0xCB, 0x36,       // SWAP (HL)
+            0xCB, 0x00,       // RLC B
+        ]);
+        assert_eq!(run(&mut cpu, 2), [3, 3]);
+        assert_eq!(run(&mut cpu, 2), [4, 4]);
+        assert_eq!(cpu.interconnect.load8(0xC000), 0x80);
+
+        assert_eq!(run(&mut cpu, 1), [3]);
+        assert_eq!(cpu.register.f() & 0xE0, 0x20);
+
+        assert_eq!(run(&mut cpu, 2), [4, 2]);
+        assert_eq!(cpu.interconnect.load8(0xC000), 0x08);
+    }
 
-                self.register.pc = 0x38;
-                4
-            }
+    // The low nibble of F reads as zero whatever was popped into it, and
+    // the popped bits drive the flags.
+    #[test]
+    fn pop_af_masks_low_nibble() {
+        let mut cpu = cpu(&[
+            0x01, 0xFF, 0x12, // LD BC,12FF
+            0xC5,             // PUSH BC
+            0xF1,             // POP AF
+            0xF5,             // PUSH AF
+            0xD1,             // POP DE
+            0x01, 0x0F, 0x34, // LD BC,340F
+            0xC5,             // PUSH BC
+            0xF1,             // POP AF
+            0x38, 0x00,       // JR C,+0
+        ]);
+        run(&mut cpu, 5);
+        assert_eq!(cpu.register.af(), 0x12F0);
+        assert_eq!(cpu.register.de(), 0x12F0);
+        assert_eq!(cpu.interconnect.load8(0xFFFC), 0xF0);
+
+        run(&mut cpu, 3);
+        assert_eq!(cpu.register.af(), 0x3400);
+        assert_eq!(run(&mut cpu, 1), [2]);
+    }
 
-            Opcode::jp_hl => {
-                self.register.pc = self.register.hl();
-                1
-            }
+    #[test]
+    fn af_packs_flag_bits() {
+        let mut register = Register::new();
+        register.set_af(0xAB5A);
+        assert_eq!(register.f(), 0x50);
+        assert!(!register.flag.z && register.flag.n && !register.flag.h && register.flag.c);
 
-            Opcode::halt => {
-                self.halted = true;
-                4
-            }
-        }
+        register.flag.z = true;
+        register.flag.c = false;
+        assert_eq!(register.af(), 0xABC0);
     }
 }
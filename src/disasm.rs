@@ -0,0 +1,178 @@
+use std::fmt;
+
+use opcode::{self, Alu, Condition, Operand8, Operation, Reg16, Reg16Stack, Reg8, Shift};
+
+impl fmt::Display for Reg8 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Reg8::B => "B",
+            Reg8::C => "C",
+            Reg8::D => "D",
+            Reg8::E => "E",
+            Reg8::H => "H",
+            Reg8::L => "L",
+            Reg8::HlInd => "(HL)",
+            Reg8::A => "A",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for Reg16 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Reg16::BC => "BC",
+            Reg16::DE => "DE",
+            Reg16::HL => "HL",
+            Reg16::SP => "SP",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for Reg16Stack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Reg16Stack::BC => "BC",
+            Reg16Stack::DE => "DE",
+            Reg16Stack::HL => "HL",
+            Reg16Stack::AF => "AF",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Condition::NZ => "NZ",
+            Condition::Z => "Z",
+            Condition::NC => "NC",
+            Condition::C => "C",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for Alu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Alu::Add => "ADD A,",
+            Alu::Adc => "ADC A,",
+            Alu::Sub => "SUB ",
+            Alu::Sbc => "SBC A,",
+            Alu::And => "AND ",
+            Alu::Xor => "XOR ",
+            Alu::Or => "OR ",
+            Alu::Cp => "CP ",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for Shift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Shift::Rlc => "RLC",
+            Shift::Rrc => "RRC",
+            Shift::Rl => "RL",
+            Shift::Rr => "RR",
+            Shift::Sla => "SLA",
+            Shift::Sra => "SRA",
+            Shift::Swap => "SWAP",
+            Shift::Srl => "SRL",
+        };
+        f.write_str(name)
+    }
+}
+
+fn operand8(operand: Operand8, imm8: u8, imm16: u16) -> String {
+    match operand {
+        Operand8::Reg(reg) => format!("{}", reg),
+        Operand8::Imm8 => format!("${:02X}", imm8),
+        Operand8::Ind(reg) => format!("({})", reg),
+        Operand8::HlInc => "(HL+)".to_string(),
+        Operand8::HlDec => "(HL-)".to_string(),
+        Operand8::Abs => format!("(${:04X})", imm16),
+        Operand8::HighImm => format!("($FF00+${:02X})", imm8),
+        Operand8::HighC => "($FF00+C)".to_string(),
+    }
+}
+
+fn condition(condition: Option<Condition>) -> String {
+    match condition {
+        Some(condition) => format!("{},", condition),
+        None => String::new(),
+    }
+}
+
+// Disassembles the instruction at `addr`, returning its text and length.
+// Only the bytes that belong to the instruction are read.
+pub fn disassemble<F: Fn(u16) -> u8>(addr: u16, read: F) -> (String, u8) {
+    let mut instruction = opcode::decode(read(addr));
+
+    if instruction.operation == Operation::Prefix {
+        instruction = opcode::decode_callback(read(addr.wrapping_add(1)));
+    }
+
+    let imm8 = if instruction.length >= 2 { read(addr.wrapping_add(1)) } else { 0 };
+    let imm16 = if instruction.length >= 3 {
+        imm8 as u16 | (read(addr.wrapping_add(2)) as u16) << 8
+    } else {
+        0
+    };
+    let next = addr.wrapping_add(instruction.length as u16);
+
+    let text = match instruction.operation {
+        Operation::Nop => "NOP".to_string(),
+        Operation::Stop => "STOP".to_string(),
+        Operation::Halt => "HALT".to_string(),
+        Operation::Di => "DI".to_string(),
+        Operation::Ei => "EI".to_string(),
+        Operation::Illegal => format!("DB ${:02X}", read(addr)),
+        Operation::Prefix => unreachable!(),
+
+        Operation::Ld8(dst, src) => {
+            format!("LD {},{}", operand8(dst, imm8, imm16), operand8(src, imm8, imm16))
+        }
+        Operation::Ld16(reg) => format!("LD {},${:04X}", reg, imm16),
+        Operation::LdAbsSp => format!("LD (${:04X}),SP", imm16),
+        Operation::LdSpHl => "LD SP,HL".to_string(),
+        Operation::LdHlSpImm => format!("LD HL,SP{:+}", imm8 as i8),
+
+        Operation::Inc8(reg) => format!("INC {}", reg),
+        Operation::Dec8(reg) => format!("DEC {}", reg),
+        Operation::Inc16(reg) => format!("INC {}", reg),
+        Operation::Dec16(reg) => format!("DEC {}", reg),
+        Operation::AddHl(reg) => format!("ADD HL,{}", reg),
+        Operation::AddSpImm => format!("ADD SP,{}", imm8 as i8),
+        Operation::Alu(op, src) => format!("{}{}", op, operand8(src, imm8, imm16)),
+
+        Operation::Rlca => "RLCA".to_string(),
+        Operation::Rrca => "RRCA".to_string(),
+        Operation::Rla => "RLA".to_string(),
+        Operation::Rra => "RRA".to_string(),
+        Operation::Daa => "DAA".to_string(),
+        Operation::Cpl => "CPL".to_string(),
+        Operation::Scf => "SCF".to_string(),
+        Operation::Ccf => "CCF".to_string(),
+
+        Operation::Jr(cc) => format!("JR {}${:04X}", condition(cc), next.wrapping_add(imm8 as i8 as u16)),
+        Operation::Jp(cc) => format!("JP {}${:04X}", condition(cc), imm16),
+        Operation::JpHl => "JP HL".to_string(),
+        Operation::Call(cc) => format!("CALL {}${:04X}", condition(cc), imm16),
+        Operation::Ret(Some(cc)) => format!("RET {}", cc),
+        Operation::Ret(None) => "RET".to_string(),
+        Operation::Reti => "RETI".to_string(),
+        Operation::Rst(vector) => format!("RST ${:02X}", vector),
+        Operation::Push(reg) => format!("PUSH {}", reg),
+        Operation::Pop(reg) => format!("POP {}", reg),
+
+        Operation::Shift(op, reg) => format!("{} {}", op, reg),
+        Operation::Bit(bit, reg) => format!("BIT {},{}", bit, reg),
+        Operation::Res(bit, reg) => format!("RES {},{}", bit, reg),
+        Operation::Set(bit, reg) => format!("SET {},{}", bit, reg),
+    };
+
+    (text, instruction.length)
+}
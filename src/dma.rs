@@ -0,0 +1,88 @@
+// OAM DMA copies 160 bytes from XX00-XX9F to OAM, one byte per M-cycle.
+// The copy starts two M-cycles after the write to 0xFF46, and OAM stays
+// blocked for the cycle after the last byte.
+pub struct OamDma {
+    register: u8,
+    // M-cycles until a requested transfer takes over.
+    start_delay: u8,
+    active: bool,
+    source: u16,
+    index: u16,
+    // The byte last put on the bus by the transfer.
+    value: u8,
+}
+
+pub const OAM_DMA_LENGTH: u16 = 0xA0;
+
+impl OamDma {
+    pub fn new() -> OamDma {
+        OamDma {
+            register: 0xFF,
+            start_delay: 0,
+            active: false,
+            source: 0,
+            index: 0,
+            value: 0xFF,
+        }
+    }
+
+    pub fn rb(&self, a: u16) -> u8 {
+        match a {
+            0xFF46 => self.register,
+            _ => panic!("OAM DMA does not handle read {:4X}", a),
+        }
+    }
+
+    // A write while a transfer runs restarts it from the new source, the
+    // old one carries on until then.
+    pub fn wb(&mut self, a: u16, v: u8) {
+        match a {
+            0xFF46 => {
+                self.register = v;
+                self.start_delay = 2;
+            }
+            _ => panic!("OAM DMA does not handle write {:4X}", a),
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    pub fn value(&self) -> u8 {
+        self.value
+    }
+
+    pub fn source(&self) -> u16 {
+        self.source
+    }
+
+    // Advances one M-cycle. Returns the source address and OAM offset of
+    // the byte to copy in this cycle, if any.
+    pub fn step(&mut self) -> Option<(u16, u8)> {
+        if self.active && self.index == OAM_DMA_LENGTH {
+            self.active = false;
+        }
+
+        if self.start_delay > 0 {
+            self.start_delay -= 1;
+            if self.start_delay == 0 {
+                self.active = true;
+                self.source = (self.register as u16) << 8;
+                self.index = 0;
+            }
+        }
+
+        if !self.active || self.index == OAM_DMA_LENGTH {
+            return None;
+        }
+
+        let transfer = (self.source + self.index, self.index as u8);
+        self.index += 1;
+        Some(transfer)
+    }
+
+    pub fn set_value(&mut self, value: u8) {
+        self.value = value;
+    }
+}
@@ -0,0 +1,112 @@
+// CGB VRAM DMA. A general purpose transfer copies everything at once, an
+// HBlank transfer one 16-byte block per HBlank. The CPU is stopped while a
+// block is copied, two bytes per M-cycle at normal speed and one at double
+// speed, so a block always takes the same real time.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Idle,
+    General,
+    HBlank,
+}
+
+const BLOCK_SIZE: u8 = 0x10;
+
+pub struct Hdma {
+    source: u16,
+    destination: u16,
+    mode: Mode,
+    // Blocks left, including the one being copied.
+    remaining: u8,
+    // Bytes left in the block being copied.
+    block: u8,
+}
+
+impl Hdma {
+    pub fn new() -> Hdma {
+        Hdma {
+            source: 0,
+            destination: 0,
+            mode: Mode::Idle,
+            remaining: 0,
+            block: 0,
+        }
+    }
+
+    pub fn rb(&self, a: u16) -> u8 {
+        match a {
+            0xFF51..=0xFF54 => 0xFF,
+            // Bit 7 reads 0 while an HBlank transfer is active. After it is
+            // done or cancelled it reads 1, the rest is the number of blocks
+            // left minus one.
+            0xFF55 => {
+                let active = if self.mode == Mode::HBlank { 0x00 } else { 0x80 };
+                active | (self.remaining.wrapping_sub(1) & 0x7F)
+            }
+            _ => panic!("HDMA does not handle read {:4X}", a),
+        }
+    }
+
+    // `in_hblank` starts the first block of an HBlank transfer right away,
+    // as happens when it is set up during HBlank or with the LCD off.
+    pub fn wb(&mut self, a: u16, v: u8, in_hblank: bool) {
+        match a {
+            0xFF51 => self.source = (self.source & 0x00FF) | (v as u16) << 8,
+            0xFF52 => self.source = (self.source & 0xFF00) | (v & 0xF0) as u16,
+            0xFF53 => self.destination = (self.destination & 0x00FF) | ((v & 0x1F) as u16) << 8,
+            0xFF54 => self.destination = (self.destination & 0xFF00) | (v & 0xF0) as u16,
+            0xFF55 => {
+                if self.mode == Mode::HBlank && v & 0x80 == 0 {
+                    self.mode = Mode::Idle;
+                    return;
+                }
+
+                self.remaining = (v & 0x7F) + 1;
+                if v & 0x80 != 0 {
+                    self.mode = Mode::HBlank;
+                    if in_hblank {
+                        self.hblank();
+                    }
+                } else {
+                    self.mode = Mode::General;
+                }
+            }
+            _ => panic!("HDMA does not handle write {:4X}", a),
+        }
+    }
+
+    // Called when the PPU enters HBlank on a visible line.
+    pub fn hblank(&mut self) {
+        if self.mode == Mode::HBlank && self.block == 0 && self.remaining > 0 {
+            self.block = BLOCK_SIZE;
+        }
+    }
+
+    // True while the CPU has to wait for the transfer.
+    pub fn copying(&self) -> bool {
+        self.block > 0 || self.mode == Mode::General
+    }
+
+    // The source address and VRAM offset of the next byte to copy, if any.
+    pub fn next_byte(&mut self) -> Option<(u16, u16)> {
+        if self.block == 0 {
+            if self.mode != Mode::General || self.remaining == 0 {
+                return None;
+            }
+            self.block = BLOCK_SIZE;
+        }
+
+        let transfer = (self.source, self.destination & 0x1FFF);
+        self.source = self.source.wrapping_add(1);
+        self.destination = (self.destination + 1) & 0x1FFF;
+
+        self.block -= 1;
+        if self.block == 0 {
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                self.mode = Mode::Idle;
+            }
+        }
+
+        Some(transfer)
+    }
+}
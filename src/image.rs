@@ -0,0 +1,174 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Screenshot writers for 8-bit RGBA frames. Both formats are simple enough
+// to produce by hand, so headless runs need no image or windowing crates.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Ppm,
+    Png,
+}
+
+impl Format {
+    // Picks the format from the file extension, PNG unless it says ppm.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Format {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("ppm") => Format::Ppm,
+            _ => Format::Png,
+        }
+    }
+}
+
+pub fn save<P: AsRef<Path>>(path: P, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    let format = Format::from_path(&path);
+    let mut out = BufWriter::new(File::create(path)?);
+
+    match format {
+        Format::Ppm => write_ppm(&mut out, width, height, rgba)?,
+        Format::Png => write_png(&mut out, width, height, rgba)?,
+    }
+
+    out.flush()
+}
+
+// Binary P6, alpha is dropped.
+pub fn write_ppm<W: Write>(out: &mut W, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    assert_eq!(rgba.len(), width * height * 4);
+
+    write!(out, "P6\n{} {}\n255\n", width, height)?;
+
+    let rgb: Vec<u8> = rgba.chunks(4).flat_map(|pixel| pixel[..3].iter().cloned()).collect();
+    out.write_all(&rgb)
+}
+
+// Truecolor with alpha, no filtering and stored (uncompressed) deflate
+// blocks. Files are larger than they need to be but decode everywhere.
+pub fn write_png<W: Write>(out: &mut W, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    assert_eq!(rgba.len(), width * height * 4);
+
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGBA, deflate, no filter, no interlace.
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &header)?;
+
+    // Every row starts with its filter type, 0 for none.
+    let mut raw = Vec::with_capacity((width * 4 + 1) * height);
+    for row in rgba.chunks(width * 4) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(out, b"IDAT", &zlib_stored(&raw))?;
+
+    write_chunk(out, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+
+    let crc = crc32(crc32(0, kind), data);
+    out.write_all(&crc.to_be_bytes())
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 0xFFFF;
+
+    // 32K window, no preset dictionary, check bits for 0x7801.
+    let mut out = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIXEL: [u8; 4] = [0x12, 0x34, 0x56, 0x78];
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(0, b"IEND"), 0xAE42_6082);
+        assert_eq!(crc32(crc32(0, b"IE"), b"ND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(b""), 0x0000_0001);
+    }
+
+    #[test]
+    fn ppm_one_pixel() {
+        let mut out = Vec::new();
+        write_ppm(&mut out, 1, 1, &PIXEL).unwrap();
+        assert_eq!(out, b"P6\n1 1\n255\n\x12\x34\x56");
+    }
+
+    #[test]
+    fn png_one_pixel() {
+        let mut out = Vec::new();
+        write_png(&mut out, 1, 1, &PIXEL).unwrap();
+        assert_eq!(out, [
+            // Signature
+            0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A,
+            // IHDR
+            0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52,
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00,
+            0x1F, 0x15, 0xC4, 0x89,
+            // IDAT: zlib header, one final stored block, the filtered row
+            // and the Adler-32
+            0x00, 0x00, 0x00, 0x10, 0x49, 0x44, 0x41, 0x54,
+            0x78, 0x01, 0x01, 0x05, 0x00, 0xFA, 0xFF,
+            0x00, 0x12, 0x34, 0x56, 0x78,
+            0x02, 0x0D, 0x01, 0x15,
+            0xB2, 0x64, 0x1E, 0xC4,
+            // IEND
+            0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+        ]);
+    }
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(Format::from_path("shot.PPM"), Format::Ppm);
+        assert_eq!(Format::from_path("shot.png"), Format::Png);
+        assert_eq!(Format::from_path("shot"), Format::Png);
+    }
+}
@@ -1,10 +1,19 @@
-use rom::Rom;
+use std::io;
+
+use cartridge::Cartridge;
+use rom::CgbSupport;
 use wram::Wram;
-use echo::Echo;
 use hram::Hram;
-use eram::Eram;
 use sdt::Sdt;
 use timer::Timer;
+use dma::OamDma;
+use hdma::Hdma;
+use interrupt::InterruptController;
+use ppu::{Mode, OamCorruption, Ppu};
+use apu::Apu;
+use joypad::{Button, Joypad};
+use model::EmulatedModel;
+use boot::BootRom;
 
 mod map {
     pub struct Range(u16, u16);
@@ -13,7 +22,7 @@ mod map {
         pub fn contains(self, addr: u16) -> Option<u16> {
             let Range(start, end) = self;
 
-            if addr >= start && addr < start + (end - start + 0x1) {
+            if (start..=end).contains(&addr) {
                 Some(addr - start)
             } else {
                 None
@@ -34,45 +43,289 @@ mod map {
 }
 
 pub struct Interconnect {
-    rom: Rom,
+    cartridge: Cartridge,
     wram: Wram,
-    echo: Echo,
     hram: Hram,
-    eram: Eram,
     sdt: Sdt,
     timer: Timer,
+    dma: OamDma,
+    hdma: Hdma,
+    ppu: Ppu,
+    apu: Apu,
+    joypad: Joypad,
+
+    pub interrupts: InterruptController,
+
+    model: EmulatedModel,
+    boot_rom: Option<BootRom>,
 
-    pub interrupt_enable: u8,
-    pub interrupt_flag: u8,
+    cgb: bool,
+    speed_switch_armed: bool,
+    pub double_speed: bool,
 }
 
 impl Interconnect {
-    pub fn new(rom: Rom) -> Interconnect {
+    // Runs on a CGB when the cartridge header supports it, a DMG otherwise.
+    pub fn new(cartridge: Cartridge) -> Interconnect {
+        let model = if cartridge.header().cgb != CgbSupport::None {
+            EmulatedModel::Cgb
+        } else {
+            EmulatedModel::Dmg
+        };
+        Interconnect::with_model(cartridge, model)
+    }
+
+    // CGB mode needs both CGB hardware and a cartridge that supports it.
+    pub fn with_model(cartridge: Cartridge, model: EmulatedModel) -> Interconnect {
+        let cgb = model.is_cgb() && cartridge.header().cgb != CgbSupport::None;
+        Interconnect::build(cartridge, model, cgb)
+    }
+
+    // Forces CGB mode on or off, whatever the cartridge says.
+    pub fn with_cgb(cartridge: Cartridge, cgb: bool) -> Interconnect {
+        let model = if cgb { EmulatedModel::Cgb } else { EmulatedModel::Dmg };
+        Interconnect::build(cartridge, model, cgb)
+    }
+
+    // A DMG cartridge on CGB hardware is colored the way the boot ROM
+    // colors one it does not recognise.
+    fn build(cartridge: Cartridge, model: EmulatedModel, cgb: bool) -> Interconnect {
+        let mut ppu = Ppu::with_cgb(cgb);
+        if model.is_cgb() && !cgb {
+            ppu.set_compat();
+        }
+
         Interconnect {
-            rom,
+            cartridge,
             wram: Wram::new(),
-            echo: Echo::new(),
             hram: Hram::new(),
-            eram: Eram::new(),
             sdt: Sdt::new(),
-            timer: Timer::new(),
+            timer: Timer::post_boot(model, cgb),
+            dma: OamDma::new(),
+            hdma: Hdma::new(),
+            ppu,
+            apu: Apu::new(),
+            joypad: Joypad::new(),
+
+            interrupts: InterruptController::new(),
 
-            interrupt_enable: 0,
-            interrupt_flag: 0,
+            model,
+            boot_rom: None,
+
+            cgb,
+            speed_switch_armed: false,
+            double_speed: false,
+        }
+    }
+
+    pub fn cgb(&self) -> bool {
+        self.cgb
+    }
+
+    pub fn model(&self) -> EmulatedModel {
+        self.model
+    }
+
+    // Maps a boot ROM over the cartridge. Has to be done before the CPU is
+    // created for it to start from 0x0000, with the divider at zero. The
+    // CGB one always starts in CGB mode and leaves it through KEY0 for a
+    // DMG cartridge, after loading the palettes itself.
+    pub fn set_boot_rom(&mut self, boot_rom: BootRom) -> io::Result<()> {
+        if boot_rom.is_cgb() != self.model.is_cgb() {
+            let kind = if boot_rom.is_cgb() { "CGB" } else { "DMG" };
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("a {} boot ROM does not run on {:?}", kind, self.model)));
+        }
+
+        if self.model.is_cgb() {
+            self.cgb = true;
+            self.ppu = Ppu::with_cgb(true);
         }
+
+        self.boot_rom = Some(boot_rom);
+        self.timer = Timer::new();
+        Ok(())
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom.is_some()
+    }
+
+    // Called by the CPU for every address it puts on the bus, whether it
+    // reads, writes or only increments or decrements it.
+    pub fn corrupt_oam(&mut self, addr: u16, corruption: OamCorruption) {
+        if self.model.has_oam_bug() && (0xFE00..=0xFEFF).contains(&addr) {
+            self.ppu.corrupt_oam(corruption);
+        }
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    pub fn joypad(&self) -> &Joypad {
+        &self.joypad
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.joypad.press(button, &mut self.interrupts);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.joypad.release(button);
     }
 
     pub fn cycle(&mut self, ticks: u32) {
-		self.timer.cycle(ticks);
+		let counter = self.timer.counter();
+		self.timer.cycle(ticks, &mut self.interrupts);
+		self.clock_frame_sequencer(counter);
+
+		// DMA moves a byte per M-cycle at either speed.
+		for _ in 0..ticks / 4 {
+			self.step_dma();
+			self.step_hdma();
+		}
 
-		self.interrupt_flag |= self.timer.interrupt;
-		
-        self.timer.interrupt = 0;
+		// The PPU and APU keep their pace when the CPU runs at double speed.
+		let dots = if self.double_speed { ticks / 2 } else { ticks };
+		let mode = self.ppu.mode();
+		self.ppu.cycle(dots, &mut self.interrupts);
+		self.apu.cycle(dots);
+
+		if mode == Mode::Transfer && self.ppu.mode() == Mode::HBlank {
+			self.hdma.hblank();
+		}
 	}
 
+    // Set while a VRAM DMA block is copied, the CPU does nothing meanwhile.
+    pub fn hdma_copying(&self) -> bool {
+        self.hdma.copying()
+    }
+
+    fn step_hdma(&mut self) {
+        let bytes = if self.double_speed { 1 } else { 2 };
+
+        for _ in 0..bytes {
+            if let Some((source, offset)) = self.hdma.next_byte() {
+                let value = self.load8_direct(source);
+                self.ppu.store_vram(offset, value);
+            }
+        }
+    }
+
+    // The APU frame sequencer steps when bit 12 of the system counter, DIV
+    // bit 4, falls (bit 13 at double speed), either by counting or because
+    // DIV was reset.
+    fn clock_frame_sequencer(&mut self, old_counter: u16) {
+        let bit = if self.double_speed { 0x2000 } else { 0x1000 };
+
+        if old_counter & bit != 0 && self.timer.counter() & bit == 0 {
+            self.apu.step_frame_sequencer();
+        }
+    }
+
+    fn step_dma(&mut self) {
+        if let Some((source, offset)) = self.dma.step() {
+            // Sources past WRAM read the echo of it.
+            let source = if source >= 0xE000 { source - 0x2000 } else { source };
+            let value = self.load8_direct(source);
+
+            self.dma.set_value(value);
+            self.ppu.dma_store_oam(offset, value);
+        }
+    }
+
+    // While OAM DMA runs the CPU only reaches IO and HRAM. Reads on the bus
+    // the transfer uses, external or VRAM, see the byte being copied and
+    // OAM reads 0xFF.
+    fn dma_conflict(&self, addr: u16) -> Option<u8> {
+        if !self.dma.active() || addr >= 0xFF00 {
+            return None;
+        }
+
+        if addr >= 0xFE00 {
+            return Some(0xFF);
+        }
+
+        let vram_source = map::VRAM.contains(self.dma.source()).is_some();
+        let vram_access = map::VRAM.contains(addr).is_some();
+
+        if vram_source == vram_access {
+            Some(self.dma.value())
+        } else {
+            None
+        }
+    }
+
+    // Called by STOP. Returns true if KEY1 was armed and the speed changed.
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.speed_switch_armed {
+            return false;
+        }
+
+        self.speed_switch_armed = false;
+        self.double_speed = !self.double_speed;
+        true
+    }
+
+    fn key1(&self) -> u8 {
+        if !self.cgb {
+            return 0xFF;
+        }
+
+        let speed = if self.double_speed { 0x80 } else { 0 };
+        let armed = if self.speed_switch_armed { 0x01 } else { 0 };
+        0x7E | speed | armed
+    }
+
     pub fn load8(&self, addr: u16) -> u8 {
-        if let Some(offset) = map::ROM.contains(addr) {
-            return self.rom.load8(offset);
+        if let Some(value) = self.dma_conflict(addr) {
+            return value;
+        }
+
+        self.load8_direct(addr)
+    }
+
+    fn load8_direct(&self, addr: u16) -> u8 {
+        if let Some(value) = self.boot_rom.as_ref().and_then(|boot_rom| boot_rom.load8(addr)) {
+            return value;
+        }
+
+        if map::ROM.contains(addr).is_some() || map::SROM.contains(addr).is_some() {
+            return self.cartridge.load_rom(addr);
+        }
+
+        if let Some(offset) = map::VRAM.contains(addr) {
+            return self.ppu.load_vram(offset);
+        }
+
+        if let Some(offset) = map::OAM.contains(addr) {
+            return self.ppu.load_oam(offset);
+        }
+
+        if map::NV.contains(addr).is_some() {
+            return 0x00;
         }
 
         if let Some(offset) = map::HRAM.contains(addr) {
@@ -80,99 +333,429 @@ impl Interconnect {
         }
 
         if let Some(offset) = map::ERAM.contains(addr) {
-            return self.eram.load8(offset);
+            return self.cartridge.load_ram(offset);
+        }
+
+        if let Some(offset) = map::WRAM.contains(addr) {
+            return self.wram.load8(offset);
+        }
+
+        // Echo RAM mirrors 0xC000-0xDDFF.
+        if let Some(offset) = map::ECHO.contains(addr) {
+            return self.wram.load8(offset);
         }
 
         if let Some(offset) = map::IO.contains(addr) {
             match addr {
+                0xFF00 => return self.joypad.rb(addr),
                 0xFF01 => return self.sdt.rb(addr),
                 0xFF02 => return self.sdt.rb(addr),
                 0xFF04 => return self.timer.rb(addr),
                 0xFF05 => return self.timer.rb(addr),
                 0xFF06 => return self.timer.rb(addr),
                 0xFF07 => return self.timer.rb(addr),
+                0xFF0F => return self.interrupts.rb(addr),
+                0xFF10..=0xFF3F => return self.apu.rb(addr),
+                0xFF40..=0xFF45 | 0xFF47..=0xFF4B => return self.ppu.rb(addr),
+                0xFF46 => return self.dma.rb(addr),
+                0xFF4C => return 0xFF,
+                0xFF4D => return self.key1(),
+                0xFF4F | 0xFF68..=0xFF6B => return self.ppu.rb(addr),
+                0xFF50 => return 0xFF,
+                0xFF51..=0xFF55 if self.cgb => return self.hdma.rb(addr),
+                0xFF70 if self.cgb => return self.wram.rb(addr),
+                0xFF51..=0xFF55 | 0xFF70 => return 0xFF,
                 _ => println!("Load IO part not implemented addr: {:#x} offset: {:#x}", addr, offset),
             };
             return 0;
         }
 
-        if 0xFF0F == addr {
-            return self.interrupt_flag;
-        }
-
         if 0xFFFF == addr {
-            return self.interrupt_enable;
+            return self.interrupts.rb(addr);
         }
 
         panic!("Unhandled load 8bit address {:#x}", addr);
     }
 
-    pub fn load16(&self, addr: u16) -> u16 {
-        if let Some(offset) = map::ROM.contains(addr) {
-            return self.rom.load16(offset);
+    pub fn store8(&mut self, addr: u16, value: u8) {
+        if self.dma_conflict(addr).is_some() {
+            return;
         }
 
-        if let Some(offset) = map::HRAM.contains(addr) {
-            return self.hram.load16(offset);
+        if map::ROM.contains(addr).is_some() || map::SROM.contains(addr).is_some() {
+            return self.cartridge.store_rom(addr, value);
         }
 
-        if let Some(offset) = map::ERAM.contains(addr) {
-            return self.eram.load16(offset);
+        if let Some(offset) = map::VRAM.contains(addr) {
+            return self.ppu.store_vram(offset, value);
         }
 
-        if let Some(offset) = map::WRAM.contains(addr) {
-            return self.wram.load16(offset);
+        if let Some(offset) = map::OAM.contains(addr) {
+            return self.ppu.store_oam(offset, value);
         }
 
-        if let Some(offset) = map::ECHO.contains(addr) {
-            return self.echo.load16(offset);
+        if map::NV.contains(addr).is_some() {
+            return;
         }
 
-        panic!("Unhandled load 16bit address {:#x}", addr);
-    }
-
-    pub fn store8(&mut self, addr: u16, value: u8) {
         if let Some(offset) = map::WRAM.contains(addr) {
-            if offset >= 0xC000 && offset < 0xDE01 {
-                let addr = offset + 0x2000;
-                self.echo.store8(addr, value)
-            }
             return self.wram.store8(offset, value);
         }
 
         if let Some(offset) = map::ECHO.contains(addr) {
-            if offset >= 0xE000 && offset < 0xFE01 {
-                let addr = offset - 0x2000;
-                self.wram.store8(addr, value)
-            }
-            return self.echo.store8(offset, value);
+            return self.wram.store8(offset, value);
         }
 
         if let Some(offset) = map::ERAM.contains(addr) {
-            return self.eram.store8(offset, value);
+            return self.cartridge.store_ram(offset, value);
+        }
+
+        if let Some(offset) = map::HRAM.contains(addr) {
+            return self.hram.store8(offset, value);
         }
 
         if let Some(offset) = map::IO.contains(addr) {
             match addr {
+                0xFF00 => { return self.joypad.wb(addr, value, &mut self.interrupts); },
                 0xFF01 => { return self.sdt.wb(addr, value); },
                 0xFF02 => { return self.sdt.wb(addr, value); },
-                0xFF04 => { return self.timer.wb(addr, value); },
+                0xFF04 => {
+                    let counter = self.timer.counter();
+                    self.timer.wb(addr, value);
+                    return self.clock_frame_sequencer(counter);
+                },
                 0xFF05 => { return self.timer.wb(addr, value); },
                 0xFF06 => { return self.timer.wb(addr, value); },
                 0xFF07 => { return self.timer.wb(addr, value); },
+                0xFF0F => { return self.interrupts.wb(addr, value); },
+                0xFF10..=0xFF3F => { return self.apu.wb(addr, value); },
+                0xFF40..=0xFF45 | 0xFF47..=0xFF4B => { return self.ppu.wb(addr, value, &mut self.interrupts); },
+                0xFF46 => { return self.dma.wb(addr, value); },
+                // KEY0, the CGB boot ROM sets bit 2 for DMG compatibility
+                // mode. Locked once the boot ROM is unmapped.
+                0xFF4C => {
+                    if self.model.is_cgb() && self.boot_rom.is_some() && value & 0x04 != 0 {
+                        self.cgb = false;
+                        self.ppu.set_dmg_mode();
+                    }
+                    return;
+                },
+                0xFF4D => { return self.speed_switch_armed = self.cgb && value & 0x01 != 0; },
+                0xFF4F | 0xFF68..=0xFF6B => { return self.ppu.wb(addr, value, &mut self.interrupts); },
+                // Any write with bit 0 set unmaps the boot ROM for good.
+                0xFF50 => {
+                    if value & 0x01 != 0 {
+                        self.boot_rom = None;
+                    }
+                    return;
+                },
+                0xFF51..=0xFF55 if self.cgb => {
+                    let in_hblank = self.ppu.mode() == Mode::HBlank;
+                    return self.hdma.wb(addr, value, in_hblank);
+                },
+                0xFF70 if self.cgb => { return self.wram.wb(addr, value); },
+                0xFF51..=0xFF55 | 0xFF70 => { return; },
                 _ => println!("Store IO part not implemented addr: {:#x} offset: {:#x}", addr, offset),
             }
             return;
         }
 
-        if 0xFF0F == addr {
-            return self.interrupt_flag = value;
-        }
-
         if 0xFFFF == addr {
-            return self.interrupt_enable = value;
+            return self.interrupts.wb(addr, value);
         }
 
         panic!("Unhandled store 8bit address {:#x}", addr);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rom::Rom;
+
+    fn interconnect(model: EmulatedModel, cgb_flag: u8) -> Interconnect {
+        let mut data = vec![0; 0x8000];
+        data[0x0143] = cgb_flag;
+        let cartridge = Cartridge::new(Rom::from_bytes(data).unwrap());
+        Interconnect::with_model(cartridge, model)
+    }
+
+    fn boot_rom(size: usize) -> BootRom {
+        BootRom::from_bytes(vec![0; size]).unwrap()
+    }
+
+    #[test]
+    fn boot_rom_must_match_model() {
+        let mut inter = interconnect(EmulatedModel::Dmg, 0x00);
+        assert!(inter.set_boot_rom(boot_rom(0x900)).is_err());
+        assert!(inter.set_boot_rom(boot_rom(0x100)).is_ok());
+
+        let mut inter = interconnect(EmulatedModel::Agb, 0x00);
+        assert!(inter.set_boot_rom(boot_rom(0x100)).is_err());
+        assert!(inter.set_boot_rom(boot_rom(0x900)).is_ok());
+    }
+
+    #[test]
+    fn key0_selects_dmg_mode() {
+        let mut inter = interconnect(EmulatedModel::Cgb, 0x00);
+        assert!(!inter.cgb());
+        inter.set_boot_rom(boot_rom(0x900)).unwrap();
+        assert!(inter.cgb());
+
+        inter.store8(0xFF4C, 0x04);
+        assert!(!inter.cgb());
+        assert!(inter.ppu().compat());
+        assert_eq!(inter.load8(0xFF4C), 0xFF);
+    }
+
+    #[test]
+    fn key0_locked_after_boot() {
+        let mut inter = interconnect(EmulatedModel::Cgb, 0x80);
+        inter.set_boot_rom(boot_rom(0x900)).unwrap();
+        inter.store8(0xFF4C, 0x80);
+        assert!(inter.cgb());
+
+        inter.store8(0xFF50, 0x01);
+        inter.store8(0xFF4C, 0x04);
+        assert!(inter.cgb());
+        assert!(!inter.ppu().compat());
+    }
+
+    // Fills C000-C09F with 1 plus the offset and starts a copy from it.
+    fn start_dma() -> Interconnect {
+        let mut inter = interconnect(EmulatedModel::Dmg, 0x00);
+        for offset in 0..0xA0 {
+            inter.store8(0xC000 + offset, 1 + offset as u8);
+        }
+        inter.store8(0xD000, 0x11);
+        inter.store8(0x8000, 0x22);
+        inter.store8(0xFF80, 0x33);
+        inter.store8(0xFF46, 0xC0);
+        inter
+    }
+
+    fn m_cycles(inter: &mut Interconnect, count: u32) {
+        for _ in 0..count {
+            inter.cycle(4);
+        }
+    }
+
+    #[test]
+    fn dma_leaves_cpu_hram_and_io() {
+        let mut inter = start_dma();
+        m_cycles(&mut inter, 3);
+
+        assert_eq!(inter.load8(0xFF80), 0x33);
+        inter.store8(0xFF81, 0x44);
+        assert_eq!(inter.load8(0xFF81), 0x44);
+        assert_eq!(inter.load8(0xFF46), 0xC0);
+    }
+
+    // Two M-cycles in, the first byte is copied. Reads on the same bus see
+    // the byte the transfer last put on it, writes are lost, and the
+    // other bus is unaffected.
+    #[test]
+    fn dma_bus_conflicts() {
+        let mut inter = start_dma();
+        m_cycles(&mut inter, 1);
+        assert_eq!(inter.load8(0xD000), 0x11);
+
+        m_cycles(&mut inter, 1);
+        assert_eq!(inter.load8(0xD000), 0x01);
+        assert_eq!(inter.load8(0x0000), 0x01);
+        assert_eq!(inter.load8(0xFE00), 0xFF);
+        assert_eq!(inter.load8(0x8000), 0x22);
+
+        inter.store8(0xD000, 0x55);
+        inter.store8(0x8001, 0x66);
+        m_cycles(&mut inter, 200);
+        assert_eq!(inter.load8(0xD000), 0x11);
+        assert_eq!(inter.load8(0x8001), 0x66);
+    }
+
+    // The copy runs from the second to the 161st M-cycle, OAM is released
+    // the cycle after.
+    #[test]
+    fn dma_takes_160_m_cycles() {
+        let mut inter = start_dma();
+        m_cycles(&mut inter, 160);
+        assert_eq!(inter.ppu().load_oam(0x9E), 0x9F);
+        assert_eq!(inter.ppu().load_oam(0x9F), 0x00);
+
+        m_cycles(&mut inter, 1);
+        assert_eq!(inter.ppu().load_oam(0x9F), 0xA0);
+        assert_eq!(inter.load8(0xFE00), 0xFF);
+
+        m_cycles(&mut inter, 1);
+        assert_eq!(inter.load8(0xFE00), 0x01);
+        assert_eq!(inter.load8(0xFE9F), 0xA0);
+        assert_eq!(inter.load8(0xD000), 0x11);
+    }
+
+    // A CGB with C000-C07F holding 1 plus the offset, set up to copy from
+    // there to 0x8000.
+    fn vram_dma() -> Interconnect {
+        let mut inter = interconnect(EmulatedModel::Cgb, 0x80);
+        for offset in 0..0x80 {
+            inter.store8(0xC000 + offset, 1 + offset as u8);
+        }
+        inter.store8(0xFF51, 0xC0);
+        inter.store8(0xFF52, 0x00);
+        inter.store8(0xFF53, 0x80);
+        inter.store8(0xFF54, 0x00);
+        inter
+    }
+
+    // M-cycles the CPU is held for.
+    fn stall(inter: &mut Interconnect) -> u32 {
+        let mut cycles = 0;
+        while inter.hdma_copying() {
+            inter.cycle(4);
+            cycles += 1;
+        }
+        cycles
+    }
+
+    fn next_hblank(inter: &mut Interconnect) {
+        while inter.ppu().mode() == Mode::HBlank {
+            inter.cycle(4);
+        }
+        while inter.ppu().mode() != Mode::HBlank {
+            inter.cycle(4);
+        }
+    }
+
+    #[test]
+    fn general_dma_stalls_the_cpu() {
+        let mut inter = vram_dma();
+        inter.store8(0xFF55, 0x01);
+        assert_eq!(stall(&mut inter), 16);
+
+        assert_eq!(inter.load8(0xFF55), 0xFF);
+        assert_eq!(inter.load8(0x8000), 0x01);
+        assert_eq!(inter.load8(0x801F), 0x20);
+        assert_eq!(inter.load8(0x8020), 0x00);
+    }
+
+    // Twice the M-cycles at double speed, the same time in dots.
+    #[test]
+    fn general_dma_at_double_speed() {
+        let mut inter = vram_dma();
+        inter.double_speed = true;
+        inter.store8(0xFF55, 0x01);
+        assert_eq!(stall(&mut inter), 32);
+        assert_eq!(inter.load8(0x801F), 0x20);
+    }
+
+    #[test]
+    fn hblank_dma_copies_a_block_per_hblank() {
+        let mut inter = vram_dma();
+        inter.store8(0xFF40, 0x80);
+        inter.store8(0xFF55, 0x82);
+        assert!(!inter.hdma_copying());
+        assert_eq!(inter.load8(0xFF55), 0x02);
+
+        next_hblank(&mut inter);
+        assert_eq!(stall(&mut inter), 8);
+        assert_eq!(inter.load8(0xFF55), 0x01);
+        assert_eq!(inter.load8(0x800F), 0x10);
+        assert_eq!(inter.load8(0x8010), 0x00);
+
+        next_hblank(&mut inter);
+        assert_eq!(stall(&mut inter), 8);
+        assert_eq!(inter.load8(0xFF55), 0x00);
+        assert_eq!(inter.load8(0x801F), 0x20);
+
+        next_hblank(&mut inter);
+        assert_eq!(stall(&mut inter), 8);
+        assert_eq!(inter.load8(0xFF55), 0xFF);
+        assert_eq!(inter.load8(0x802F), 0x30);
+
+        next_hblank(&mut inter);
+        assert!(!inter.hdma_copying());
+    }
+
+    // Clearing bit 7 stops an HBlank transfer, FF55 then reads bit 7 set
+    // over the blocks that were left.
+    #[test]
+    fn hblank_dma_cancel() {
+        let mut inter = vram_dma();
+        inter.store8(0xFF40, 0x80);
+        inter.store8(0xFF55, 0x83);
+
+        next_hblank(&mut inter);
+        stall(&mut inter);
+        inter.store8(0xFF55, 0x00);
+        assert_eq!(inter.load8(0xFF55), 0x82);
+
+        next_hblank(&mut inter);
+        assert!(!inter.hdma_copying());
+        assert_eq!(inter.load8(0x8010), 0x00);
+    }
+
+    // With the LCD off the first block goes right away.
+    #[test]
+    fn hblank_dma_with_lcd_off() {
+        let mut inter = vram_dma();
+        inter.store8(0xFF55, 0x81);
+        assert_eq!(stall(&mut inter), 8);
+        assert_eq!(inter.load8(0xFF55), 0x00);
+        assert_eq!(inter.load8(0x800F), 0x10);
+    }
+
+    // Restarts the APU and the system counter, then starts channel 2 one
+    // length clock from expiring.
+    fn expiring_square2(inter: &mut Interconnect) {
+        inter.store8(0xFF26, 0x00);
+        inter.store8(0xFF26, 0x80);
+        inter.store8(0xFF04, 0x00);
+        inter.store8(0xFF16, 0x3F);
+        inter.store8(0xFF17, 0xF0);
+        inter.store8(0xFF19, 0xC0);
+    }
+
+    #[test]
+    fn frame_sequencer_follows_div_bit_4() {
+        let mut inter = interconnect(EmulatedModel::Dmg, 0x00);
+        expiring_square2(&mut inter);
+
+        // Bit 12 rises at 0x1000 T-cycles and falls at 0x2000.
+        m_cycles(&mut inter, 0x2000 / 4 - 1);
+        assert_eq!(inter.load8(0xFF26) & 0x02, 0x02);
+        m_cycles(&mut inter, 1);
+        assert_eq!(inter.load8(0xFF26) & 0x02, 0x00);
+    }
+
+    #[test]
+    fn div_reset_steps_frame_sequencer() {
+        let mut inter = interconnect(EmulatedModel::Dmg, 0x00);
+        expiring_square2(&mut inter);
+
+        // Resetting with bit 12 clear is not an edge.
+        m_cycles(&mut inter, 0x1000 / 4 - 1);
+        inter.store8(0xFF04, 0x00);
+        assert_eq!(inter.load8(0xFF26) & 0x02, 0x02);
+
+        m_cycles(&mut inter, 0x1000 / 4);
+        inter.store8(0xFF04, 0x00);
+        assert_eq!(inter.load8(0xFF26) & 0x02, 0x00);
+    }
+
+    #[test]
+    fn svbk_only_on_cgb() {
+        let mut inter = interconnect(EmulatedModel::Dmg, 0x00);
+        inter.store8(0xD000, 0x11);
+        inter.store8(0xFF70, 0x02);
+        assert_eq!(inter.load8(0xFF70), 0xFF);
+        assert_eq!(inter.load8(0xD000), 0x11);
+
+        let mut inter = interconnect(EmulatedModel::Cgb, 0x80);
+        inter.store8(0xD000, 0x11);
+        inter.store8(0xFF70, 0x02);
+        assert_eq!(inter.load8(0xFF70), 0xFA);
+        assert_eq!(inter.load8(0xD000), 0x00);
+        assert_eq!(inter.load8(0xF000), 0x00);
+        inter.store8(0xFF70, 0x00);
+        assert_eq!(inter.load8(0xF000), 0x11);
+    }
+}
@@ -0,0 +1,124 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+// Highest priority first, matching the bit order in IE and IF.
+const PRIORITY: [Interrupt; 5] = [
+    Interrupt::VBlank,
+    Interrupt::LcdStat,
+    Interrupt::Timer,
+    Interrupt::Serial,
+    Interrupt::Joypad,
+];
+
+impl Interrupt {
+    fn mask(self) -> u8 {
+        match self {
+            Interrupt::VBlank => 0x01,
+            Interrupt::LcdStat => 0x02,
+            Interrupt::Timer => 0x04,
+            Interrupt::Serial => 0x08,
+            Interrupt::Joypad => 0x10,
+        }
+    }
+
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x0040,
+            Interrupt::LcdStat => 0x0048,
+            Interrupt::Timer => 0x0050,
+            Interrupt::Serial => 0x0058,
+            Interrupt::Joypad => 0x0060,
+        }
+    }
+}
+
+pub struct InterruptController {
+    enable: u8,
+    flag: u8,
+}
+
+impl InterruptController {
+    pub fn new() -> InterruptController {
+        InterruptController {
+            enable: 0x00,
+            flag: 0x00,
+        }
+    }
+
+    pub fn request(&mut self, interrupt: Interrupt) {
+        self.flag |= interrupt.mask();
+    }
+
+    pub fn is_requested(&self, interrupt: Interrupt) -> bool {
+        self.flag & interrupt.mask() != 0
+    }
+
+    pub fn acknowledge(&mut self, interrupt: Interrupt) {
+        self.flag &= !interrupt.mask();
+    }
+
+    // The highest priority interrupt that is both requested and enabled.
+    pub fn pending(&self) -> Option<Interrupt> {
+        let pending = self.enable & self.flag;
+
+        PRIORITY.iter().cloned().find(|interrupt| pending & interrupt.mask() != 0)
+    }
+
+    pub fn rb(&self, a: u16) -> u8 {
+        match a {
+            0xFF0F => self.flag | 0xE0,
+            0xFFFF => self.enable,
+            _ => panic!("Interrupt controller does not handle read {:4X}", a),
+        }
+    }
+
+    pub fn wb(&mut self, a: u16, v: u8) {
+        match a {
+            0xFF0F => self.flag = v & 0x1F,
+            0xFFFF => self.enable = v,
+            _ => panic!("Interrupt controller does not handle write {:4X}", a),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_needs_enable_and_flag() {
+        let mut interrupts = InterruptController::new();
+        interrupts.request(Interrupt::Serial);
+        assert_eq!(interrupts.pending(), None);
+
+        interrupts.wb(0xFFFF, 0x1F);
+        assert_eq!(interrupts.pending(), Some(Interrupt::Serial));
+
+        interrupts.request(Interrupt::LcdStat);
+        assert_eq!(interrupts.pending(), Some(Interrupt::LcdStat));
+
+        interrupts.acknowledge(Interrupt::LcdStat);
+        assert_eq!(interrupts.pending(), Some(Interrupt::Serial));
+    }
+
+    #[test]
+    fn flag_register_bits() {
+        let mut interrupts = InterruptController::new();
+        assert_eq!(interrupts.rb(0xFF0F), 0xE0);
+
+        interrupts.wb(0xFF0F, 0xFF);
+        assert_eq!(interrupts.rb(0xFF0F), 0xFF);
+        assert!(interrupts.is_requested(Interrupt::Joypad));
+
+        // IE keeps all eight bits.
+        interrupts.wb(0xFFFF, 0xE4);
+        assert_eq!(interrupts.rb(0xFFFF), 0xE4);
+        assert_eq!(interrupts.pending(), Some(Interrupt::Timer));
+    }
+}
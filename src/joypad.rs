@@ -0,0 +1,204 @@
+use interrupt::{Interrupt, InterruptController};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    // Directions in the low nibble and buttons in the high one, each in
+    // the order of their P1 input line.
+    fn mask(self) -> u8 {
+        match self {
+            Button::Right => 0x01,
+            Button::Left => 0x02,
+            Button::Up => 0x04,
+            Button::Down => 0x08,
+            Button::A => 0x10,
+            Button::B => 0x20,
+            Button::Select => 0x40,
+            Button::Start => 0x80,
+        }
+    }
+}
+
+const SELECT_DIRECTIONS: u8 = 0x10;
+const SELECT_BUTTONS: u8 = 0x20;
+
+// P1 is a 2x4 key matrix. Writing 0 to bit 4 or 5 selects a row, the low
+// nibble reads 0 for every pressed key in the selected rows.
+pub struct Joypad {
+    select: u8,
+    pressed: u8,
+}
+
+impl Default for Joypad {
+    fn default() -> Joypad {
+        Joypad::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        // Both rows are left selected after boot, P1 reads 0xCF.
+        Joypad {
+            select: 0x00,
+            pressed: 0,
+        }
+    }
+
+    // Input lines pulled low, as a mask of the low nibble.
+    fn lines(&self) -> u8 {
+        let mut lines = 0;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            lines |= self.pressed & 0x0F;
+        }
+        if self.select & SELECT_BUTTONS == 0 {
+            lines |= self.pressed >> 4;
+        }
+        lines
+    }
+
+    // STOP ends as soon as any input line is low.
+    pub fn input_low(&self) -> bool {
+        self.lines() != 0
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.pressed & button.mask() != 0
+    }
+
+    pub fn press(&mut self, button: Button, interrupts: &mut InterruptController) {
+        let lines = self.lines();
+        self.pressed |= button.mask();
+        self.check_interrupt(lines, interrupts);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.pressed &= !button.mask();
+    }
+
+    // The interrupt fires when a line goes from high to low, whether by a
+    // key press or by selecting a row with a key already held.
+    fn check_interrupt(&self, old_lines: u8, interrupts: &mut InterruptController) {
+        if self.lines() & !old_lines != 0 {
+            interrupts.request(Interrupt::Joypad);
+        }
+    }
+
+    pub fn rb(&self, a: u16) -> u8 {
+        match a {
+            0xFF00 => 0xC0 | self.select | (!self.lines() & 0x0F),
+            _ => panic!("Joypad does not handle read {:4X}", a),
+        }
+    }
+
+    pub fn wb(&mut self, a: u16, v: u8, interrupts: &mut InterruptController) {
+        match a {
+            0xFF00 => {
+                let lines = self.lines();
+                self.select = v & (SELECT_DIRECTIONS | SELECT_BUTTONS);
+                self.check_interrupt(lines, interrupts);
+            }
+            _ => panic!("Joypad does not handle write {:4X}", a),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pressed(buttons: &[Button]) -> (Joypad, InterruptController) {
+        let mut joypad = Joypad::new();
+        let mut interrupts = InterruptController::new();
+        joypad.wb(0xFF00, 0x30, &mut interrupts);
+        for &button in buttons {
+            joypad.press(button, &mut interrupts);
+        }
+        (joypad, InterruptController::new())
+    }
+
+    #[test]
+    fn reads_selected_rows() {
+        let (mut joypad, mut interrupts) = pressed(&[Button::Right, Button::Start]);
+        assert_eq!(joypad.rb(0xFF00), 0xFF);
+
+        joypad.wb(0xFF00, 0x20, &mut interrupts);
+        assert_eq!(joypad.rb(0xFF00), 0xEE);
+
+        joypad.wb(0xFF00, 0x10, &mut interrupts);
+        assert_eq!(joypad.rb(0xFF00), 0xD7);
+
+        joypad.wb(0xFF00, 0x00, &mut interrupts);
+        assert_eq!(joypad.rb(0xFF00), 0xC6);
+
+        // Only the select bits are written.
+        joypad.wb(0xFF00, 0xFF, &mut interrupts);
+        assert_eq!(joypad.rb(0xFF00), 0xFF);
+    }
+
+    #[test]
+    fn boots_with_both_rows_selected() {
+        assert_eq!(Joypad::new().rb(0xFF00), 0xCF);
+    }
+
+    // Only a line going low requests the interrupt, a key in a row that
+    // is not selected does not.
+    #[test]
+    fn press_interrupts() {
+        let (mut joypad, mut interrupts) = pressed(&[]);
+        joypad.wb(0xFF00, 0x20, &mut interrupts);
+
+        joypad.press(Button::A, &mut interrupts);
+        assert!(!interrupts.is_requested(Interrupt::Joypad));
+
+        joypad.press(Button::Down, &mut interrupts);
+        assert!(interrupts.is_requested(Interrupt::Joypad));
+
+        // Releasing raises the line, pressing again is a new edge.
+        interrupts.acknowledge(Interrupt::Joypad);
+        joypad.release(Button::Down);
+        assert!(!interrupts.is_requested(Interrupt::Joypad));
+        joypad.press(Button::Down, &mut interrupts);
+        assert!(interrupts.is_requested(Interrupt::Joypad));
+    }
+
+    #[test]
+    fn select_with_key_held_interrupts() {
+        let (mut joypad, mut interrupts) = pressed(&[Button::B]);
+        joypad.wb(0xFF00, 0x20, &mut interrupts);
+        assert!(!interrupts.is_requested(Interrupt::Joypad));
+
+        joypad.wb(0xFF00, 0x10, &mut interrupts);
+        assert!(interrupts.is_requested(Interrupt::Joypad));
+
+        // Already low with both rows selected, no new edge.
+        interrupts.acknowledge(Interrupt::Joypad);
+        joypad.wb(0xFF00, 0x00, &mut interrupts);
+        assert!(!interrupts.is_requested(Interrupt::Joypad));
+    }
+
+    #[test]
+    fn input_low_needs_a_selected_row() {
+        let (mut joypad, mut interrupts) = pressed(&[Button::Select]);
+        assert!(!joypad.input_low());
+
+        joypad.wb(0xFF00, 0x20, &mut interrupts);
+        assert!(!joypad.input_low());
+
+        joypad.wb(0xFF00, 0x10, &mut interrupts);
+        assert!(joypad.input_low());
+        assert!(joypad.is_pressed(Button::Select));
+
+        joypad.release(Button::Select);
+        assert!(!joypad.input_low());
+    }
+}
@@ -0,0 +1,21 @@
+pub mod cpu;
+pub mod interconnect;
+pub mod rom;
+pub mod cartridge;
+pub mod save;
+pub mod ppu;
+pub mod apu;
+pub mod wav;
+pub mod joypad;
+pub mod model;
+pub mod boot;
+pub mod image;
+mod opcode;
+mod disasm;
+mod wram;
+mod hram;
+mod sdt;
+mod timer;
+mod dma;
+mod hdma;
+mod interrupt;
@@ -1,33 +1,224 @@
+extern crate gb;
+
 use std::env::args;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use gb::cpu::Cpu;
+use gb::interconnect::Interconnect;
+use gb::rom::Rom;
+use gb::cartridge::{Cartridge, StaticImage};
+use gb::save::SaveFile;
+use gb::ppu::{DmgPalette, Renderer, SCREEN_WIDTH, SCREEN_HEIGHT};
+use gb::image;
+use gb::apu::DEFAULT_SAMPLE_RATE;
+use gb::wav::Recorder;
+use gb::boot::BootRom;
+use gb::model::EmulatedModel;
+
+// Battery RAM is written back about once a second of emulated time.
+const SAVE_INTERVAL: u32 = 1 << 20;
+
+// Audio is written out about 60 times a second, well before the APU
+// starts dropping samples.
+const RECORD_INTERVAL: u32 = 1 << 14;
+
+// When --screenshot is written. The emulator quits right after.
+enum Trigger {
+    // Once this many frames have been completed.
+    Frame(u64),
+    // Just before the first LD B,B, which test ROMs use as a software
+    // breakpoint.
+    Breakpoint,
+    // When emulation stops, on STOP or unhandled hardware. A killed
+    // process writes nothing.
+    Exit,
+}
 
-mod cpu;
-mod interconnect;
-mod rom;
-mod opcode;
-mod wram;
-mod echo;
-mod hram;
-mod eram;
-mod sdt;
-mod timer;
-
-use cpu::Cpu;
-use interconnect::Interconnect;
-use rom::Rom;
+impl Trigger {
+    fn parse(args: &[String]) -> Trigger {
+        match (option(args, "--screenshot-frame="), option(args, "--screenshot-on=")) {
+            (Some(frame), None) => Trigger::Frame(frame.parse().expect("Invalid --screenshot-frame")),
+            (None, Some("breakpoint")) => Trigger::Breakpoint,
+            (None, Some("exit")) | (None, None) => Trigger::Exit,
+            (None, Some(other)) => panic!("Unknown screenshot trigger {}, expected breakpoint or exit", other),
+            (Some(_), Some(_)) => panic!("--screenshot-frame and --screenshot-on are exclusive"),
+        }
+    }
+
+    fn fired(&self, cpu: &Cpu) -> bool {
+        match *self {
+            Trigger::Frame(frame) => cpu.interconnect.ppu().frame_count() >= frame,
+            Trigger::Breakpoint => cpu.interconnect.load8(cpu.pc()) == 0x40,
+            Trigger::Exit => false,
+        }
+    }
+}
+
+fn screenshot(cpu: &Cpu, path: &str) {
+    let rgba = cpu.interconnect.ppu().frame_rgba();
+
+    match image::save(path, SCREEN_WIDTH, SCREEN_HEIGHT, &rgba) {
+        Ok(()) => println!("Wrote {}", path),
+        Err(err) => println!("Warning: could not write {}: {}", path, err),
+    }
+}
+
+fn option<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().find(|arg| arg.starts_with(name)).map(|arg| &arg[name.len()..])
+}
 
 fn main() {
-    let rom_file = args().nth(1).unwrap();
+    let args: Vec<String> = args().skip(1).collect();
+
+    let rom_file = args.iter().find(|arg| !arg.starts_with("--")).unwrap();
+    let trace = args.iter().any(|arg| arg == "--trace");
+    let camera = option(&args, "--camera=");
+    let save_dir = option(&args, "--save-dir=").map(Path::new);
+    let renderer = match option(&args, "--renderer=") {
+        Some("fifo") => Renderer::Fifo,
+        Some("scanline") | None => Renderer::Scanline,
+        Some(other) => panic!("Unknown renderer {}, expected scanline or fifo", other),
+    };
+    let palette = match option(&args, "--palette=") {
+        Some(text) => DmgPalette::parse(text).expect("Invalid --palette, expected grey, green or four RRGGBB colors"),
+        None => DmgPalette::default(),
+    };
+    let color_correction = args.iter().any(|arg| arg == "--color-correction");
+    let boot_rom = option(&args, "--boot-rom=");
+    let model = option(&args, "--model=").map(|name| {
+        EmulatedModel::parse(name).expect("Unknown --model, expected dmg0, dmg, mgb, sgb, sgb2, cgb or agb")
+    });
+    let force_cgb = args.iter().any(|arg| arg == "--cgb");
+    let force_dmg = args.iter().any(|arg| arg == "--dmg");
+    let screenshot_file = option(&args, "--screenshot=");
+    let wav_file = option(&args, "--wav=");
+    let wav_channels = args.iter().any(|arg| arg == "--wav-channels");
+    let sample_rate = option(&args, "--sample-rate=")
+        .map(|rate| rate.parse().expect("Invalid --sample-rate"))
+        .unwrap_or(DEFAULT_SAMPLE_RATE);
+    let trigger = Trigger::parse(&args);
+
+    let rom = Rom::new(rom_file).unwrap();
+
+    println!("{}", rom.header);
+
+    if let Err(err) = rom.validate() {
+        println!("Warning: {}", err);
+    }
+
+    let mut cartridge = Cartridge::new(rom);
+
+    if let Some(path) = camera {
+        cartridge.set_image_sensor(Box::new(StaticImage::open(path).unwrap()));
+    }
+
+    let save = SaveFile::new(rom_file, save_dir);
+
+    if let Err(err) = save.load(&mut cartridge) {
+        println!("Warning: could not load {}: {}", save.path().display(), err);
+    }
 
-    let rom = Rom::new(&rom_file).unwrap();
+    let mut inter = match (model, force_cgb, force_dmg) {
+        (Some(model), false, false) => Interconnect::with_model(cartridge, model),
+        (None, true, false) => Interconnect::with_cgb(cartridge, true),
+        (None, false, true) => Interconnect::with_cgb(cartridge, false),
+        (None, false, false) => Interconnect::new(cartridge),
+        _ => panic!("--model, --cgb and --dmg are exclusive"),
+    };
 
-    let inter = Interconnect::new(rom);
+    if let Some(path) = boot_rom {
+        inter.set_boot_rom(BootRom::open(path).unwrap()).unwrap();
+    }
 
     let mut cpu = Cpu::new(inter);
 
+    cpu.trace = trace;
+
+    cpu.interconnect.ppu_mut().set_renderer(renderer);
+    cpu.interconnect.ppu_mut().set_palette(palette);
+    cpu.interconnect.ppu_mut().set_color_correction(color_correction);
+    cpu.interconnect.apu_mut().set_sample_rate(sample_rate);
+
+    let mut recorder = wav_file.map(|path| {
+        Recorder::create(path, cpu.interconnect.apu_mut(), wav_channels).unwrap()
+    });
+
     cpu.power_up();
 
-    loop {
-        let time = cpu.cycle();
-        cpu.interconnect.cycle(time);
+    // Runs until STOP, unhandled hardware or the screenshot, then
+    // saves what we have. Battery RAM is also flushed as the game runs, so
+    // killing the process only loses the last interval. WAV files are only
+    // finished on the way out.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut cycles = 0;
+        let mut audio_cycles = 0;
+
+        loop {
+            let elapsed = cpu.cycle();
+            cycles += elapsed;
+            audio_cycles += elapsed;
+
+            // Nothing presses a key here, so STOP is never woken from.
+            if cpu.stopped {
+                println!("Stopped with no joypad input to resume");
+                return;
+            }
+
+            if audio_cycles >= RECORD_INTERVAL {
+                audio_cycles = 0;
+
+                // A full or failing file is finished and recording stops,
+                // the emulation carries on.
+                if let Some(mut active) = recorder.take() {
+                    match active.record(cpu.interconnect.apu_mut()) {
+                        Ok(()) => recorder = Some(active),
+                        Err(err) => {
+                            println!("Warning: stopped recording {}: {}", wav_file.unwrap(), err);
+                            if let Err(err) = active.finish() {
+                                println!("Warning: could not write {}: {}", wav_file.unwrap(), err);
+                            }
+                        }
+                    }
+                }
+            }
+
+            if let Some(path) = screenshot_file {
+                if trigger.fired(&cpu) {
+                    screenshot(&cpu, path);
+                    return;
+                }
+            }
+
+            if cycles >= SAVE_INTERVAL {
+                cycles = 0;
+
+                if let Err(err) = save.flush(cpu.interconnect.cartridge_mut()) {
+                    println!("Warning: could not write {}: {}", save.path().display(), err);
+                }
+            }
+        }
+    }));
+
+    if let Some(path) = screenshot_file {
+        if let Trigger::Exit = trigger {
+            screenshot(&cpu, path);
+        }
+    }
+
+    if let Err(err) = save.flush(cpu.interconnect.cartridge_mut()) {
+        println!("Warning: could not write {}: {}", save.path().display(), err);
+    }
+
+    if let Some(mut recorder) = recorder {
+        let finished = recorder.record(cpu.interconnect.apu_mut()).and_then(|_| recorder.finish());
+
+        if let Err(err) = finished {
+            println!("Warning: could not write {}: {}", wav_file.unwrap(), err);
+        }
+    }
+
+    if let Err(panic) = result {
+        panic::resume_unwind(panic);
     }
 }
@@ -0,0 +1,39 @@
+// The hardware being emulated. Decides whether CGB mode is available, the
+// state the boot ROM leaves behind when it is skipped and a few quirks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmulatedModel {
+    Dmg0,
+    Dmg,
+    Mgb,
+    Sgb,
+    Sgb2,
+    Cgb,
+    Agb,
+}
+
+impl EmulatedModel {
+    pub fn is_cgb(self) -> bool {
+        matches!(self, EmulatedModel::Cgb | EmulatedModel::Agb)
+    }
+
+    // Takes the names used on the command line: dmg0, dmg, mgb, sgb, sgb2,
+    // cgb and agb.
+    pub fn parse(text: &str) -> Option<EmulatedModel> {
+        match text {
+            "dmg0" => Some(EmulatedModel::Dmg0),
+            "dmg" => Some(EmulatedModel::Dmg),
+            "mgb" => Some(EmulatedModel::Mgb),
+            "sgb" => Some(EmulatedModel::Sgb),
+            "sgb2" => Some(EmulatedModel::Sgb2),
+            "cgb" => Some(EmulatedModel::Cgb),
+            "agb" => Some(EmulatedModel::Agb),
+            _ => None,
+        }
+    }
+
+    // Only the monochrome models corrupt OAM when the CPU puts an address
+    // in 0xFE00-0xFEFF on the bus during the OAM scan.
+    pub fn has_oam_bug(self) -> bool {
+        !self.is_cgb()
+    }
+}
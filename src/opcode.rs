@@ -1,172 +1,384 @@
-use std::mem;
+// Opcodes are decoded from their bit fields rather than a lookup of named
+// variants, see https://gb-archive.github.io/salvage/decoding_gbz80_opcodes/
+//
+//   7 6 5 4 3 2 1 0
+//   x x y y y z z z
+//       p p q
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Opcode {
-    nop = 0x00,
-
-    ld_bc_nn = 0x01,
-    ld_de_nn = 0x11,
-    ld_hl_nn = 0x21,
-    ld_sp_nn = 0x31,
-
-    ld_a_a = 0x7F,
-    ld_a_b = 0x78,
-    ld_a_c = 0x79,
-    ld_a_d = 0x7A,
-    ld_a_e = 0x7B,
-    ld_a_h = 0x7C,
-    ld_a_l = 0x7D,
-    ld_a_bc = 0x0A,
-    ld_a_de = 0x1A,
-    ld_a_nn = 0xFA,
-    ld_a_sharp = 0x3E,
-    ld_a_hl = 0x7E,
-    ld_b_b = 0x40,
-    ld_b_c = 0x41,
-    ld_b_d = 0x42,
-    ld_b_e = 0x43,
-    ld_b_h = 0x44,
-    ld_b_l = 0x45,
-    ld_b_hl = 0x46,
-    ld_c_b = 0x48,
-    ld_c_c = 0x49,
-    ld_c_d = 0x4A,
-    ld_c_e = 0x4B,
-    ld_c_h = 0x4C,
-    ld_c_l = 0x4D,
-    ld_c_hl = 0x4E,
-    ld_d_b = 0x50,
-    ld_d_c = 0x51,
-    ld_d_d = 0x52,
-    ld_d_e = 0x53,
-    ld_d_h = 0x54,
-    ld_d_l = 0x55,
-    ld_d_hl = 0x56,
-    ld_e_b = 0x58,
-    ld_e_c = 0x59,
-    ld_e_d = 0x5A,
-    ld_e_e = 0x5B,
-    ld_e_h = 0x5C,
-    ld_e_l = 0x5D,
-    ld_e_hl = 0x5E,
-    ld_h_b = 0x60,
-    ld_h_c = 0x61,
-    ld_h_d = 0x62,
-    ld_h_e = 0x63,
-    ld_h_h = 0x64,
-    ld_h_l = 0x65,
-    ld_h_hl = 0x66,
-    ld_l_b = 0x68,
-    ld_l_c = 0x69,
-    ld_l_d = 0x6A,
-    ld_l_e = 0x6B,
-    ld_l_h = 0x6C,
-    ld_l_l = 0x6D,
-    ld_l_hl = 0x6E,
-    ld_hl_b = 0x70,
-    ld_hl_c = 0x71,
-    ld_hl_d = 0x72,
-    ld_hl_e = 0x73,
-    ld_hl_h = 0x74,
-    ld_hl_l = 0x75,
-    ld_hl_n = 0x36,
-
-    ld_b_a = 0x47,
-    ld_c_a = 0x4F,
-    ld_d_a = 0x57,
-    ld_e_a = 0x5F,
-    ld_h_a = 0x67,
-    ld_l_a = 0x6F,
-    ld_bc_a = 0x02,
-    ld_de_a = 0x12,
-    ld_hl_a = 0x77,
-    ld_nn_a = 0xEA,
-
-    add_a_a = 0x87,
-    add_a_b = 0x80,
-    add_a_c = 0x81,
-    add_a_d = 0x82,
-    add_a_e = 0x83,
-    add_a_h = 0x84,
-    add_a_l = 0x85,
-    add_a_hl = 0x86,
-    add_a_sharp = 0xC6,
-
-    sub_a_a = 0x97,
-    sub_a_b = 0x90,
-    sub_a_c = 0x91,
-    sub_a_d = 0x92,
-    sub_a_e = 0x93,
-    sub_a_h = 0x94,
-    sub_a_l = 0x95,
-    sub_a_hl = 0x96,
-    sub_a_sharp = 0xD6,
-
-    push_af = 0xF5,
-    push_bc = 0xC5,
-    push_de = 0xD5,
-    push_hl = 0xE5,
-
-    call_nn = 0xCD,
-
-    jp_nn = 0xC3,
-    jp_hl = 0xE9,
-
-    di = 0xF3,
-    ei = 0xFB,
-
-    callback = 0xCB,
-
-    rst_00 = 0xC7,
-    rst_08 = 0xCF,
-    rst_10 = 0xD7,
-    rst_18 = 0xDF,
-    rst_20 = 0xE7,
-    rst_28 = 0xEF,
-    rst_30 = 0xF7,
-    rst_38 = 0xFF,
-
-    xor_a_a = 0xAF,
-    xor_a_b = 0xA8,
-    xor_a_c = 0xA9,
-    xor_a_d = 0xAA,
-    xor_a_e = 0xAB,
-    xor_a_h = 0xAC,
-    xor_a_l = 0xAD,
-    xor_a_hl = 0xAE,
-    xor_a_asterisk = 0xEE,
-
-    ldh_n_a = 0xE0,
-
-    ldh_a_n = 0xF0,
-
-    ret_nz = 0xC0,
-    ret_z = 0xC8,
-    ret_nc = 0xD0,
-    ret_c = 0xD8,
-
-    cp_a_a = 0xBF,
-    cp_a_b = 0xB8,
-    cp_a_c = 0xB9,
-    cp_a_d = 0xBA,
-    cp_a_e = 0xBB,
-    cp_a_h = 0xBC,
-    cp_a_l = 0xBD,
-    cp_a_hl = 0xBE,
-    cp_a_sharp = 0xFE,
-
-    jr_nz_n = 0x20,
-    jr_z_n = 0x28,
-    jr_nc_n = 0x30,
-    jr_c_n = 0x38,
-
-    ret = 0xC9,
-
-    halt = 0x76,
+pub enum Reg8 {
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    HlInd,
+    A,
 }
 
-impl Opcode {
-    pub fn find(self, value: u8) -> Opcode {
-        unsafe { mem::transmute(value) }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg16 {
+    BC,
+    DE,
+    HL,
+    SP,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg16Stack {
+    BC,
+    DE,
+    HL,
+    AF,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alu {
+    Add,
+    Adc,
+    Sub,
+    Sbc,
+    And,
+    Xor,
+    Or,
+    Cp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shift {
+    Rlc,
+    Rrc,
+    Rl,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+}
+
+// Source or destination of an 8-bit transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand8 {
+    Reg(Reg8),
+    Imm8,
+    Ind(Reg16),
+    HlInc,
+    HlDec,
+    Abs,
+    HighImm,
+    HighC,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Nop,
+    Stop,
+    Halt,
+    Di,
+    Ei,
+    Illegal,
+    Prefix,
+
+    Ld8(Operand8, Operand8),
+    Ld16(Reg16),
+    LdAbsSp,
+    LdSpHl,
+    LdHlSpImm,
+
+    Inc8(Reg8),
+    Dec8(Reg8),
+    Inc16(Reg16),
+    Dec16(Reg16),
+    AddHl(Reg16),
+    AddSpImm,
+    Alu(Alu, Operand8),
+
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+
+    Jr(Option<Condition>),
+    Jp(Option<Condition>),
+    JpHl,
+    Call(Option<Condition>),
+    Ret(Option<Condition>),
+    Reti,
+    Rst(u8),
+    Push(Reg16Stack),
+    Pop(Reg16Stack),
+
+    Shift(Shift, Reg8),
+    Bit(u8, Reg8),
+    Res(u8, Reg8),
+    Set(u8, Reg8),
+}
+
+// Cycles are M-cycles. `branch_cycles` is the cost when a conditional
+// jump, call or return is taken and equals `cycles` for everything else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub operation: Operation,
+    pub length: u8,
+    pub cycles: u8,
+    pub branch_cycles: u8,
+}
+
+impl Instruction {
+    fn new(operation: Operation, length: u8, cycles: u8) -> Instruction {
+        Instruction {
+            operation,
+            length,
+            cycles,
+            branch_cycles: cycles,
+        }
+    }
+
+    fn branch(operation: Operation, length: u8, cycles: u8, branch_cycles: u8) -> Instruction {
+        Instruction {
+            operation,
+            length,
+            cycles,
+            branch_cycles,
+        }
+    }
+}
+
+const R: [Reg8; 8] = [Reg8::B, Reg8::C, Reg8::D, Reg8::E, Reg8::H, Reg8::L, Reg8::HlInd, Reg8::A];
+const RP: [Reg16; 4] = [Reg16::BC, Reg16::DE, Reg16::HL, Reg16::SP];
+const RP2: [Reg16Stack; 4] = [Reg16Stack::BC, Reg16Stack::DE, Reg16Stack::HL, Reg16Stack::AF];
+const CC: [Condition; 4] = [Condition::NZ, Condition::Z, Condition::NC, Condition::C];
+const ALU: [Alu; 8] = [Alu::Add, Alu::Adc, Alu::Sub, Alu::Sbc, Alu::And, Alu::Xor, Alu::Or, Alu::Cp];
+const SHIFT: [Shift; 8] = [Shift::Rlc, Shift::Rrc, Shift::Rl, Shift::Rr, Shift::Sla, Shift::Sra, Shift::Swap, Shift::Srl];
+
+pub fn decode(opcode: u8) -> Instruction {
+    let x = opcode >> 6;
+    let y = ((opcode >> 3) & 0x07) as usize;
+    let z = opcode & 0x07;
+    let p = y >> 1;
+    let q = y & 0x01;
+
+    let cost = |r: Reg8, reg: u8, ind: u8| if r == Reg8::HlInd { ind } else { reg };
+
+    match (x, z) {
+        (0, 0) => match y {
+            0 => Instruction::new(Operation::Nop, 1, 1),
+            1 => Instruction::new(Operation::LdAbsSp, 3, 5),
+            2 => Instruction::new(Operation::Stop, 2, 1),
+            3 => Instruction::new(Operation::Jr(None), 2, 3),
+            _ => Instruction::branch(Operation::Jr(Some(CC[y - 4])), 2, 2, 3),
+        },
+        (0, 1) if q == 0 => Instruction::new(Operation::Ld16(RP[p]), 3, 3),
+        (0, 1) => Instruction::new(Operation::AddHl(RP[p]), 1, 2),
+        (0, 2) => {
+            let mem = match p {
+                0 => Operand8::Ind(Reg16::BC),
+                1 => Operand8::Ind(Reg16::DE),
+                2 => Operand8::HlInc,
+                _ => Operand8::HlDec,
+            };
+            let a = Operand8::Reg(Reg8::A);
+
+            if q == 0 {
+                Instruction::new(Operation::Ld8(mem, a), 1, 2)
+            } else {
+                Instruction::new(Operation::Ld8(a, mem), 1, 2)
+            }
+        }
+        (0, 3) if q == 0 => Instruction::new(Operation::Inc16(RP[p]), 1, 2),
+        (0, 3) => Instruction::new(Operation::Dec16(RP[p]), 1, 2),
+        (0, 4) => Instruction::new(Operation::Inc8(R[y]), 1, cost(R[y], 1, 3)),
+        (0, 5) => Instruction::new(Operation::Dec8(R[y]), 1, cost(R[y], 1, 3)),
+        (0, 6) => Instruction::new(Operation::Ld8(Operand8::Reg(R[y]), Operand8::Imm8), 2, cost(R[y], 2, 3)),
+        (0, _) => {
+            let operation = match y {
+                0 => Operation::Rlca,
+                1 => Operation::Rrca,
+                2 => Operation::Rla,
+                3 => Operation::Rra,
+                4 => Operation::Daa,
+                5 => Operation::Cpl,
+                6 => Operation::Scf,
+                _ => Operation::Ccf,
+            };
+            Instruction::new(operation, 1, 1)
+        }
+
+        (1, 6) if y == 6 => Instruction::new(Operation::Halt, 1, 1),
+        (1, _) => {
+            let dst = R[y];
+            let src = R[z as usize];
+            let cycles = if dst == Reg8::HlInd || src == Reg8::HlInd { 2 } else { 1 };
+            Instruction::new(Operation::Ld8(Operand8::Reg(dst), Operand8::Reg(src)), 1, cycles)
+        }
+
+        (2, _) => Instruction::new(Operation::Alu(ALU[y], Operand8::Reg(R[z as usize])), 1, cost(R[z as usize], 1, 2)),
+
+        (_, 0) => match y {
+            0..=3 => Instruction::branch(Operation::Ret(Some(CC[y])), 1, 2, 5),
+            4 => Instruction::new(Operation::Ld8(Operand8::HighImm, Operand8::Reg(Reg8::A)), 2, 3),
+            5 => Instruction::new(Operation::AddSpImm, 2, 4),
+            6 => Instruction::new(Operation::Ld8(Operand8::Reg(Reg8::A), Operand8::HighImm), 2, 3),
+            _ => Instruction::new(Operation::LdHlSpImm, 2, 3),
+        },
+        (_, 1) if q == 0 => Instruction::new(Operation::Pop(RP2[p]), 1, 3),
+        (_, 1) => match p {
+            0 => Instruction::new(Operation::Ret(None), 1, 4),
+            1 => Instruction::new(Operation::Reti, 1, 4),
+            2 => Instruction::new(Operation::JpHl, 1, 1),
+            _ => Instruction::new(Operation::LdSpHl, 1, 2),
+        },
+        (_, 2) => match y {
+            0..=3 => Instruction::branch(Operation::Jp(Some(CC[y])), 3, 3, 4),
+            4 => Instruction::new(Operation::Ld8(Operand8::HighC, Operand8::Reg(Reg8::A)), 1, 2),
+            5 => Instruction::new(Operation::Ld8(Operand8::Abs, Operand8::Reg(Reg8::A)), 3, 4),
+            6 => Instruction::new(Operation::Ld8(Operand8::Reg(Reg8::A), Operand8::HighC), 1, 2),
+            _ => Instruction::new(Operation::Ld8(Operand8::Reg(Reg8::A), Operand8::Abs), 3, 4),
+        },
+        (_, 3) => match y {
+            0 => Instruction::new(Operation::Jp(None), 3, 4),
+            1 => Instruction::new(Operation::Prefix, 1, 1),
+            6 => Instruction::new(Operation::Di, 1, 1),
+            7 => Instruction::new(Operation::Ei, 1, 1),
+            _ => Instruction::new(Operation::Illegal, 1, 1),
+        },
+        (_, 4) => match y {
+            0..=3 => Instruction::branch(Operation::Call(Some(CC[y])), 3, 3, 6),
+            _ => Instruction::new(Operation::Illegal, 1, 1),
+        },
+        (_, 5) if q == 0 => Instruction::new(Operation::Push(RP2[p]), 1, 4),
+        (_, 5) if p == 0 => Instruction::new(Operation::Call(None), 3, 6),
+        (_, 5) => Instruction::new(Operation::Illegal, 1, 1),
+        (_, 6) => Instruction::new(Operation::Alu(ALU[y], Operand8::Imm8), 2, 2),
+        (_, _) => Instruction::new(Operation::Rst((y as u8) << 3), 1, 4),
+    }
+}
+
+// Decodes the byte following a 0xCB prefix. Lengths and cycles include
+// the prefix itself.
+pub fn decode_callback(opcode: u8) -> Instruction {
+    let y = (opcode >> 3) & 0x07;
+    let r = R[(opcode & 0x07) as usize];
+    let ind = r == Reg8::HlInd;
+
+    match opcode >> 6 {
+        0 => Instruction::new(Operation::Shift(SHIFT[y as usize], r), 2, if ind { 4 } else { 2 }),
+        1 => Instruction::new(Operation::Bit(y, r), 2, if ind { 3 } else { 2 }),
+        2 => Instruction::new(Operation::Res(y, r), 2, if ind { 4 } else { 2 }),
+        _ => Instruction::new(Operation::Set(y, r), 2, if ind { 4 } else { 2 }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // M-cycles of every opcode, branches not taken, as measured by the
+    // instr_timing test ROM. Zero marks STOP, HALT, the prefix and the
+    // illegal opcodes, which it does not time.
+    const CYCLES: [u8; 256] = [
+        1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1,
+        0, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 2, 2, 2, 2, 2, 0, 2, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 0, 3, 6, 2, 4,
+        2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4,
+        3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4,
+        3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4,
+    ];
+
+    const LENGTHS: [u8; 256] = [
+        1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 1, 3, 3, 2, 1,
+        1, 1, 3, 1, 3, 1, 2, 1, 1, 1, 3, 1, 3, 1, 2, 1,
+        2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1,
+        2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1,
+    ];
+
+    #[test]
+    fn lengths() {
+        for opcode in 0..=255u8 {
+            assert_eq!(decode(opcode).length, LENGTHS[opcode as usize], "{:02X}", opcode);
+        }
+    }
+
+    #[test]
+    fn cycles() {
+        for opcode in 0..=255u8 {
+            let expected = CYCLES[opcode as usize];
+            if expected != 0 {
+                assert_eq!(decode(opcode).cycles, expected, "{:02X}", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn branch_cycles() {
+        for opcode in 0..=255u8 {
+            let instruction = decode(opcode);
+            let taken = match opcode {
+                0x20 | 0x28 | 0x30 | 0x38 => 3,
+                0xC0 | 0xC8 | 0xD0 | 0xD8 => 5,
+                0xC2 | 0xCA | 0xD2 | 0xDA => 4,
+                0xC4 | 0xCC | 0xD4 | 0xDC => 6,
+                _ => instruction.cycles,
+            };
+            assert_eq!(instruction.branch_cycles, taken, "{:02X}", opcode);
+        }
+    }
+
+    #[test]
+    fn illegal_opcodes() {
+        for &opcode in &[0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD] {
+            assert_eq!(decode(opcode).operation, Operation::Illegal, "{:02X}", opcode);
+        }
+    }
+
+    // Including the prefix: 2, or 4 through (HL), except BIT n,(HL) which
+    // only reads.
+    #[test]
+    fn callback_cycles() {
+        for opcode in 0..=255u8 {
+            let instruction = decode_callback(opcode);
+            let expected = match (opcode >> 6, opcode & 0x07) {
+                (_, z) if z != 6 => 2,
+                (1, _) => 3,
+                _ => 4,
+            };
+            assert_eq!(instruction.length, 2, "CB {:02X}", opcode);
+            assert_eq!(instruction.cycles, expected, "CB {:02X}", opcode);
+        }
     }
 }
@@ -0,0 +1,326 @@
+use std::collections::VecDeque;
+
+use super::*;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+// Fetcher and FIFO state for the line being drawn. Each fetcher step
+// takes two dots and pushing waits until the background FIFO is empty.
+// Sprites stall pixel output while their row is fetched, which is what
+// makes mode 3 longer on busy lines.
+pub struct Fifo {
+    bg: VecDeque<BgPixel>,
+    obj: VecDeque<ObjPixel>,
+
+    step: Step,
+    step_dots: u8,
+    fetcher_x: u8,
+    tile: u8,
+    attributes: u8,
+    low: u8,
+    high: u8,
+    first_fetch: bool,
+
+    window: bool,
+    window_drawn: bool,
+    sprite: usize,
+    sprite_dots: u8,
+
+    discard: u8,
+    lx: u8,
+}
+
+impl Fifo {
+    pub fn new() -> Fifo {
+        Fifo {
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(8),
+
+            step: Step::Tile,
+            step_dots: 0,
+            fetcher_x: 0,
+            tile: 0,
+            attributes: 0,
+            low: 0,
+            high: 0,
+            first_fetch: true,
+
+            window: false,
+            window_drawn: false,
+            sprite: 0,
+            sprite_dots: 0,
+
+            discard: 0,
+            lx: 0,
+        }
+    }
+
+    fn restart_fetch(&mut self) {
+        self.step = Step::Tile;
+        self.step_dots = 0;
+    }
+}
+
+impl Ppu {
+    pub(super) fn fifo_start_line(&mut self) {
+        let fifo = &mut self.fifo;
+
+        fifo.bg.clear();
+        fifo.obj.clear();
+        fifo.restart_fetch();
+        fifo.fetcher_x = 0;
+        fifo.first_fetch = true;
+        fifo.window = false;
+        fifo.window_drawn = false;
+        fifo.sprite = 0;
+        fifo.sprite_dots = 0;
+        // The fine scroll is only looked at once, the pixels it covers are
+        // fetched and then dropped.
+        fifo.discard = self.scx & 0x07;
+        fifo.lx = 0;
+    }
+
+    // Called at the end of every line drawn in FIFO mode.
+    pub(super) fn fifo_end_line(&mut self) {
+        if self.fifo.window_drawn {
+            self.window_line += 1;
+        }
+    }
+
+    // Advances mode 3 by one dot. Returns true once the last pixel of the
+    // line has been output.
+    pub(super) fn fifo_dot(&mut self) -> bool {
+        if self.fifo.sprite_dots > 0 {
+            self.fifo.sprite_dots -= 1;
+            if self.fifo.sprite_dots == 0 {
+                self.fetch_sprite();
+            }
+            return false;
+        }
+
+        if self.window_starts() {
+            self.fifo.window = true;
+            self.fifo.window_drawn = true;
+            self.fifo.fetcher_x = 0;
+            self.fifo.bg.clear();
+            self.fifo.restart_fetch();
+        }
+
+        let sprite_pending = self.sprite_pending();
+
+        // A sprite waits for the background fetch under way to finish.
+        if sprite_pending && self.fifo.step == Step::Push && !self.fifo.bg.is_empty() {
+            self.fifo.sprite_dots = 6;
+            return false;
+        }
+
+        self.fetch_step();
+
+        if sprite_pending || self.fifo.bg.is_empty() {
+            return false;
+        }
+
+        self.output_pixel()
+    }
+
+    fn window_starts(&self) -> bool {
+        !self.fifo.window &&
+            self.lcdc & LCDC_WINDOW_ENABLE != 0 &&
+            (self.cgb || self.lcdc & LCDC_BG_ENABLE != 0) &&
+            self.window_y_reached &&
+            self.wx < 167 &&
+            self.fifo.lx as u16 + 7 >= self.wx as u16
+    }
+
+    // Sprites reached while OBJ is off are never fetched, so turning it
+    // back on later in the line does not bring them back.
+    fn sprite_pending(&mut self) -> bool {
+        let lx = self.fifo.lx as i16;
+
+        if self.lcdc & LCDC_OBJ_ENABLE == 0 {
+            while self.sprites.get(self.fifo.sprite).is_some_and(|sprite| sprite.x <= lx) {
+                self.fifo.sprite += 1;
+            }
+            return false;
+        }
+
+        match self.sprites.get(self.fifo.sprite) {
+            Some(sprite) => sprite.x <= lx,
+            None => false,
+        }
+    }
+
+    fn fetch_step(&mut self) {
+        self.fifo.step_dots += 1;
+
+        match self.fifo.step {
+            Step::Tile if self.fifo.step_dots == 2 => {
+                let (map, column, row) = if self.fifo.window {
+                    let map = if self.lcdc & LCDC_WINDOW_MAP != 0 { 0x1C00 } else { 0x1800 };
+                    (map, self.fifo.fetcher_x, self.window_line / 8)
+                } else {
+                    let map = if self.lcdc & LCDC_BG_MAP != 0 { 0x1C00 } else { 0x1800 };
+                    let column = (self.scx / 8).wrapping_add(self.fifo.fetcher_x) & 0x1F;
+                    (map, column, self.ly.wrapping_add(self.scy) / 8)
+                };
+
+                let entry = map + row as usize * 32 + column as usize;
+                self.fifo.tile = self.vram[entry];
+                self.fifo.attributes = if self.cgb { self.vram[VRAM_BANK_SIZE + entry] } else { 0 };
+                self.fifo.step = Step::DataLow;
+                self.fifo.step_dots = 0;
+            }
+            Step::DataLow if self.fifo.step_dots == 2 => {
+                self.fifo.low = self.vram[self.fetch_addr()];
+                self.fifo.step = Step::DataHigh;
+                self.fifo.step_dots = 0;
+            }
+            Step::DataHigh if self.fifo.step_dots == 2 => {
+                self.fifo.high = self.vram[self.fetch_addr() + 1];
+                self.fifo.step = Step::Push;
+                self.fifo.step_dots = 0;
+            }
+            Step::Push if self.fifo.bg.is_empty() => {
+                if self.fifo.first_fetch {
+                    // The first fetch of every line is thrown away.
+                    self.fifo.first_fetch = false;
+                } else {
+                    let attributes = self.fifo.attributes;
+                    for column in 0..8 {
+                        let bit = if attributes & ATTR_X_FLIP != 0 { column } else { 7 - column };
+                        let color = ((self.fifo.high >> bit) & 0x01) << 1 | ((self.fifo.low >> bit) & 0x01);
+                        self.fifo.bg.push_back(BgPixel { color, attributes });
+                    }
+                    self.fifo.fetcher_x = self.fifo.fetcher_x.wrapping_add(1);
+                }
+                self.fifo.restart_fetch();
+            }
+            _ => {}
+        }
+    }
+
+    fn fetch_addr(&self) -> usize {
+        let mut line = if self.fifo.window {
+            self.window_line % 8
+        } else {
+            self.ly.wrapping_add(self.scy) % 8
+        };
+        if self.fifo.attributes & ATTR_Y_FLIP != 0 {
+            line = 7 - line;
+        }
+
+        let base = if self.lcdc & LCDC_TILE_DATA != 0 {
+            self.fifo.tile as usize * 16
+        } else {
+            (0x1000 + (self.fifo.tile as i8 as i32) * 16) as usize
+        };
+
+        base + tile_bank(self.fifo.attributes) + line as usize * 2
+    }
+
+    fn fetch_sprite(&mut self) {
+        let sprite = self.sprites[self.fifo.sprite];
+        self.fifo.sprite += 1;
+
+        let row = self.sprite_row(&sprite);
+
+        while self.fifo.obj.len() < 8 {
+            self.fifo.obj.push_back(ObjPixel::default());
+        }
+
+        // Pixels left of the screen edge were already passed.
+        let skip = ((self.fifo.lx as i16 - sprite.x) as usize).min(row.len());
+        for (slot, &color) in row[skip..].iter().enumerate() {
+            let pixel = ObjPixel { color, attributes: sprite.attributes, index: sprite.index };
+            if self.sprite_wins(&pixel, &self.fifo.obj[slot]) {
+                self.fifo.obj[slot] = pixel;
+            }
+        }
+    }
+
+    fn output_pixel(&mut self) -> bool {
+        let bg = self.fifo.bg.pop_front().unwrap_or_default();
+
+        if self.fifo.discard > 0 && !self.fifo.window {
+            self.fifo.discard -= 1;
+            return false;
+        }
+
+        let obj = self.fifo.obj.pop_front().unwrap_or_default();
+
+        // Registers are sampled as each pixel leaves the FIFO.
+        let x = self.fifo.lx as usize;
+        self.put_pixel(x, bg, obj);
+        self.fifo.lx += 1;
+
+        self.fifo.lx as usize == SCREEN_WIDTH
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interrupt::InterruptController;
+
+    // Ten sprites on line 0, one every 16 pixels from the left edge, drawn
+    // in shade 3 over a background of shade 0.
+    fn sprite_line() -> (Ppu, InterruptController) {
+        let mut ppu = Ppu::new();
+        let mut interrupts = InterruptController::new();
+
+        ppu.store_vram(0, 0xFF);
+        ppu.store_vram(1, 0xFF);
+        for sprite in 0..10u8 {
+            ppu.dma_store_oam(sprite * 4, 16);
+            ppu.dma_store_oam(sprite * 4 + 1, 8 + sprite * 16);
+        }
+        ppu.wb(0xFF47, 0x00, &mut interrupts);
+        ppu.wb(0xFF48, 0xFF, &mut interrupts);
+        ppu.set_renderer(Renderer::Fifo);
+
+        (ppu, interrupts)
+    }
+
+    #[test]
+    fn obj_enabled_mid_line() {
+        let (mut ppu, mut interrupts) = sprite_line();
+        ppu.wb(0xFF40, 0x91, &mut interrupts);
+
+        while ppu.mode() != Mode::Transfer || ppu.fifo.lx < 44 {
+            ppu.cycle(1, &mut interrupts);
+        }
+        ppu.wb(0xFF40, 0x93, &mut interrupts);
+        while ppu.mode() == Mode::Transfer {
+            ppu.cycle(1, &mut interrupts);
+        }
+
+        // The sprites passed while OBJ was off stay hidden.
+        assert!(ppu.framebuffer()[..48].iter().all(|&shade| shade == 0));
+        for x in (48..SCREEN_WIDTH).step_by(16) {
+            assert!(ppu.framebuffer()[x..x + 8].iter().all(|&shade| shade == 3), "sprite at {}", x);
+        }
+    }
+
+    #[test]
+    fn obj_enabled_inside_sprite() {
+        let (mut ppu, mut interrupts) = sprite_line();
+        ppu.wb(0xFF40, 0x91, &mut interrupts);
+
+        while ppu.mode() != Mode::Transfer || ppu.fifo.lx < 36 {
+            ppu.cycle(1, &mut interrupts);
+        }
+        ppu.wb(0xFF40, 0x93, &mut interrupts);
+        while ppu.mode() == Mode::Transfer {
+            ppu.cycle(1, &mut interrupts);
+        }
+
+        assert!(ppu.framebuffer()[32..48].iter().all(|&shade| shade == 0));
+        assert_eq!(ppu.framebuffer()[48], 3);
+    }
+}
@@ -0,0 +1,1046 @@
+use interrupt::{Interrupt, InterruptController};
+
+mod fifo;
+
+use self::fifo::Fifo;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+// Dots (T-cycles at normal speed) per scanline and the number of lines,
+// including the ten lines of VBlank.
+const LINE_DOTS: u32 = 456;
+const LINES: u8 = 154;
+const OAM_SCAN_DOTS: u32 = 80;
+const TRANSFER_DOTS: u32 = 172;
+
+const SPRITES_PER_LINE: usize = 10;
+
+// OAM as the PPU reads it during the scan, in rows of two entries.
+const OAM_ROWS: usize = 20;
+
+const LCDC_BG_ENABLE: u8 = 0x01;
+const LCDC_OBJ_ENABLE: u8 = 0x02;
+const LCDC_OBJ_SIZE: u8 = 0x04;
+const LCDC_BG_MAP: u8 = 0x08;
+const LCDC_TILE_DATA: u8 = 0x10;
+const LCDC_WINDOW_ENABLE: u8 = 0x20;
+const LCDC_WINDOW_MAP: u8 = 0x40;
+const LCDC_ENABLE: u8 = 0x80;
+
+const STAT_HBLANK: u8 = 0x08;
+const STAT_VBLANK: u8 = 0x10;
+const STAT_OAM: u8 = 0x20;
+const STAT_LYC: u8 = 0x40;
+
+// Sprite attributes. CGB background map attributes share the flip and
+// priority bits, the bank and palette bits are CGB only.
+const ATTR_CGB_PALETTE: u8 = 0x07;
+const ATTR_BANK: u8 = 0x08;
+const ATTR_PALETTE: u8 = 0x10;
+const ATTR_X_FLIP: u8 = 0x20;
+const ATTR_Y_FLIP: u8 = 0x40;
+const ATTR_BEHIND_BG: u8 = 0x80;
+
+const VRAM_BANK_SIZE: usize = 0x2000;
+
+// Auto-increment flag of BCPS/OCPS.
+const PALETTE_INCREMENT: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    HBlank,
+    VBlank,
+    OamScan,
+    Transfer,
+}
+
+impl Mode {
+    fn bits(self) -> u8 {
+        match self {
+            Mode::HBlank => 0,
+            Mode::VBlank => 1,
+            Mode::OamScan => 2,
+            Mode::Transfer => 3,
+        }
+    }
+}
+
+// How mode 3 turns VRAM into pixels. `Scanline` draws the whole line in
+// one go from the registers as they are when mode 3 ends. `Fifo` runs the
+// background fetcher and pixel FIFO dot by dot, so mode 3 has its real
+// length and register writes take effect mid-line, at a higher cost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Renderer {
+    Scanline,
+    Fifo,
+}
+
+// RGB colors of the four DMG shades, lightest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmgPalette {
+    pub colors: [[u8; 3]; 4],
+}
+
+impl DmgPalette {
+    pub const GREY: DmgPalette = DmgPalette {
+        colors: [[0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55], [0x00, 0x00, 0x00]],
+    };
+
+    // The yellow-green of the original LCD.
+    pub const GREEN: DmgPalette = DmgPalette {
+        colors: [[0x9B, 0xBC, 0x0F], [0x8B, 0xAC, 0x0F], [0x30, 0x62, 0x30], [0x0F, 0x38, 0x0F]],
+    };
+
+    // Accepts "grey", "green" or four comma separated RRGGBB colors.
+    pub fn parse(text: &str) -> Option<DmgPalette> {
+        match text {
+            "grey" | "gray" => return Some(DmgPalette::GREY),
+            "green" => return Some(DmgPalette::GREEN),
+            _ => {}
+        }
+
+        let parts: Vec<&str> = text.split(',').collect();
+        if parts.len() != 4 {
+            return None;
+        }
+
+        let mut colors = [[0; 3]; 4];
+        for (color, part) in colors.iter_mut().zip(parts) {
+            let part = part.trim_start_matches('#');
+            if part.len() != 6 {
+                return None;
+            }
+            let rgb = u32::from_str_radix(part, 16).ok()?;
+            *color = [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8];
+        }
+
+        Some(DmgPalette { colors })
+    }
+}
+
+impl Default for DmgPalette {
+    fn default() -> DmgPalette {
+        DmgPalette::GREY
+    }
+}
+
+// A background or window pixel before palette lookup, with its CGB map
+// attributes (always 0 on DMG).
+#[derive(Clone, Copy, Default)]
+struct BgPixel {
+    color: u8,
+    attributes: u8,
+}
+
+// A sprite pixel before palette lookup. Color 0 is transparent.
+#[derive(Clone, Copy, Default)]
+struct ObjPixel {
+    color: u8,
+    attributes: u8,
+    index: usize,
+}
+
+// The RGB555 colors the CGB boot ROM falls back to for a DMG cartridge
+// it has no palette for, used here for every DMG cartridge. BGP maps onto
+// the first set, OBP0 and OBP1 onto the second.
+const COMPAT_BG: [u16; 4] = [0x7FFF, 0x1BEF, 0x6180, 0x0000];
+const COMPAT_OBJ: [u16; 4] = [0x7FFF, 0x421F, 0x1CF2, 0x0000];
+
+// How a CPU access in 0xFE00-0xFEFF during the OAM scan damages the row
+// of OAM the PPU is reading, on the models with the OAM bug.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OamCorruption {
+    // A write, or an increment or decrement with no access.
+    Write,
+    Read,
+    // A read while the same address is incremented or decremented, as in
+    // LD A,(HL+) and POP.
+    ReadIncDec,
+}
+
+#[derive(Clone, Copy)]
+struct Sprite {
+    index: usize,
+    y: i16,
+    x: i16,
+    tile: u8,
+    attributes: u8,
+}
+
+// With the scanline renderer mode 3 is stretched by the fine scroll and
+// the number of sprites on the line, but the pixels are not fetched at
+// the time they are output.
+pub struct Ppu {
+    cgb: bool,
+    // A DMG game on CGB hardware, colored through the palette RAM.
+    compat: bool,
+
+    vram: Vec<u8>,
+    vram_bank: u8,
+    oam: Vec<u8>,
+
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    wy: u8,
+    wx: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+
+    // CGB palette RAM, eight palettes of four RGB555 colors each, and the
+    // BCPS/OCPS index registers.
+    bg_palettes: [u8; 64],
+    obj_palettes: [u8; 64],
+    bcps: u8,
+    ocps: u8,
+
+    renderer: Renderer,
+    line_renderer: Renderer,
+    fifo: Fifo,
+
+    mode: Mode,
+    dot: u32,
+    transfer_dots: u32,
+    window_line: u8,
+    window_y_reached: bool,
+    stat_line: bool,
+    sprites: Vec<Sprite>,
+
+    // Shades 0 (lightest) to 3, after palette mapping. In CGB mode the
+    // frame goes to `color_framebuffer` as RGB555 instead, in compatibility
+    // mode to both.
+    framebuffer: Vec<u8>,
+    color_framebuffer: Vec<u16>,
+    palette: DmgPalette,
+    color_correction: bool,
+    frames: u64,
+}
+
+impl Default for Ppu {
+    fn default() -> Ppu {
+        Ppu::new()
+    }
+}
+
+impl Ppu {
+    pub fn new() -> Ppu {
+        Ppu::with_cgb(false)
+    }
+
+    pub fn with_cgb(cgb: bool) -> Ppu {
+        Ppu {
+            cgb,
+            compat: false,
+
+            vram: vec![0; VRAM_BANK_SIZE * 2],
+            vram_bank: 0,
+            oam: vec![0; 0xA0],
+
+            lcdc: 0x00,
+            stat: 0x00,
+            scy: 0x00,
+            scx: 0x00,
+            ly: 0x00,
+            lyc: 0x00,
+            wy: 0x00,
+            wx: 0x00,
+            bgp: 0x00,
+            obp0: 0x00,
+            obp1: 0x00,
+
+            // The CGB boot ROM leaves every palette white.
+            bg_palettes: [0xFF; 64],
+            obj_palettes: [0xFF; 64],
+            bcps: 0x00,
+            ocps: 0x00,
+
+            renderer: Renderer::Scanline,
+            line_renderer: Renderer::Scanline,
+            fifo: Fifo::new(),
+
+            mode: Mode::HBlank,
+            dot: 0,
+            transfer_dots: TRANSFER_DOTS,
+            window_line: 0,
+            window_y_reached: false,
+            stat_line: false,
+            sprites: Vec::with_capacity(SPRITES_PER_LINE),
+
+            framebuffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            color_framebuffer: vec![0x7FFF; SCREEN_WIDTH * SCREEN_HEIGHT],
+            palette: DmgPalette::default(),
+            color_correction: false,
+            frames: 0,
+        }
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    // The CGB frame, unused on DMG.
+    pub fn color_framebuffer(&self) -> &[u16] {
+        &self.color_framebuffer
+    }
+
+    pub fn cgb(&self) -> bool {
+        self.cgb
+    }
+
+    // Runs a DMG game the way a CGB does, with the shades colored by the
+    // boot ROM's fallback palette. The per-title palettes the boot ROM
+    // picks from the header and those chosen with the joypad at boot are
+    // not supported. The palette registers stay out of reach.
+    pub(crate) fn set_compat(&mut self) {
+        self.compat = true;
+
+        store_colors(&mut self.bg_palettes[0..8], &COMPAT_BG);
+        store_colors(&mut self.obj_palettes[0..8], &COMPAT_OBJ);
+        store_colors(&mut self.obj_palettes[8..16], &COMPAT_OBJ);
+    }
+
+    // Compatibility mode as the CGB boot ROM enters it, keeping the colors
+    // it loaded.
+    pub(crate) fn set_dmg_mode(&mut self) {
+        self.cgb = false;
+        self.compat = true;
+    }
+
+    pub fn compat(&self) -> bool {
+        self.compat
+    }
+
+    // The current frame as 8-bit RGBA, row by row.
+    pub fn frame_rgba(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+
+        if self.cgb || self.compat {
+            for &color in &self.color_framebuffer {
+                rgba.extend_from_slice(&rgb888(color, self.color_correction));
+                rgba.push(0xFF);
+            }
+        } else {
+            for &shade in &self.framebuffer {
+                rgba.extend_from_slice(&self.palette.colors[shade as usize]);
+                rgba.push(0xFF);
+            }
+        }
+
+        rgba
+    }
+
+    pub fn color_correction(&self) -> bool {
+        self.color_correction
+    }
+
+    // Mimics the washed out colors of the CGB screen in `frame_rgba`
+    // instead of showing RGB555 values as they are.
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        self.color_correction = enabled;
+    }
+
+    pub fn palette(&self) -> DmgPalette {
+        self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: DmgPalette) {
+        self.palette = palette;
+    }
+
+    // Number of frames completed, counted at the start of each VBlank.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    // Takes effect from the next line.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    fn enabled(&self) -> bool {
+        self.lcdc & LCDC_ENABLE != 0
+    }
+
+    // The CPU cannot see VRAM while it is being read for the screen, nor
+    // OAM while sprites are searched or drawn.
+    pub fn load_vram(&self, offset: u16) -> u8 {
+        if self.mode == Mode::Transfer {
+            return 0xFF;
+        }
+        self.vram[self.vram_bank as usize * VRAM_BANK_SIZE + offset as usize]
+    }
+
+    pub fn store_vram(&mut self, offset: u16, value: u8) {
+        if self.mode != Mode::Transfer {
+            self.vram[self.vram_bank as usize * VRAM_BANK_SIZE + offset as usize] = value;
+        }
+    }
+
+    pub fn load_oam(&self, offset: u16) -> u8 {
+        if self.mode == Mode::OamScan || self.mode == Mode::Transfer {
+            return 0xFF;
+        }
+        self.oam[offset as usize]
+    }
+
+    pub fn store_oam(&mut self, offset: u16, value: u8) {
+        if self.mode != Mode::OamScan && self.mode != Mode::Transfer {
+            self.oam[offset as usize] = value;
+        }
+    }
+
+    // Applies the OAM bug to the row read in the current M-cycle of the OAM
+    // scan. Rows are eight bytes, two entries, read one per M-cycle. The
+    // first row is never touched.
+    pub fn corrupt_oam(&mut self, corruption: OamCorruption) {
+        if !self.enabled() || self.mode != Mode::OamScan {
+            return;
+        }
+
+        let row = (self.dot / 4) as usize;
+        if row == 0 || row >= OAM_ROWS {
+            return;
+        }
+
+        // Away from the first four rows and the last, a read during an
+        // increment or decrement first mixes the preceding row with the one
+        // before it and copies the result around.
+        if corruption == OamCorruption::ReadIncDec && (4..OAM_ROWS - 1).contains(&row) {
+            let a = self.oam_word(row - 2, 0);
+            let b = self.oam_word(row - 1, 0);
+            let c = self.oam_word(row, 0);
+            let d = self.oam_word(row - 2, 2);
+            self.set_oam_word(row - 1, 0, (b & (a | c | d)) | (a & c & d));
+
+            for offset in 0..8 {
+                let value = self.oam[(row - 1) * 8 + offset];
+                self.oam[(row - 2) * 8 + offset] = value;
+                self.oam[row * 8 + offset] = value;
+            }
+        }
+
+        let a = self.oam_word(row, 0);
+        let b = self.oam_word(row - 1, 0);
+        let c = self.oam_word(row - 1, 2);
+        let first = match corruption {
+            OamCorruption::Write => ((a ^ c) & (b ^ c)) ^ c,
+            OamCorruption::Read | OamCorruption::ReadIncDec => b | (a & c),
+        };
+
+        self.set_oam_word(row, 0, first);
+        for offset in 2..8 {
+            self.oam[row * 8 + offset] = self.oam[(row - 1) * 8 + offset];
+        }
+    }
+
+    fn oam_word(&self, row: usize, word: usize) -> u16 {
+        let index = row * 8 + word * 2;
+        self.oam[index] as u16 | (self.oam[index + 1] as u16) << 8
+    }
+
+    fn set_oam_word(&mut self, row: usize, word: usize, value: u16) {
+        let index = row * 8 + word * 2;
+        self.oam[index] = value as u8;
+        self.oam[index + 1] = (value >> 8) as u8;
+    }
+
+    // OAM DMA writes no matter what the PPU is doing.
+    pub fn dma_store_oam(&mut self, offset: u8, value: u8) {
+        self.oam[offset as usize] = value;
+    }
+
+    pub fn rb(&self, a: u16) -> u8 {
+        match a {
+            0xFF40 => self.lcdc,
+            0xFF41 => {
+                let coincidence = if self.ly == self.lyc { 0x04 } else { 0 };
+                0x80 | self.stat | coincidence | self.mode.bits()
+            }
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            _ if !self.cgb => 0xFF,
+            0xFF4F => 0xFE | self.vram_bank,
+            0xFF68 => 0x40 | self.bcps,
+            0xFF69 => self.load_palette(self.bcps, false),
+            0xFF6A => 0x40 | self.ocps,
+            0xFF6B => self.load_palette(self.ocps, true),
+            _ => panic!("PPU does not handle read {:4X}", a),
+        }
+    }
+
+    pub fn wb(&mut self, a: u16, v: u8, interrupts: &mut InterruptController) {
+        match a {
+            0xFF40 => {
+                let was_enabled = self.enabled();
+                self.lcdc = v;
+
+                if was_enabled && !self.enabled() {
+                    self.ly = 0;
+                    self.dot = 0;
+                    self.window_line = 0;
+                    self.window_y_reached = false;
+                    self.mode = Mode::HBlank;
+                } else if !was_enabled && self.enabled() {
+                    self.start_line();
+                }
+            }
+            0xFF41 => self.stat = v & 0x78,
+            0xFF42 => self.scy = v,
+            0xFF43 => self.scx = v,
+            // LY is read only.
+            0xFF44 => {}
+            0xFF45 => self.lyc = v,
+            0xFF47 => self.bgp = v,
+            0xFF48 => self.obp0 = v,
+            0xFF49 => self.obp1 = v,
+            0xFF4A => self.wy = v,
+            0xFF4B => self.wx = v,
+            _ if !self.cgb => {}
+            0xFF4F => self.vram_bank = v & 0x01,
+            0xFF68 => self.bcps = v & 0xBF,
+            0xFF69 => self.bcps = self.store_palette(self.bcps, false, v),
+            0xFF6A => self.ocps = v & 0xBF,
+            0xFF6B => self.ocps = self.store_palette(self.ocps, true, v),
+            _ => panic!("PPU does not handle write {:4X}", a),
+        }
+
+        self.update_stat(interrupts);
+    }
+
+    // Palette RAM is out of reach while the screen is drawn.
+    fn load_palette(&self, index: u8, obj: bool) -> u8 {
+        if self.mode == Mode::Transfer {
+            return 0xFF;
+        }
+
+        let palettes = if obj { &self.obj_palettes } else { &self.bg_palettes };
+        palettes[(index & 0x3F) as usize]
+    }
+
+    // Returns the index register after the write, incremented if asked to
+    // even when the write itself was blocked.
+    fn store_palette(&mut self, index: u8, obj: bool, value: u8) -> u8 {
+        if self.mode != Mode::Transfer {
+            let palettes = if obj { &mut self.obj_palettes } else { &mut self.bg_palettes };
+            palettes[(index & 0x3F) as usize] = value;
+        }
+
+        if index & PALETTE_INCREMENT != 0 {
+            PALETTE_INCREMENT | (index.wrapping_add(1) & 0x3F)
+        } else {
+            index
+        }
+    }
+
+    pub fn cycle(&mut self, ticks: u32, interrupts: &mut InterruptController) {
+        if !self.enabled() {
+            return;
+        }
+
+        for _ in 0..ticks {
+            self.dot += 1;
+
+            if self.ly < SCREEN_HEIGHT as u8 {
+                if self.dot == OAM_SCAN_DOTS {
+                    self.mode = Mode::Transfer;
+                    self.line_renderer = self.renderer;
+                    if self.line_renderer == Renderer::Fifo {
+                        self.fifo_start_line();
+                    }
+                } else if self.mode == Mode::Transfer {
+                    let done = match self.line_renderer {
+                        Renderer::Scanline => self.dot == OAM_SCAN_DOTS + self.transfer_dots,
+                        Renderer::Fifo => self.fifo_dot(),
+                    };
+
+                    if done {
+                        match self.line_renderer {
+                            Renderer::Scanline => self.render_line(),
+                            Renderer::Fifo => self.fifo_end_line(),
+                        }
+                        self.mode = Mode::HBlank;
+                    }
+                }
+            }
+
+            if self.dot == LINE_DOTS {
+                self.dot = 0;
+                self.ly = (self.ly + 1) % LINES;
+
+                if self.ly == SCREEN_HEIGHT as u8 {
+                    self.mode = Mode::VBlank;
+                    self.frames += 1;
+                    interrupts.request(Interrupt::VBlank);
+                } else if self.ly == 0 {
+                    self.window_line = 0;
+                    self.window_y_reached = false;
+                    self.start_line();
+                } else if self.ly < SCREEN_HEIGHT as u8 {
+                    self.start_line();
+                }
+            }
+
+            self.update_stat(interrupts);
+        }
+    }
+
+    fn start_line(&mut self) {
+        if self.ly == self.wy {
+            self.window_y_reached = true;
+        }
+
+        self.mode = Mode::OamScan;
+        self.scan_oam();
+        self.transfer_dots = TRANSFER_DOTS + (self.scx & 0x07) as u32 + 6 * self.sprites.len() as u32;
+    }
+
+    // The STAT interrupt fires when any enabled condition becomes true
+    // while none was true before.
+    fn update_stat(&mut self, interrupts: &mut InterruptController) {
+        let line = self.enabled() && (
+            (self.stat & STAT_LYC != 0 && self.ly == self.lyc) ||
+            (self.stat & STAT_HBLANK != 0 && self.mode == Mode::HBlank) ||
+            (self.stat & STAT_VBLANK != 0 && self.mode == Mode::VBlank) ||
+            (self.stat & STAT_OAM != 0 && self.mode == Mode::OamScan));
+
+        if line && !self.stat_line {
+            interrupts.request(Interrupt::LcdStat);
+        }
+        self.stat_line = line;
+    }
+
+    fn sprite_height(&self) -> i16 {
+        if self.lcdc & LCDC_OBJ_SIZE != 0 { 16 } else { 8 }
+    }
+
+    // Picks the first ten sprites in OAM order that cover this line, then
+    // orders them by drawing priority on DMG: the lower X wins, then the
+    // lower OAM index.
+    fn scan_oam(&mut self) {
+        let height = self.sprite_height();
+        let ly = self.ly as i16;
+
+        self.sprites.clear();
+        for index in 0..40 {
+            let entry = &self.oam[index * 4..index * 4 + 4];
+            let y = entry[0] as i16 - 16;
+
+            if ly >= y && ly < y + height {
+                self.sprites.push(Sprite {
+                    index,
+                    y,
+                    x: entry[1] as i16 - 8,
+                    tile: entry[2],
+                    attributes: entry[3],
+                });
+
+                if self.sprites.len() == SPRITES_PER_LINE {
+                    break;
+                }
+            }
+        }
+
+        // Sprites are also fetched in this order on CGB, where only the
+        // priority between overlapping pixels differs.
+        self.sprites.sort_by_key(|sprite| (sprite.x, sprite.index));
+    }
+
+    fn tile_pixel(&self, tile_addr: usize, x: u8, y: u8) -> u8 {
+        let low = self.vram[tile_addr + y as usize * 2];
+        let high = self.vram[tile_addr + y as usize * 2 + 1];
+        let bit = 7 - x;
+
+        ((high >> bit) & 0x01) << 1 | ((low >> bit) & 0x01)
+    }
+
+    // Address of a tile's data in the right VRAM bank, and its CGB map
+    // attributes from bank 1.
+    fn bg_tile(&self, map: usize, column: u8, row: u8) -> (usize, u8) {
+        let entry = map + row as usize * 32 + column as usize;
+        let tile = self.vram[entry];
+        let attributes = if self.cgb { self.vram[VRAM_BANK_SIZE + entry] } else { 0 };
+
+        let addr = if self.lcdc & LCDC_TILE_DATA != 0 {
+            tile as usize * 16
+        } else {
+            (0x1000 + (tile as i8 as i32) * 16) as usize
+        };
+
+        (addr + tile_bank(attributes), attributes)
+    }
+
+    fn bg_pixel(&self, map: usize, x: u8, y: u8) -> BgPixel {
+        let (tile, attributes) = self.bg_tile(map, x / 8, y / 8);
+        let x = if attributes & ATTR_X_FLIP != 0 { 7 - x % 8 } else { x % 8 };
+        let y = if attributes & ATTR_Y_FLIP != 0 { 7 - y % 8 } else { y % 8 };
+
+        BgPixel { color: self.tile_pixel(tile, x, y), attributes }
+    }
+
+    // The current line of a sprite, as eight colors from left to right.
+    fn sprite_row(&self, sprite: &Sprite) -> [u8; 8] {
+        let height = self.sprite_height();
+        let mut line = self.ly as i16 - sprite.y;
+        if sprite.attributes & ATTR_Y_FLIP != 0 {
+            line = height - 1 - line;
+        }
+
+        let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+        let bank = if self.cgb { tile_bank(sprite.attributes) } else { 0 };
+        let addr = tile as usize * 16 + bank;
+
+        let mut row = [0; 8];
+        for (column, color) in row.iter_mut().enumerate() {
+            let x = if sprite.attributes & ATTR_X_FLIP != 0 { 7 - column } else { column };
+            *color = self.tile_pixel(addr, x as u8, line as u8);
+        }
+        row
+    }
+
+    // Whether a sprite pixel wins over another one already in place.
+    fn sprite_wins(&self, pixel: &ObjPixel, over: &ObjPixel) -> bool {
+        // On CGB the lower OAM index always wins, on DMG the sprite that
+        // came first in drawing order keeps its pixel.
+        pixel.color != 0 && (over.color == 0 || (self.cgb && pixel.index < over.index))
+    }
+
+    // Mixes a background and a sprite pixel and writes the result out.
+    fn put_pixel(&mut self, x: usize, bg: BgPixel, obj: ObjPixel) {
+        let index = self.ly as usize * SCREEN_WIDTH + x;
+        let obj_visible = obj.color != 0 && self.lcdc & LCDC_OBJ_ENABLE != 0;
+
+        if self.cgb {
+            // Bit 0 of LCDC takes away all background priority on CGB.
+            let obj_wins = obj_visible && (bg.color == 0 || self.lcdc & LCDC_BG_ENABLE == 0 ||
+                (bg.attributes & ATTR_BEHIND_BG == 0 && obj.attributes & ATTR_BEHIND_BG == 0));
+
+            self.color_framebuffer[index] = if obj_wins {
+                palette_color(&self.obj_palettes, obj.attributes & ATTR_CGB_PALETTE, obj.color)
+            } else {
+                palette_color(&self.bg_palettes, bg.attributes & ATTR_CGB_PALETTE, bg.color)
+            };
+        } else {
+            // On DMG, clearing bit 0 blanks both background and window.
+            let bg_color = if self.lcdc & LCDC_BG_ENABLE != 0 { bg.color } else { 0 };
+            let obj_wins = obj_visible && (obj.attributes & ATTR_BEHIND_BG == 0 || bg_color == 0);
+
+            let obj1 = obj.attributes & ATTR_PALETTE != 0;
+            let value = if obj_wins {
+                shade(if obj1 { self.obp1 } else { self.obp0 }, obj.color)
+            } else {
+                shade(self.bgp, bg_color)
+            };
+            self.framebuffer[index] = value;
+
+            // BG palette 0 and OBJ palettes 0 and 1 hold the colors.
+            if self.compat {
+                self.color_framebuffer[index] = if obj_wins {
+                    palette_color(&self.obj_palettes, obj1 as u8, value)
+                } else {
+                    palette_color(&self.bg_palettes, 0, value)
+                };
+            }
+        }
+    }
+
+    fn render_line(&mut self) {
+        let ly = self.ly;
+        let mut bg = [BgPixel::default(); SCREEN_WIDTH];
+
+        if self.cgb || self.lcdc & LCDC_BG_ENABLE != 0 {
+            let map = if self.lcdc & LCDC_BG_MAP != 0 { 0x1C00 } else { 0x1800 };
+            let y = ly.wrapping_add(self.scy);
+
+            for (x, pixel) in bg.iter_mut().enumerate() {
+                *pixel = self.bg_pixel(map, (x as u8).wrapping_add(self.scx), y);
+            }
+
+            let window_x = self.wx as i16 - 7;
+            if self.lcdc & LCDC_WINDOW_ENABLE != 0 && self.window_y_reached && window_x < SCREEN_WIDTH as i16 {
+                let map = if self.lcdc & LCDC_WINDOW_MAP != 0 { 0x1C00 } else { 0x1800 };
+                let y = self.window_line;
+
+                for x in window_x.max(0)..SCREEN_WIDTH as i16 {
+                    bg[x as usize] = self.bg_pixel(map, (x - window_x) as u8, y);
+                }
+
+                self.window_line += 1;
+            }
+        }
+
+        let mut obj = [ObjPixel::default(); SCREEN_WIDTH];
+        if self.lcdc & LCDC_OBJ_ENABLE != 0 {
+            for sprite in &self.sprites {
+                let row = self.sprite_row(sprite);
+
+                for (column, &color) in row.iter().enumerate() {
+                    let x = sprite.x + column as i16;
+                    if x < 0 || x >= SCREEN_WIDTH as i16 {
+                        continue;
+                    }
+
+                    // Sprites are in drawing order, so a pixel is only taken
+                    // over by one with a lower OAM index, and only on CGB.
+                    let pixel = ObjPixel { color, attributes: sprite.attributes, index: sprite.index };
+                    if self.sprite_wins(&pixel, &obj[x as usize]) {
+                        obj[x as usize] = pixel;
+                    }
+                }
+            }
+        }
+
+        for x in 0..SCREEN_WIDTH {
+            self.put_pixel(x, bg[x], obj[x]);
+        }
+    }
+}
+
+// CGB attribute bit 3 selects VRAM bank 1 for the tile data.
+fn tile_bank(attributes: u8) -> usize {
+    if attributes & ATTR_BANK != 0 { VRAM_BANK_SIZE } else { 0 }
+}
+
+fn shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0x03
+}
+
+fn palette_color(palettes: &[u8; 64], palette: u8, color: u8) -> u16 {
+    let index = palette as usize * 8 + color as usize * 2;
+    (palettes[index] as u16 | (palettes[index + 1] as u16) << 8) & 0x7FFF
+}
+
+fn store_colors(ram: &mut [u8], colors: &[u16; 4]) {
+    for (bytes, &color) in ram.chunks_mut(2).zip(colors.iter()) {
+        bytes[0] = color as u8;
+        bytes[1] = (color >> 8) as u8;
+    }
+}
+
+// Expands RGB555 to 8 bits per channel. With correction the channels are
+// mixed and compressed, as the CGB screen shows them.
+fn rgb888(color: u16, correction: bool) -> [u8; 3] {
+    let r = (color & 0x1F) as u32;
+    let g = ((color >> 5) & 0x1F) as u32;
+    let b = ((color >> 10) & 0x1F) as u32;
+
+    if correction {
+        [
+            ((r * 13 + g * 2 + b) >> 1) as u8,
+            ((g * 3 + b) << 1) as u8,
+            ((r * 3 + g * 2 + b * 11) >> 1) as u8,
+        ]
+    } else {
+        [(r << 3 | r >> 2) as u8, (g << 3 | g >> 2) as u8, (b << 3 | b >> 2) as u8]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A sprite at the left edge of line 0 whose color 3 maps to shade 1,
+    // over a background of color 0, in DMG compatibility mode.
+    #[test]
+    fn compat_colors() {
+        let mut ppu = Ppu::new();
+        let mut interrupts = InterruptController::new();
+        ppu.set_compat();
+
+        ppu.store_vram(0x10, 0xFF);
+        ppu.store_vram(0x11, 0xFF);
+        ppu.dma_store_oam(0, 16);
+        ppu.dma_store_oam(1, 8);
+        ppu.dma_store_oam(2, 1);
+        ppu.wb(0xFF47, 0xE4, &mut interrupts);
+        ppu.wb(0xFF48, 0x40, &mut interrupts);
+        ppu.wb(0xFF40, 0x93, &mut interrupts);
+
+        while ppu.ly == 0 {
+            ppu.cycle(1, &mut interrupts);
+        }
+
+        let frame = ppu.color_framebuffer();
+        assert_eq!(frame[0], COMPAT_OBJ[1]);
+        assert_eq!(frame[8], COMPAT_BG[0]);
+        assert_eq!(ppu.frame_rgba()[8 * 4..8 * 4 + 4], [0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    // Once LY has matched WY the window stays on for the frame, even if WY
+    // is moved past LY afterwards.
+    #[test]
+    fn window_stays_latched() {
+        let mut ppu = Ppu::new();
+        let mut interrupts = InterruptController::new();
+
+        for addr in 0x10..0x20 {
+            ppu.store_vram(addr, 0xFF);
+        }
+        for addr in 0x1C00..0x2000 {
+            ppu.store_vram(addr, 1);
+        }
+        ppu.wb(0xFF47, 0xE4, &mut interrupts);
+        ppu.wb(0xFF4B, 7, &mut interrupts);
+        ppu.wb(0xFF40, 0xF1, &mut interrupts);
+
+        while ppu.ly != 10 {
+            ppu.cycle(1, &mut interrupts);
+        }
+        ppu.wb(0xFF4A, 100, &mut interrupts);
+        while ppu.ly != 12 {
+            ppu.cycle(1, &mut interrupts);
+        }
+
+        assert_eq!(ppu.framebuffer()[11 * SCREEN_WIDTH], 3);
+    }
+
+    #[test]
+    fn vbk_switches_vram_bank() {
+        let mut ppu = Ppu::with_cgb(true);
+        let mut interrupts = InterruptController::new();
+        assert_eq!(ppu.rb(0xFF4F), 0xFE);
+        ppu.store_vram(0x0000, 0x11);
+
+        ppu.wb(0xFF4F, 0x01, &mut interrupts);
+        assert_eq!(ppu.rb(0xFF4F), 0xFF);
+        assert_eq!(ppu.load_vram(0x0000), 0x00);
+        ppu.store_vram(0x0000, 0x22);
+
+        // Only bit 0 is decoded.
+        ppu.wb(0xFF4F, 0xFE, &mut interrupts);
+        assert_eq!(ppu.load_vram(0x0000), 0x11);
+        ppu.wb(0xFF4F, 0x03, &mut interrupts);
+        assert_eq!(ppu.load_vram(0x0000), 0x22);
+
+        let mut ppu = Ppu::new();
+        ppu.wb(0xFF4F, 0x01, &mut interrupts);
+        assert_eq!(ppu.rb(0xFF4F), 0xFF);
+        ppu.store_vram(0x0000, 0x33);
+        assert_eq!(ppu.vram[VRAM_BANK_SIZE], 0x00);
+    }
+
+    #[test]
+    fn palette_index_auto_increment() {
+        let mut ppu = Ppu::with_cgb(true);
+        let mut interrupts = InterruptController::new();
+
+        // The index wraps within its six bits and keeps bit 7.
+        ppu.wb(0xFF68, 0xBE, &mut interrupts);
+        ppu.wb(0xFF69, 0x12, &mut interrupts);
+        ppu.wb(0xFF69, 0x34, &mut interrupts);
+        ppu.wb(0xFF69, 0x56, &mut interrupts);
+        assert_eq!(ppu.rb(0xFF68), 0xC1);
+        assert_eq!(&ppu.bg_palettes[0x3E..], &[0x12, 0x34]);
+        assert_eq!(ppu.bg_palettes[0x00], 0x56);
+
+        // Reads never increment, and writes without bit 7 do not either.
+        ppu.wb(0xFF68, 0x3F, &mut interrupts);
+        assert_eq!(ppu.rb(0xFF69), 0x34);
+        assert_eq!(ppu.rb(0xFF69), 0x34);
+        ppu.wb(0xFF69, 0x78, &mut interrupts);
+        assert_eq!(ppu.rb(0xFF68), 0x7F);
+        assert_eq!(ppu.rb(0xFF69), 0x78);
+
+        // Object palettes have their own index.
+        ppu.wb(0xFF6A, 0x81, &mut interrupts);
+        ppu.wb(0xFF6B, 0x9A, &mut interrupts);
+        assert_eq!(ppu.rb(0xFF6A), 0xC2);
+        assert_eq!(ppu.obj_palettes[0x01], 0x9A);
+        assert_eq!(ppu.bg_palettes[0x01], 0xFF);
+    }
+
+    #[test]
+    fn palette_write_blocked_in_transfer_still_increments() {
+        let mut ppu = Ppu::with_cgb(true);
+        let mut interrupts = InterruptController::new();
+        ppu.wb(0xFF40, 0x91, &mut interrupts);
+        while ppu.mode() != Mode::Transfer {
+            ppu.cycle(1, &mut interrupts);
+        }
+
+        // Reads see 0xFF in mode 3 whatever the RAM holds.
+        ppu.wb(0xFF68, 0x80, &mut interrupts);
+        ppu.wb(0xFF69, 0x12, &mut interrupts);
+        assert_eq!(ppu.rb(0xFF69), 0xFF);
+        assert_eq!(ppu.rb(0xFF68), 0xC1);
+        assert_eq!(ppu.bg_palettes[0x00], 0xFF);
+    }
+
+    fn store_palette(ppu: &mut Ppu, register: u16, palette: u8, colors: &[u16; 4], interrupts: &mut InterruptController) {
+        ppu.wb(register, PALETTE_INCREMENT | (palette * 8), interrupts);
+        for &color in colors {
+            ppu.wb(register + 1, color as u8, interrupts);
+            ppu.wb(register + 1, (color >> 8) as u8, interrupts);
+        }
+    }
+
+    // Line 0 of a CGB background whose map attributes pick the bank, the
+    // flips, the palette and the priority of each tile.
+    #[test]
+    fn cgb_tile_attributes() {
+        let mut ppu = Ppu::with_cgb(true);
+        let mut interrupts = InterruptController::new();
+        let colors = [0x0000, 0x001F, 0x03E0, 0x7C00];
+        store_palette(&mut ppu, 0xFF68, 2, &colors, &mut interrupts);
+        store_palette(&mut ppu, 0xFF6A, 0, &[0, 0, 0, 0x7FFF], &mut interrupts);
+
+        // Tile 0 in bank 1 has color 1 at the top left and color 2 at the
+        // bottom right, tile 1 in bank 0 is solid color 3 for the sprite.
+        for addr in 0x10..0x20 {
+            ppu.store_vram(addr, 0xFF);
+        }
+        ppu.wb(0xFF4F, 0x01, &mut interrupts);
+        ppu.store_vram(0x0000, 0x80);
+        ppu.store_vram(0x000F, 0x01);
+        let attributes = [
+            ATTR_BANK | 2,
+            ATTR_BANK | ATTR_X_FLIP | 2,
+            ATTR_BANK | ATTR_Y_FLIP | 2,
+            2,
+            ATTR_BANK | ATTR_BEHIND_BG | 2,
+        ];
+        for (column, &attribute) in attributes.iter().enumerate() {
+            ppu.store_vram(0x1800 + column as u16, attribute);
+        }
+        ppu.wb(0xFF4F, 0x00, &mut interrupts);
+
+        ppu.dma_store_oam(0, 16);
+        ppu.dma_store_oam(1, 8 + 32);
+        ppu.dma_store_oam(2, 1);
+        ppu.wb(0xFF40, 0x93, &mut interrupts);
+        while ppu.ly == 0 {
+            ppu.cycle(1, &mut interrupts);
+        }
+
+        let frame = ppu.color_framebuffer();
+        assert_eq!(frame[0], colors[1]);
+        assert_eq!(frame[1], colors[0]);
+        assert_eq!(frame[8 + 7], colors[1]);
+        assert_eq!(frame[8], colors[0]);
+        assert_eq!(frame[16 + 7], colors[2]);
+        assert_eq!(frame[16], colors[0]);
+        assert_eq!(frame[24], colors[0]);
+        // BG priority only holds over background color 0.
+        assert_eq!(frame[32], colors[1]);
+        assert_eq!(frame[33], 0x7FFF);
+    }
+}
@@ -1,32 +1,805 @@
 use std::path::Path;
 use std::fs::File;
-use std::io::*;
+use std::io::{self, Read};
+use std::error;
+use std::fmt;
+
+pub(crate) const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83,
+    0x00, 0x0C, 0x00, 0x0D, 0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
+    0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63,
+    0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+const HEADER_END: usize = 0x0150;
+
+#[derive(Debug)]
+pub enum HeaderError {
+    Truncated(usize),
+    BadLogo,
+    HeaderChecksum { expected: u8, computed: u8 },
+    GlobalChecksum { expected: u16, computed: u16 },
+    UnknownCartridgeType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeaderError::Truncated(len) => {
+                write!(f, "ROM is {} bytes, too short to contain a header", len)
+            }
+            HeaderError::BadLogo => write!(f, "Nintendo logo does not match"),
+            HeaderError::HeaderChecksum { expected, computed } => {
+                write!(f, "header checksum is {:#04x}, computed {:#04x}", expected, computed)
+            }
+            HeaderError::GlobalChecksum { expected, computed } => {
+                write!(f, "global checksum is {:#06x}, computed {:#06x}", expected, computed)
+            }
+            HeaderError::UnknownCartridgeType(code) => write!(f, "unknown cartridge type {:#04x}", code),
+            HeaderError::UnknownRomSize(code) => write!(f, "unknown ROM size {:#04x}", code),
+            HeaderError::UnknownRamSize(code) => write!(f, "unknown RAM size {:#04x}", code),
+        }
+    }
+}
+
+impl error::Error for HeaderError {}
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    Header(HeaderError),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::Io(ref err) => write!(f, "{}", err),
+            RomError::Header(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> RomError {
+        RomError::Io(err)
+    }
+}
+
+impl From<HeaderError> for RomError {
+    fn from(err: HeaderError) -> RomError {
+        RomError::Header(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,
+    Supported,
+    Only,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Controller {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    Mmm01,
+    PocketCamera,
+    Tama5,
+    HuC1,
+    HuC3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub controller: Controller,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+    pub sensor: bool,
+}
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> Option<CartridgeType> {
+        let (controller, ram, battery, timer, rumble, sensor) = match code {
+            0x00 => (Controller::RomOnly, false, false, false, false, false),
+            0x01 => (Controller::Mbc1, false, false, false, false, false),
+            0x02 => (Controller::Mbc1, true, false, false, false, false),
+            0x03 => (Controller::Mbc1, true, true, false, false, false),
+            0x05 => (Controller::Mbc2, false, false, false, false, false),
+            0x06 => (Controller::Mbc2, false, true, false, false, false),
+            0x08 => (Controller::RomOnly, true, false, false, false, false),
+            0x09 => (Controller::RomOnly, true, true, false, false, false),
+            0x0B => (Controller::Mmm01, false, false, false, false, false),
+            0x0C => (Controller::Mmm01, true, false, false, false, false),
+            0x0D => (Controller::Mmm01, true, true, false, false, false),
+            0x0F => (Controller::Mbc3, false, true, true, false, false),
+            0x10 => (Controller::Mbc3, true, true, true, false, false),
+            0x11 => (Controller::Mbc3, false, false, false, false, false),
+            0x12 => (Controller::Mbc3, true, false, false, false, false),
+            0x13 => (Controller::Mbc3, true, true, false, false, false),
+            0x19 => (Controller::Mbc5, false, false, false, false, false),
+            0x1A => (Controller::Mbc5, true, false, false, false, false),
+            0x1B => (Controller::Mbc5, true, true, false, false, false),
+            0x1C => (Controller::Mbc5, false, false, false, true, false),
+            0x1D => (Controller::Mbc5, true, false, false, true, false),
+            0x1E => (Controller::Mbc5, true, true, false, true, false),
+            0x20 => (Controller::Mbc6, true, true, false, false, false),
+            0x22 => (Controller::Mbc7, true, true, false, true, true),
+            0xFC => (Controller::PocketCamera, true, true, false, false, false),
+            0xFD => (Controller::Tama5, true, true, true, false, false),
+            0xFE => (Controller::HuC3, true, true, true, false, false),
+            0xFF => (Controller::HuC1, true, true, false, false, false),
+            _ => return None,
+        };
+
+        Some(CartridgeType {
+            code,
+            controller,
+            ram,
+            battery,
+            timer,
+            rumble,
+            sensor,
+        })
+    }
+}
+
+impl fmt::Display for CartridgeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.controller)?;
+        if self.ram { write!(f, "+RAM")?; }
+        if self.battery { write!(f, "+BATTERY")?; }
+        if self.timer { write!(f, "+TIMER")?; }
+        if self.rumble { write!(f, "+RUMBLE")?; }
+        if self.sensor { write!(f, "+SENSOR")?; }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomSize {
+    Kib32,
+    Kib64,
+    Kib128,
+    Kib256,
+    Kib512,
+    Mib1,
+    Mib2,
+    Mib4,
+    Mib8,
+    Kib1152,
+    Kib1280,
+    Kib1536,
+}
+
+impl RomSize {
+    pub fn from_code(code: u8) -> Option<RomSize> {
+        match code {
+            0x00 => Some(RomSize::Kib32),
+            0x01 => Some(RomSize::Kib64),
+            0x02 => Some(RomSize::Kib128),
+            0x03 => Some(RomSize::Kib256),
+            0x04 => Some(RomSize::Kib512),
+            0x05 => Some(RomSize::Mib1),
+            0x06 => Some(RomSize::Mib2),
+            0x07 => Some(RomSize::Mib4),
+            0x08 => Some(RomSize::Mib8),
+            0x52 => Some(RomSize::Kib1152),
+            0x53 => Some(RomSize::Kib1280),
+            0x54 => Some(RomSize::Kib1536),
+            _ => None,
+        }
+    }
+
+    // Number of 16 KiB banks.
+    pub fn banks(self) -> usize {
+        match self {
+            RomSize::Kib32 => 2,
+            RomSize::Kib64 => 4,
+            RomSize::Kib128 => 8,
+            RomSize::Kib256 => 16,
+            RomSize::Kib512 => 32,
+            RomSize::Mib1 => 64,
+            RomSize::Mib2 => 128,
+            RomSize::Mib4 => 256,
+            RomSize::Mib8 => 512,
+            RomSize::Kib1152 => 72,
+            RomSize::Kib1280 => 80,
+            RomSize::Kib1536 => 96,
+        }
+    }
+
+    pub fn bytes(self) -> usize {
+        self.banks() * 0x4000
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RamSize {
+    None,
+    Kib2,
+    Kib8,
+    Kib32,
+    Kib64,
+    Kib128,
+}
+
+impl RamSize {
+    pub fn from_code(code: u8) -> Option<RamSize> {
+        match code {
+            0x00 => Some(RamSize::None),
+            0x01 => Some(RamSize::Kib2),
+            0x02 => Some(RamSize::Kib8),
+            0x03 => Some(RamSize::Kib32),
+            0x04 => Some(RamSize::Kib128),
+            0x05 => Some(RamSize::Kib64),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(self) -> usize {
+        match self {
+            RamSize::None => 0,
+            RamSize::Kib2 => 0x800,
+            RamSize::Kib8 => 0x2000,
+            RamSize::Kib32 => 0x8000,
+            RamSize::Kib64 => 0x10000,
+            RamSize::Kib128 => 0x20000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+// Publishers are identified by a one-byte code, or when that byte is 0x33
+// by a two-character code stored at 0x0144.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Licensee {
+    Old(u8),
+    New(String),
+}
+
+impl Licensee {
+    pub fn name(&self) -> Option<&'static str> {
+        match *self {
+            Licensee::Old(code) => old_licensee_name(code),
+            Licensee::New(ref code) => new_licensee_name(code),
+        }
+    }
+}
+
+impl fmt::Display for Licensee {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = self.name() {
+            return write!(f, "{}", name);
+        }
+
+        match *self {
+            Licensee::Old(code) => write!(f, "unknown ({:02X})", code),
+            Licensee::New(ref code) => write!(f, "unknown ({})", code),
+        }
+    }
+}
+
+fn old_licensee_name(code: u8) -> Option<&'static str> {
+    let name = match code {
+        0x00 => "None",
+        0x01 | 0x31 => "Nintendo",
+        0x08 | 0x38 => "Capcom",
+        0x09 => "Hot-B",
+        0x0A | 0xE0 => "Jaleco",
+        0x0B => "Coconuts Japan",
+        0x0C | 0x6E => "Elite Systems",
+        0x13 | 0x69 => "Electronic Arts",
+        0x18 => "Hudson Soft",
+        0x19 => "ITC Entertainment",
+        0x1A => "Yanoman",
+        0x1D => "Japan Clary",
+        0x1F | 0x4A | 0x61 => "Virgin Interactive",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 => "Kotobuki Systems",
+        0x29 => "Seta",
+        0x30 | 0x70 => "Infogrames",
+        0x32 | 0xA2 | 0xB2 => "Bandai",
+        0x34 | 0xA4 => "Konami",
+        0x35 => "HectorSoft",
+        0x39 | 0x9D | 0xD9 => "Banpresto",
+        0x3C => "Entertainment Interactive",
+        0x3E => "Gremlin",
+        0x41 => "Ubi Soft",
+        0x42 | 0xEB => "Atlus",
+        0x44 | 0x4D => "Malibu Interactive",
+        0x46 | 0xCF => "Angel",
+        0x47 => "Spectrum HoloByte",
+        0x49 => "Irem",
+        0x4F => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 | 0xB0 => "Acclaim Entertainment",
+        0x52 => "Activision",
+        0x53 => "Sammy USA Corporation",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 | 0xDB | 0xFF => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley Company",
+        0x5A => "Mindscape",
+        0x5B => "Romstar",
+        0x5C | 0xD6 => "Naxat Soft",
+        0x5D => "Tradewest",
+        0x60 => "Titus Interactive",
+        0x67 => "Ocean Software",
+        0x6F => "Electro Brain",
+        0x71 => "Interplay Entertainment",
+        0x72 | 0xAA => "Broderbund",
+        0x73 => "Sculptured Software",
+        0x75 => "The Sales Curve Limited",
+        0x78 => "THQ",
+        0x79 => "Accolade",
+        0x7A => "Triffix Entertainment",
+        0x7C => "MicroProse",
+        0x7F | 0xC2 => "Kemco",
+        0x80 => "Misawa Entertainment",
+        0x83 => "LOZC G.",
+        0x86 | 0xC4 => "Tokuma Shoten",
+        0x8B => "Bullet-Proof Software",
+        0x8C => "Vic Tokai Corp.",
+        0x8E => "Ape Inc.",
+        0x8F => "I'Max",
+        0x91 => "Chunsoft Co.",
+        0x92 => "Video System",
+        0x93 => "Tsubaraya Productions",
+        0x95 | 0xE3 => "Varie",
+        0x96 => "Yonezawa/S'Pal",
+        0x97 => "Kemco",
+        0x99 => "Arc",
+        0x9A => "Nihon Bussan",
+        0x9B => "Tecmo",
+        0x9C => "Imagineer",
+        0x9F => "Nova",
+        0xA1 => "Hori Electric",
+        0xA6 => "Kawada",
+        0xA7 => "Takara",
+        0xA9 => "Technos Japan",
+        0xAC => "Toei Animation",
+        0xAD => "Toho",
+        0xAF => "Namco",
+        0xB1 => "ASCII Corporation or Nexsoft",
+        0xB4 => "Square Enix",
+        0xB6 => "HAL Laboratory",
+        0xB7 => "SNK",
+        0xB9 | 0xCE => "Pony Canyon",
+        0xBA => "Culture Brain",
+        0xBB => "Sunsoft",
+        0xBD => "Sony Imagesoft",
+        0xBF => "Sammy Corporation",
+        0xC0 | 0xD0 => "Taito",
+        0xC3 => "Square",
+        0xC5 => "Data East",
+        0xC6 => "Tonkin House",
+        0xC8 => "Koei",
+        0xC9 => "UFL",
+        0xCA => "Ultra Games",
+        0xCB => "VAP, Inc.",
+        0xCC => "Use Corporation",
+        0xCD => "Meldac",
+        0xD1 => "SOFEL",
+        0xD2 => "Quest",
+        0xD3 => "Sigma Enterprises",
+        0xD4 => "ASK Kodansha Co.",
+        0xD7 => "Copya System",
+        0xDA => "Tomy",
+        0xDD => "Nippon Computer Systems",
+        0xDE => "Human Ent.",
+        0xDF => "Altron",
+        0xE1 => "Towa Chiki",
+        0xE2 => "Yutaka",
+        0xE5 => "Epoch",
+        0xE7 => "Athena",
+        0xE8 => "Asmik Ace Entertainment",
+        0xE9 => "Natsume",
+        0xEA => "King Records",
+        0xEC => "Epic/Sony Records",
+        0xEE => "IGS",
+        0xF0 => "A Wave",
+        0xF3 => "Extreme Entertainment",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn new_licensee_name(code: &str) -> Option<&'static str> {
+    let name = match code {
+        "00" => "None",
+        "01" | "31" => "Nintendo",
+        "08" => "Capcom",
+        "13" | "69" => "Electronic Arts",
+        "18" | "38" => "Hudson Soft",
+        "19" => "B-AI",
+        "20" => "KSS",
+        "22" => "Planning Office WADA",
+        "24" => "PCM Complete",
+        "25" => "San-X",
+        "28" => "Kemco",
+        "29" => "SETA Corporation",
+        "30" => "Viacom",
+        "32" => "Bandai",
+        "33" | "93" => "Ocean Software/Acclaim Entertainment",
+        "34" | "54" | "A4" => "Konami",
+        "35" => "HectorSoft",
+        "37" => "Taito",
+        "39" => "Banpresto",
+        "41" => "Ubi Soft",
+        "42" => "Atlus",
+        "44" => "Malibu Interactive",
+        "46" => "Angel",
+        "47" => "Bullet-Proof Software",
+        "49" => "Irem",
+        "50" => "Absolute",
+        "51" => "Acclaim Entertainment",
+        "52" => "Activision",
+        "53" => "Sammy USA Corporation",
+        "55" => "Hi Tech Expressions",
+        "56" => "LJN",
+        "57" => "Matchbox",
+        "58" => "Mattel",
+        "59" => "Milton Bradley Company",
+        "60" => "Titus Interactive",
+        "61" => "Virgin Games Ltd.",
+        "64" => "Lucasfilm Games",
+        "67" => "Ocean Software",
+        "70" => "Infogrames",
+        "71" => "Interplay Entertainment",
+        "72" => "Broderbund",
+        "73" => "Sculptured Software",
+        "75" => "The Sales Curve Limited",
+        "78" => "THQ",
+        "79" => "Accolade",
+        "80" => "Misawa Entertainment",
+        "83" => "LOZC G.",
+        "86" => "Tokuma Shoten",
+        "87" => "Tsukuda Original",
+        "91" => "Chunsoft Co.",
+        "92" => "Video System",
+        "95" => "Varie",
+        "96" => "Yonezawa/S'Pal",
+        "97" => "Kaneko",
+        "99" => "Pack-In-Video",
+        "9H" => "Bottom Up",
+        "BL" => "MTO",
+        "DK" => "Kodansha",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer: Option<String>,
+    pub licensee: Licensee,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: RomSize,
+    pub ram_size: RamSize,
+    pub destination: Destination,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    // Decodes the header fields. Integrity checks that real hardware does
+    // not enforce are left to `validate`.
+    pub fn parse(data: &[u8]) -> Result<CartridgeHeader, HeaderError> {
+        if data.len() < HEADER_END {
+            return Err(HeaderError::Truncated(data.len()));
+        }
+
+        let cgb = match data[0x0143] {
+            0xC0 => CgbSupport::Only,
+            flag if flag & 0x80 != 0 => CgbSupport::Supported,
+            _ => CgbSupport::None,
+        };
+
+        // Newer cartridges shorten the title to make room for a
+        // four-character manufacturer code and the CGB flag.
+        let code = &data[0x013F..0x0143];
+        let has_manufacturer = cgb != CgbSupport::None &&
+            code.iter().all(|&b| b.is_ascii_uppercase() || b.is_ascii_digit());
+
+        let (title_end, manufacturer) = if has_manufacturer {
+            (0x013F, Some(String::from_utf8_lossy(code).into_owned()))
+        } else if cgb != CgbSupport::None {
+            (0x0143, None)
+        } else {
+            (0x0144, None)
+        };
+
+        let title = data[0x0134..title_end]
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '?' })
+            .collect::<String>();
+
+        let licensee = match data[0x014B] {
+            0x33 => Licensee::New(String::from_utf8_lossy(&data[0x0144..0x0146]).into_owned()),
+            code => Licensee::Old(code),
+        };
+
+        let cartridge_type = CartridgeType::from_code(data[0x0147])
+            .ok_or(HeaderError::UnknownCartridgeType(data[0x0147]))?;
+        let rom_size = RomSize::from_code(data[0x0148])
+            .ok_or(HeaderError::UnknownRomSize(data[0x0148]))?;
+        let ram_size = RamSize::from_code(data[0x0149])
+            .ok_or(HeaderError::UnknownRamSize(data[0x0149]))?;
+
+        Ok(CartridgeHeader {
+            title,
+            manufacturer,
+            licensee,
+            cgb,
+            sgb: data[0x0146] == 0x03,
+            cartridge_type,
+            rom_size,
+            ram_size,
+            destination: if data[0x014A] == 0x00 { Destination::Japan } else { Destination::Overseas },
+            version: data[0x014C],
+            header_checksum: data[0x014D],
+            global_checksum: (data[0x014E] as u16) << 8 | data[0x014F] as u16,
+        })
+    }
+
+    // Checks the logo and both checksums. The boot ROM only refuses to
+    // start on a bad logo or header checksum, the global checksum is
+    // never verified by hardware.
+    pub fn validate(&self, data: &[u8]) -> Result<(), HeaderError> {
+        if data.len() < HEADER_END {
+            return Err(HeaderError::Truncated(data.len()));
+        }
+
+        if !has_logo(data, 0x0104) {
+            return Err(HeaderError::BadLogo);
+        }
+
+        let computed = data[0x0134..0x014D]
+            .iter()
+            .fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1));
+        if computed != self.header_checksum {
+            return Err(HeaderError::HeaderChecksum { expected: self.header_checksum, computed });
+        }
+
+        let computed = data
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 0x014E && i != 0x014F)
+            .fold(0u16, |x, (_, &b)| x.wrapping_add(b as u16));
+        if computed != self.global_checksum {
+            return Err(HeaderError::GlobalChecksum { expected: self.global_checksum, computed });
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.title)?;
+        if let Some(ref manufacturer) = self.manufacturer {
+            write!(f, " [{}]", manufacturer)?;
+        }
+        write!(
+            f,
+            " v{} ({}, {:?}) {} ROM {} KiB RAM {} KiB CGB {:?} SGB {} checksums {:02X}/{:04X}",
+            self.version,
+            self.licensee,
+            self.destination,
+            self.cartridge_type,
+            self.rom_size.bytes() / 1024,
+            self.ram_size.bytes() / 1024,
+            self.cgb,
+            self.sgb,
+            self.header_checksum,
+            self.global_checksum
+        )
+    }
+}
+
+fn has_logo(data: &[u8], offset: usize) -> bool {
+    data.get(offset..offset + NINTENDO_LOGO.len()) == Some(&NINTENDO_LOGO[..])
+}
 
 pub struct Rom {
     data: Vec<u8>,
+    pub header: CartridgeHeader,
 }
 
 impl Rom {
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<Rom> {
-        let mut file = try!(File::open(&path));
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Rom, RomError> {
+        let mut file = File::open(&path)?;
 
         let mut data = Vec::new();
 
-        try!(file.read_to_end(&mut data));
+        file.read_to_end(&mut data)?;
+
+        Rom::from_bytes(data)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<Rom, RomError> {
+        let mut header = CartridgeHeader::parse(&data);
 
-        Ok(Rom { data: data })
+        // MMM01 multicarts boot into a menu stored in the last 32 KiB, and
+        // only that copy of the header names the controller.
+        if data.len() >= 0x8000 {
+            if let Ok(menu) = CartridgeHeader::parse(&data[data.len() - 0x8000..]) {
+                if menu.cartridge_type.controller == Controller::Mmm01 {
+                    header = Ok(menu);
+                }
+            }
+        }
+
+        Ok(Rom { data, header: header? })
+    }
+
+    pub fn validate(&self) -> Result<(), HeaderError> {
+        self.header.validate(&self.data)
+    }
+
+    // Multicarts repeat the header of each game at the start of its
+    // bank group, so the logo doubles as a signature.
+    pub fn has_logo_at(&self, bank: usize) -> bool {
+        has_logo(&self.data, bank * 0x4000 + 0x0104)
+    }
+
+    // Offsets past the end of the image wrap around, which is what unused
+    // high bank bits do on a real cartridge.
+    pub fn load8(&self, offset: usize) -> u8 {
+        self.data[offset % self.data.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fix_checksums(data: &mut [u8]) {
+        data[0x014D] = data[0x0134..0x014D].iter().fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1));
+
+        let global = data
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 0x014E && i != 0x014F)
+            .fold(0u16, |x, (_, &b)| x.wrapping_add(b as u16));
+        data[0x014E] = (global >> 8) as u8;
+        data[0x014F] = global as u8;
+    }
+
+    // A 32 KiB MBC1 image with a logo, a title and both checksums right.
+    fn image() -> Vec<u8> {
+        let mut data = vec![0; 0x8000];
+        data[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+        data[0x0134..0x0138].copy_from_slice(b"TEST");
+        data[0x0147] = 0x03;
+        data[0x0149] = 0x02;
+        data[0x014B] = 0x01;
+        data[0x014C] = 0x02;
+        fix_checksums(&mut data);
+        data
+    }
+
+    #[test]
+    fn valid_header() {
+        let data = image();
+        let header = CartridgeHeader::parse(&data).unwrap();
+        assert!(header.validate(&data).is_ok());
+
+        assert_eq!(header.title, "TEST");
+        assert_eq!(header.manufacturer, None);
+        assert_eq!(header.licensee, Licensee::Old(0x01));
+        assert_eq!(header.cgb, CgbSupport::None);
+        assert_eq!(header.cartridge_type.controller, Controller::Mbc1);
+        assert!(header.cartridge_type.ram && header.cartridge_type.battery);
+        assert_eq!(header.rom_size, RomSize::Kib32);
+        assert_eq!(header.ram_size.bytes(), 0x2000);
+        assert_eq!(header.destination, Destination::Japan);
+        assert_eq!(header.version, 0x02);
+    }
+
+    #[test]
+    fn one_byte_short() {
+        let data = image();
+        let short = &data[..HEADER_END - 1];
+        assert!(matches!(CartridgeHeader::parse(short), Err(HeaderError::Truncated(0x014F))));
+
+        let header = CartridgeHeader::parse(&data).unwrap();
+        assert!(matches!(header.validate(short), Err(HeaderError::Truncated(0x014F))));
     }
 
-    pub fn load8(&self, offset: u16) -> u8 {
-        self.data[offset as usize]
+    #[test]
+    fn corrupted_logo() {
+        let mut data = image();
+        data[0x0110] ^= 0x01;
+        fix_checksums(&mut data);
+
+        let header = CartridgeHeader::parse(&data).unwrap();
+        assert!(matches!(header.validate(&data), Err(HeaderError::BadLogo)));
     }
 
-    pub fn load16(&self, offset: u16) -> u16 {
-        let offset = offset as usize;
+    #[test]
+    fn wrong_header_checksum() {
+        let mut data = image();
+        let computed = data[0x014D];
+        data[0x014D] = computed.wrapping_add(1);
+
+        let header = CartridgeHeader::parse(&data).unwrap();
+        match header.validate(&data) {
+            Err(HeaderError::HeaderChecksum { expected, computed: found }) => {
+                assert_eq!(expected, computed.wrapping_add(1));
+                assert_eq!(found, computed);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn wrong_global_checksum() {
+        let mut data = image();
+        data[0x7FFF] = 0x01;
+
+        let header = CartridgeHeader::parse(&data).unwrap();
+        match header.validate(&data) {
+            Err(HeaderError::GlobalChecksum { expected, computed }) => {
+                assert_eq!(computed, expected.wrapping_add(1));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    fn parse_with(offset: usize, code: u8) -> Result<CartridgeHeader, HeaderError> {
+        let mut data = image();
+        data[offset] = code;
+        CartridgeHeader::parse(&data)
+    }
+
+    #[test]
+    fn unknown_codes() {
+        assert!(matches!(parse_with(0x0147, 0x04), Err(HeaderError::UnknownCartridgeType(0x04))));
+        assert!(matches!(parse_with(0x0148, 0x09), Err(HeaderError::UnknownRomSize(0x09))));
+        assert!(matches!(parse_with(0x0149, 0x06), Err(HeaderError::UnknownRamSize(0x06))));
+    }
 
-        let b0 = self.data[offset + 0] as u16;
-        let b1 = self.data[offset + 1] as u16;
+    // A CGB title gives up its last four characters to a manufacturer
+    // code, and the new licensee code is two characters.
+    #[test]
+    fn cgb_title_and_licensee() {
+        let mut data = image();
+        data[0x0134..0x0143].copy_from_slice(b"POKEMON CRYAPUE");
+        data[0x0143] = 0x80;
+        data[0x0144..0x0146].copy_from_slice(b"01");
+        data[0x014B] = 0x33;
+        fix_checksums(&mut data);
 
-        b0 | (b1 << 8)
+        let header = CartridgeHeader::parse(&data).unwrap();
+        assert_eq!(header.title, "POKEMON CRY");
+        assert_eq!(header.manufacturer.as_deref(), Some("APUE"));
+        assert_eq!(header.cgb, CgbSupport::Supported);
+        assert_eq!(header.licensee, Licensee::New("01".to_string()));
+        assert!(header.validate(&data).is_ok());
     }
 }
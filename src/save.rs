@@ -0,0 +1,157 @@
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use cartridge::Cartridge;
+
+// Where a cartridge's battery backed RAM lives between runs. Saves are
+// named after the ROM with a .sav extension, next to it unless a save
+// directory is given.
+pub struct SaveFile {
+    path: PathBuf,
+}
+
+impl SaveFile {
+    pub fn new<P: AsRef<Path>>(rom_path: P, save_dir: Option<&Path>) -> SaveFile {
+        let rom_path = rom_path.as_ref();
+        let path = match (save_dir, rom_path.file_name()) {
+            (Some(dir), Some(name)) => dir.join(name).with_extension("sav"),
+            _ => rom_path.with_extension("sav"),
+        };
+
+        SaveFile { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // A missing save is not an error, the cartridge keeps its blank RAM.
+    pub fn load(&self, cartridge: &mut Cartridge) -> io::Result<()> {
+        if !cartridge.has_battery() {
+            return Ok(());
+        }
+
+        let mut data = Vec::new();
+        match File::open(&self.path) {
+            Ok(mut file) => file.read_to_end(&mut data)?,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        cartridge.load_save_data(&data);
+        cartridge.clear_dirty();
+        Ok(())
+    }
+
+    // Writes to a temporary file beside the save and renames it over the
+    // old one, so a crash leaves either the old or the new save intact.
+    pub fn store(&self, cartridge: &mut Cartridge) -> io::Result<()> {
+        if !cartridge.has_battery() {
+            return Ok(());
+        }
+
+        if let Some(dir) = self.path.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+
+        let tmp = self.path.with_extension("sav.tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(&cartridge.save_data())?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+
+        cartridge.clear_dirty();
+        Ok(())
+    }
+
+    // Only writes when the battery backed state changed since the last load
+    // or store.
+    pub fn flush(&self, cartridge: &mut Cartridge) -> io::Result<()> {
+        if cartridge.is_dirty() {
+            self.store(cartridge)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::tests::rom;
+    use cartridge::FakeClock;
+    use std::env;
+    use std::process;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("gb-save-{}-{}", name, process::id()))
+    }
+
+    fn cartridge(cartridge_type: u8, ram_size: u8) -> Cartridge {
+        Cartridge::with_clock(rom(cartridge_type, ram_size), Box::new(FakeClock::new(0)))
+    }
+
+    #[test]
+    fn save_dir_keeps_rom_name() {
+        let save = SaveFile::new("roms/game.gb", Some(Path::new("saves")));
+        assert_eq!(save.path(), Path::new("saves/game.sav"));
+
+        let save = SaveFile::new("roms/game.gb", None);
+        assert_eq!(save.path(), Path::new("roms/game.sav"));
+    }
+
+    #[test]
+    fn flush_then_load_round_trips() {
+        let dir = temp_dir("round-trip");
+        let save = SaveFile::new("game.gb", Some(&dir));
+
+        let mut cartridge = cartridge(0x03, 0x02);
+        save.load(&mut cartridge).unwrap();
+        save.flush(&mut cartridge).unwrap();
+        assert!(!save.path().exists());
+
+        cartridge.store_rom(0x0000, 0x0A);
+        cartridge.store_ram(0x0123, 0x45);
+        save.flush(&mut cartridge).unwrap();
+        assert!(!cartridge.is_dirty());
+        assert_eq!(fs::metadata(save.path()).unwrap().len(), 0x2000);
+
+        let mut loaded = self::cartridge(0x03, 0x02);
+        save.load(&mut loaded).unwrap();
+        assert!(!loaded.is_dirty());
+        loaded.store_rom(0x0000, 0x0A);
+        assert_eq!(loaded.load_ram(0x0123), 0x45);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rtc_footer_follows_ram() {
+        let dir = temp_dir("rtc");
+        let save = SaveFile::new("game.gb", Some(&dir));
+
+        // A clock that was never saved is dirty from the start.
+        let mut cartridge = cartridge(0x10, 0x02);
+        save.flush(&mut cartridge).unwrap();
+        assert_eq!(fs::metadata(save.path()).unwrap().len(), 0x2000 + 48);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn no_battery_no_file() {
+        let dir = temp_dir("no-battery");
+        let save = SaveFile::new("game.gb", Some(&dir));
+
+        let mut cartridge = cartridge(0x02, 0x02);
+        cartridge.store_rom(0x0000, 0x0A);
+        cartridge.store_ram(0x0000, 0x12);
+        save.store(&mut cartridge).unwrap();
+        assert!(!dir.exists());
+    }
+}
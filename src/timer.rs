@@ -1,74 +1,295 @@
+use interrupt::{Interrupt, InterruptController};
+use model::EmulatedModel;
 
+// T-cycles between TIMA overflowing and TMA being loaded, and how long
+// the cycle after that lasts.
+const RELOAD_DELAY: u8 = 4;
+
+// Everything runs off a 16-bit counter incremented every T-cycle, DIV is
+// its high byte. TIMA counts the falling edges of one counter bit, picked
+// by TAC and ANDed with the enable bit, so anything that drops that signal
+// counts: DIV resets and TAC writes as much as the counter itself.
 pub struct Timer {
-	divider: u8,
-	counter: u8,
-	modulo: u8,
-	enabled: bool,
-	step: u32,
-	internalcnt: u32,
-	internaldiv: u32,
-	pub interrupt: u8,
+	counter: u16,
+	tima: u8,
+	tma: u8,
+	tac: u8,
+	// T-cycles until TMA is loaded after an overflow. TIMA reads 0x00
+	// meanwhile and writing it cancels the reload and the interrupt.
+	reload_delay: u8,
+	// T-cycles left in the M-cycle TMA was loaded in. TIMA writes are lost
+	// and TMA writes go through to TIMA as well.
+	reloading: u8,
 }
 
 impl Timer {
 	pub fn new() -> Timer {
 		Timer {
-			divider: 0,
 			counter: 0,
-			modulo: 0,
-			enabled: true,
-			step: 256,
-			internalcnt: 0,
-			internaldiv: 0,
-			interrupt: 0,
+			tima: 0,
+			tma: 0,
+			tac: 0,
+			reload_delay: 0,
+			reloading: 0,
 		}
 	}
 
+	// The divider where each boot ROM leaves it at 0x0100, DIV in the high
+	// byte. Test ROMs read DIV right away to tell the models apart. The
+	// SGB ones wait on the SNES, so theirs is not fixed and the DMG value
+	// is used. The CGB boot ROM takes longer with a DMG cartridge.
+	pub fn post_boot(model: EmulatedModel, cgb_mode: bool) -> Timer {
+		let counter = match model {
+			EmulatedModel::Dmg0 => 0x182C,
+			EmulatedModel::Dmg | EmulatedModel::Mgb => 0xABCC,
+			EmulatedModel::Sgb | EmulatedModel::Sgb2 => 0xABCC,
+			EmulatedModel::Cgb | EmulatedModel::Agb if cgb_mode => 0x1EA0,
+			EmulatedModel::Cgb | EmulatedModel::Agb => 0x267C,
+		};
+
+		let mut timer = Timer::new();
+		timer.counter = counter;
+		timer
+	}
+
+	// The whole system counter, the APU frame sequencer is clocked from it.
+	pub fn counter(&self) -> u16 {
+		self.counter
+	}
+
 	pub fn rb(&self, a: u16) -> u8 {
 		match a {
-			0xFF04 => self.divider,
-			0xFF05 => self.counter,
-			0xFF06 => self.modulo,
-			0xFF07 => {
-				(if self.enabled { 0x4 } else { 0 }) |
-				(match self.step { 4 => 1, 16 => 2, 64 => 3, _ => 0 })
-			}
+			0xFF04 => (self.counter >> 8) as u8,
+			0xFF05 => self.tima,
+			0xFF06 => self.tma,
+			0xFF07 => 0xF8 | self.tac,
 			_ => panic!("Timer does not handler read {:4X}", a),
 		}
 	}
 
 	pub fn wb(&mut self, a: u16, v: u8) {
 		match a {
-			0xFF04 => { self.divider = 0; },
-			0xFF05 => { self.counter = v; },
-			0xFF06 => { self.modulo = v; },
+			0xFF04 => { self.set_counter(0); },
+			0xFF05 => {
+				if self.reloading == 0 {
+					self.tima = v;
+					self.reload_delay = 0;
+				}
+			},
+			0xFF06 => {
+				self.tma = v;
+				if self.reloading != 0 {
+					self.tima = v;
+				}
+			},
 			0xFF07 => {
-				self.enabled = v & 0x4 == 0x4;
-				self.step = match v & 0x3 { 1 => 4, 2 => 16, 3 => 64, _ => 256 };
+				let signal = self.signal();
+				self.tac = v & 0x07;
+				if signal && !self.signal() {
+					self.increment();
+				}
 			},
 			_ => panic!("Timer does not handler write {:4X}", a),
 		};
 	}
 
-	pub fn cycle(&mut self, ticks: u32) {
-		self.internaldiv += ticks;
-		while self.internaldiv >= 64 {
-			self.divider = self.divider.wrapping_add(1);
-			self.internaldiv -= 64;
-		}
-
-		if self.enabled {
-			self.internalcnt += ticks;
+	pub fn cycle(&mut self, ticks: u32, interrupts: &mut InterruptController) {
+		for _ in 0..ticks {
+			if self.reloading != 0 {
+				self.reloading -= 1;
+			}
 
-			while self.internalcnt >= self.step {
-				self.counter = self.counter.wrapping_add(1);
-				if self.counter == 0 {
-					self.counter = self.modulo;
-					self.interrupt |= 0x04;
+			if self.reload_delay != 0 {
+				self.reload_delay -= 1;
+				if self.reload_delay == 0 {
+					self.tima = self.tma;
+					self.reloading = RELOAD_DELAY;
+					interrupts.request(Interrupt::Timer);
 				}
-				self.internalcnt -= self.step;
 			}
+
+			let counter = self.counter.wrapping_add(1);
+			self.set_counter(counter);
+		}
+	}
+
+	fn set_counter(&mut self, counter: u16) {
+		let signal = self.signal();
+		self.counter = counter;
+		if signal && !self.signal() {
+			self.increment();
+		}
+	}
+
+	// The counter bit selected by TAC, gated by the enable bit.
+	fn signal(&self) -> bool {
+		let bit = match self.tac & 0x03 {
+			1 => 3,
+			2 => 5,
+			3 => 7,
+			_ => 9,
+		};
+		self.tac & 0x04 != 0 && self.counter & (1 << bit) != 0
+	}
+
+	fn increment(&mut self) {
+		let (tima, overflow) = self.tima.overflowing_add(1);
+		self.tima = tima;
+		if overflow {
+			self.reload_delay = RELOAD_DELAY;
 		}
 	}
 }
 
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// TAC 0x05 counts on bit 3, every 16 T-cycles.
+	fn counting(tima: u8) -> (Timer, InterruptController) {
+		let mut timer = Timer::new();
+		timer.wb(0xFF07, 0x05);
+		timer.wb(0xFF06, 0x42);
+		timer.wb(0xFF05, tima);
+		(timer, InterruptController::new())
+	}
+
+	// Runs up to the M-cycle TIMA overflows in.
+	fn overflow(timer: &mut Timer, interrupts: &mut InterruptController) {
+		timer.cycle(16, interrupts);
+		assert_eq!(timer.rb(0xFF05), 0x00);
+		assert!(!interrupts.is_requested(Interrupt::Timer));
+	}
+
+	#[test]
+	fn counts_falling_edges() {
+		let (mut timer, mut interrupts) = counting(0x00);
+		timer.cycle(15, &mut interrupts);
+		assert_eq!(timer.rb(0xFF05), 0x00);
+		timer.cycle(1, &mut interrupts);
+		assert_eq!(timer.rb(0xFF05), 0x01);
+		timer.cycle(16 * 10, &mut interrupts);
+		assert_eq!(timer.rb(0xFF05), 0x0B);
+	}
+
+	#[test]
+	fn reload_is_delayed() {
+		let (mut timer, mut interrupts) = counting(0xFF);
+		overflow(&mut timer, &mut interrupts);
+
+		timer.cycle(3, &mut interrupts);
+		assert_eq!(timer.rb(0xFF05), 0x00);
+		assert!(!interrupts.is_requested(Interrupt::Timer));
+
+		timer.cycle(1, &mut interrupts);
+		assert_eq!(timer.rb(0xFF05), 0x42);
+		assert!(interrupts.is_requested(Interrupt::Timer));
+	}
+
+	#[test]
+	fn tima_write_cancels_reload() {
+		let (mut timer, mut interrupts) = counting(0xFF);
+		overflow(&mut timer, &mut interrupts);
+
+		timer.wb(0xFF05, 0x10);
+		timer.cycle(8, &mut interrupts);
+		assert_eq!(timer.rb(0xFF05), 0x10);
+		assert!(!interrupts.is_requested(Interrupt::Timer));
+	}
+
+	#[test]
+	fn tima_write_during_reload_is_dropped() {
+		let (mut timer, mut interrupts) = counting(0xFF);
+		overflow(&mut timer, &mut interrupts);
+		timer.cycle(4, &mut interrupts);
+
+		timer.wb(0xFF05, 0x10);
+		assert_eq!(timer.rb(0xFF05), 0x42);
+		assert!(interrupts.is_requested(Interrupt::Timer));
+
+		// The cycle after, TIMA can be written again.
+		timer.cycle(4, &mut interrupts);
+		timer.wb(0xFF05, 0x10);
+		assert_eq!(timer.rb(0xFF05), 0x10);
+	}
+
+	#[test]
+	fn tma_write_during_reload_is_picked_up() {
+		let (mut timer, mut interrupts) = counting(0xFF);
+		overflow(&mut timer, &mut interrupts);
+		timer.cycle(4, &mut interrupts);
+
+		timer.wb(0xFF06, 0x77);
+		assert_eq!(timer.rb(0xFF05), 0x77);
+
+		timer.cycle(4, &mut interrupts);
+		timer.wb(0xFF06, 0x99);
+		assert_eq!(timer.rb(0xFF05), 0x77);
+	}
+
+	#[test]
+	fn div_write_glitches_tima() {
+		// Bit 3 is set, resetting the counter makes it fall.
+		let (mut timer, mut interrupts) = counting(0x00);
+		timer.cycle(8, &mut interrupts);
+		timer.wb(0xFF04, 0x00);
+		assert_eq!(timer.rb(0xFF05), 0x01);
+		assert_eq!(timer.rb(0xFF04), 0x00);
+
+		// With bit 3 clear nothing happens.
+		let (mut timer, mut interrupts) = counting(0x00);
+		timer.cycle(4, &mut interrupts);
+		timer.wb(0xFF04, 0x00);
+		assert_eq!(timer.rb(0xFF05), 0x00);
+	}
+
+	#[test]
+	fn div_write_overflow_reloads() {
+		let (mut timer, mut interrupts) = counting(0xFF);
+		timer.cycle(8, &mut interrupts);
+		timer.wb(0xFF04, 0x00);
+		assert_eq!(timer.rb(0xFF05), 0x00);
+
+		timer.cycle(4, &mut interrupts);
+		assert_eq!(timer.rb(0xFF05), 0x42);
+		assert!(interrupts.is_requested(Interrupt::Timer));
+	}
+
+	#[test]
+	fn tac_disable_glitches_tima() {
+		let (mut timer, mut interrupts) = counting(0x00);
+		timer.cycle(8, &mut interrupts);
+		timer.wb(0xFF07, 0x01);
+		assert_eq!(timer.rb(0xFF05), 0x01);
+
+		// Disabled, the counter no longer reaches TIMA.
+		timer.cycle(64, &mut interrupts);
+		assert_eq!(timer.rb(0xFF05), 0x01);
+
+		// Enabling never glitches, the signal only rises.
+		timer.cycle(8, &mut interrupts);
+		timer.wb(0xFF07, 0x05);
+		assert_eq!(timer.rb(0xFF05), 0x01);
+	}
+
+	#[test]
+	fn tac_frequency_change_glitches_tima() {
+		// Bit 3 high and bit 9 low: switching to 4096 Hz drops the signal.
+		let (mut timer, mut interrupts) = counting(0x00);
+		timer.cycle(8, &mut interrupts);
+		timer.wb(0xFF07, 0x04);
+		assert_eq!(timer.rb(0xFF05), 0x01);
+
+		// Bit 3 low: switching to bit 5, which is high, does not.
+		let (mut timer, mut interrupts) = counting(0x00);
+		timer.cycle(32, &mut interrupts);
+		timer.wb(0xFF07, 0x06);
+		assert_eq!(timer.rb(0xFF05), 0x02);
+	}
+
+	#[test]
+	fn tac_reads_unused_bits_set() {
+		let (timer, _) = counting(0x00);
+		assert_eq!(timer.rb(0xFF07), 0xFD);
+	}
+}
@@ -0,0 +1,188 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use apu::{Apu, Channel, CHANNELS};
+
+// The RIFF size counts everything after itself, 36 bytes of header and
+// the data, in 32 bits.
+const MAX_DATA_BYTES: u32 = u32::MAX - 36;
+
+// 16-bit PCM stereo. The sizes in the header are patched in by `finish`,
+// a file that was never finished still plays but reports no length.
+pub struct WavWriter {
+    out: BufWriter<File>,
+    data_bytes: u32,
+}
+
+impl WavWriter {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<WavWriter> {
+        const CHANNELS: u16 = 2;
+        const BYTES_PER_SAMPLE: u16 = 2;
+
+        let mut out = BufWriter::new(File::create(path)?);
+
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+
+        out.write_all(b"fmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // Integer PCM.
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&CHANNELS.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * (CHANNELS * BYTES_PER_SAMPLE) as u32).to_le_bytes())?;
+        out.write_all(&(CHANNELS * BYTES_PER_SAMPLE).to_le_bytes())?;
+        out.write_all(&(BYTES_PER_SAMPLE * 8).to_le_bytes())?;
+
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter { out, data_bytes: 0 })
+    }
+
+    // Takes interleaved left/right samples from -1.0 to 1.0, as produced by
+    // the APU. Anything beyond is clipped. Fails without writing anything
+    // once the file would outgrow the 4 GiB RIFF limit, about six hours at
+    // 48 kHz, leaving what was written so far to `finish`.
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let data_bytes = (samples.len() as u64 * 2)
+            .checked_add(self.data_bytes as u64)
+            .filter(|&bytes| bytes <= MAX_DATA_BYTES as u64)
+            .ok_or_else(|| io::Error::other("WAV file is full"))?;
+
+        for &sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.out.write_all(&value.to_le_bytes())?;
+        }
+        self.data_bytes = data_bytes as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + self.data_bytes).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&self.data_bytes.to_le_bytes())?;
+        self.out.flush()
+    }
+}
+
+// Records the APU mix to one file and, optionally, every channel to a file
+// of its own next to it, named after the channel.
+pub struct Recorder {
+    mix: WavWriter,
+    channels: Vec<(Channel, WavWriter)>,
+}
+
+impl Recorder {
+    // Turns on channel capture in the APU when `per_channel` is set.
+    pub fn create<P: AsRef<Path>>(path: P, apu: &mut Apu, per_channel: bool) -> io::Result<Recorder> {
+        let path = path.as_ref();
+        let mix = WavWriter::create(path, apu.sample_rate())?;
+
+        let mut channels = Vec::new();
+        if per_channel {
+            for &channel in CHANNELS.iter() {
+                let writer = WavWriter::create(channel_path(path, channel), apu.sample_rate())?;
+                channels.push((channel, writer));
+            }
+            apu.set_channel_capture(true);
+        }
+
+        Ok(Recorder { mix, channels })
+    }
+
+    // Writes out what the APU produced since the last call.
+    pub fn record(&mut self, apu: &mut Apu) -> io::Result<()> {
+        self.mix.write_samples(&apu.take_samples())?;
+
+        for &mut (channel, ref mut writer) in self.channels.iter_mut() {
+            writer.write_samples(&apu.take_channel_samples(channel))?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.mix.finish()?;
+
+        for (_, writer) in self.channels {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// music.wav becomes music-square1.wav and so on.
+pub fn channel_path(path: &Path, channel: Channel) -> PathBuf {
+    let name = match channel {
+        Channel::Square1 => "square1",
+        Channel::Square2 => "square2",
+        Channel::Wave => "wave",
+        Channel::Noise => "noise",
+    };
+
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!("{}-{}.wav", stem, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("gb-{}-{}.wav", name, process::id()))
+    }
+
+    #[test]
+    fn header_layout() {
+        let path = temp_path("header");
+        let mut writer = WavWriter::create(&path, 48_000).unwrap();
+        writer.write_samples(&[0.0, 1.0, -1.0, 2.0]).unwrap();
+        writer.finish().unwrap();
+
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(b"RIFF");
+        expected.extend_from_slice(&44u32.to_le_bytes());
+        expected.extend_from_slice(b"WAVEfmt ");
+        expected.extend_from_slice(&16u32.to_le_bytes());
+        expected.extend_from_slice(&[0x01, 0x00, 0x02, 0x00]);
+        expected.extend_from_slice(&48_000u32.to_le_bytes());
+        expected.extend_from_slice(&192_000u32.to_le_bytes());
+        expected.extend_from_slice(&[0x04, 0x00, 0x10, 0x00]);
+        expected.extend_from_slice(b"data");
+        expected.extend_from_slice(&8u32.to_le_bytes());
+        expected.extend_from_slice(&[0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80, 0xFF, 0x7F]);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn riff_limit() {
+        let path = temp_path("limit");
+        let mut writer = WavWriter::create(&path, 48_000).unwrap();
+        writer.data_bytes = MAX_DATA_BYTES - 2;
+
+        assert!(writer.write_samples(&[0.0, 0.0]).is_err());
+        assert_eq!(writer.data_bytes, MAX_DATA_BYTES - 2);
+        writer.write_samples(&[0.0]).unwrap();
+        assert_eq!(writer.data_bytes, MAX_DATA_BYTES);
+        assert!(writer.write_samples(&[0.0]).is_err());
+
+        drop(writer);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn channel_file_names() {
+        let path = Path::new("out/music.wav");
+        assert_eq!(channel_path(path, Channel::Square1), Path::new("out/music-square1.wav"));
+        assert_eq!(channel_path(path, Channel::Noise), Path::new("out/music-noise.wav"));
+    }
+}
@@ -1,28 +1,94 @@
+const BANK_SIZE: usize = 0x1000;
+
+// 0xC000-0xCFFF is always bank 0. On CGB, SVBK selects which of banks 1-7
+// appears at 0xD000-0xDFFF, on DMG it is always bank 1.
 pub struct Wram {
     data: Vec<u8>,
+    bank: u8,
 }
 
 impl Wram {
     pub fn new() -> Wram {
         Wram {
-            data: vec![0; 0xDFFF - 0xC000 + 0x1],
+            data: vec![0; BANK_SIZE * 8],
+            bank: 1,
+        }
+    }
+
+    fn index(&self, offset: u16) -> usize {
+        let offset = offset as usize;
+
+        if offset < BANK_SIZE {
+            offset
+        } else {
+            self.bank as usize * BANK_SIZE + (offset - BANK_SIZE)
         }
     }
 
     pub fn load8(&self, offset: u16) -> u8 {
-        self.data[offset as usize]
+        self.data[self.index(offset)]
     }
 
-    pub fn load16(&self, offset: u16) -> u16 {
-        let offset = offset as usize;
+    pub fn store8(&mut self, offset: u16, value: u8) {
+        let index = self.index(offset);
+        self.data[index] = value;
+    }
+
+    pub fn rb(&self, a: u16) -> u8 {
+        match a {
+            0xFF70 => 0xF8 | self.bank,
+            _ => panic!("WRAM does not handle read {:4X}", a),
+        }
+    }
+
+    // Selecting bank 0 selects bank 1.
+    pub fn wb(&mut self, a: u16, v: u8) {
+        match a {
+            0xFF70 => self.bank = (v & 0x07).max(1),
+            _ => panic!("WRAM does not handle write {:4X}", a),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let b0 = self.data[offset + 0] as u16;
-        let b1 = self.data[offset + 1] as u16;
+    #[test]
+    fn svbk_switches_upper_half() {
+        let mut wram = Wram::new();
+        assert_eq!(wram.rb(0xFF70), 0xF9);
+        wram.store8(0x0000, 0x10);
+        wram.store8(0x1000, 0x11);
 
-        b0 | (b1 << 8)
+        for bank in 2..8 {
+            wram.wb(0xFF70, bank);
+            wram.store8(0x1000, 0x10 + bank);
+        }
+        wram.wb(0xFF70, 0x03);
+        assert_eq!(wram.rb(0xFF70), 0xFB);
+        assert_eq!(wram.load8(0x1000), 0x13);
+        assert_eq!(wram.load8(0x0000), 0x10);
+
+        // Only three bits are decoded.
+        wram.wb(0xFF70, 0xFE);
+        assert_eq!(wram.rb(0xFF70), 0xFE);
+        assert_eq!(wram.load8(0x1FFF), 0x00);
+        assert_eq!(wram.load8(0x1000), 0x16);
     }
 
-    pub fn store8(&mut self, offset: u16, value: u8) {
-        self.data[offset as usize] = value;
+    #[test]
+    fn svbk_zero_selects_one() {
+        let mut wram = Wram::new();
+        wram.store8(0x1000, 0x11);
+        wram.wb(0xFF70, 0x02);
+        assert_eq!(wram.load8(0x1000), 0x00);
+
+        wram.wb(0xFF70, 0x00);
+        assert_eq!(wram.rb(0xFF70), 0xF9);
+        assert_eq!(wram.load8(0x1000), 0x11);
+
+        wram.wb(0xFF70, 0x08);
+        assert_eq!(wram.load8(0x1000), 0x11);
     }
 }